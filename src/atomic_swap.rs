@@ -0,0 +1,520 @@
+use crate::script::{Op, Script};
+use crate::transaction::{MAX_NON_FINAL_SEQUENCE, SigHashType, TXInput, TXOutput};
+use crate::{
+    Blockchain, Transaction, UTXOSet, Wallet, convert_address, convert_script_address, hash_pub_key,
+};
+
+use std::fmt;
+
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+
+/// Length of the secrets swap contracts are locked with.
+pub const SECRET_LEN: usize = 32;
+/// How much later than the participant's contract the initiator's must
+/// time out, in seconds. The participant needs this long to redeem the
+/// initiator's coins once the secret is out.
+pub const MIN_LOCK_TIME_MARGIN: u32 = 6 * 60 * 60;
+
+/// A hash time-locked contract: coins the recipient can take by revealing
+/// the secret behind `secret_hash`, or that go back to the refund key once
+/// `lock_time` has passed. The contract is the redeem script of a pay to
+/// script hash output:
+///
+/// ```text
+/// IF
+///     SHA256 <secret_hash> EQUALVERIFY DUP HASH160 <recipient>
+/// ELSE
+///     <lock_time> CHECKLOCKTIMEVERIFY DROP DUP HASH160 <refund>
+/// ENDIF
+/// EQUALVERIFY CHECKSIG
+/// ```
+///
+/// In an atomic swap, the initiator locks coins on one chain to a secret
+/// only they know, and the participant locks coins on the other chain to
+/// the same hash with an earlier lock time. The initiator redeems the
+/// participant's contract, which publishes the secret, and the participant
+/// uses it to redeem the initiator's. If either stops, both get refunds.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Htlc {
+    secret_hash: Vec<u8>,
+    recipient: Vec<u8>,
+    lock_time: u32,
+    refund: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SwapError {
+    /// No unspent output of the transaction pays to the contract.
+    ContractNotFound,
+    /// The secret does not hash to the one the contract is locked with.
+    WrongSecret,
+    /// The wallet does not hold the key the spend needs.
+    WrongWallet,
+    /// The fee takes all of the contract value.
+    FeeTooHigh,
+    /// The contract does not lock the agreed amount.
+    WrongAmount { expected: i32, found: i32 },
+    /// The contract is not buried under enough blocks yet.
+    Unconfirmed {
+        confirmations: usize,
+        required: usize,
+    },
+    /// The contract times out too soon after the one to be created.
+    LockTimeTooClose { lock_time: u32, required: u32 },
+}
+
+impl fmt::Display for SwapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SwapError::ContractNotFound => write!(f, "no unspent output pays to the contract"),
+            SwapError::WrongSecret => write!(f, "secret does not match the contract"),
+            SwapError::WrongWallet => write!(f, "wallet cannot spend this way"),
+            SwapError::FeeTooHigh => write!(f, "fee exceeds the contract value"),
+            SwapError::WrongAmount { expected, found } => {
+                write!(f, "contract locks {} instead of {}", found, expected)
+            }
+            SwapError::Unconfirmed {
+                confirmations,
+                required,
+            } => write!(
+                f,
+                "contract has {} confirmations, {} needed",
+                confirmations, required
+            ),
+            SwapError::LockTimeTooClose {
+                lock_time,
+                required,
+            } => write!(
+                f,
+                "contract lock time {} is before {}, too close to ours",
+                lock_time, required
+            ),
+        }
+    }
+}
+
+impl Htlc {
+    pub fn new(secret_hash: &[u8], recipient: &[u8], lock_time: u32, refund: &[u8]) -> Htlc {
+        Htlc {
+            secret_hash: secret_hash.to_vec(),
+            recipient: recipient.to_vec(),
+            lock_time,
+            refund: refund.to_vec(),
+        }
+    }
+
+    /// Reads the terms of a contract script, if it is one.
+    pub fn from_script(script: &Script) -> Option<Htlc> {
+        match script.get_ops() {
+            [
+                Op::If,
+                Op::Sha256,
+                Op::Push(secret_hash),
+                Op::EqualVerify,
+                Op::Dup,
+                Op::Hash160,
+                Op::Push(recipient),
+                Op::Else,
+                lock_time @ Op::Push(_),
+                Op::CheckLockTimeVerify,
+                Op::Drop,
+                Op::Dup,
+                Op::Hash160,
+                Op::Push(refund),
+                Op::EndIf,
+                Op::EqualVerify,
+                Op::CheckSig,
+            ] => Some(Htlc {
+                secret_hash: secret_hash.clone(),
+                recipient: recipient.clone(),
+                lock_time: lock_time.as_number()?,
+                refund: refund.clone(),
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn to_script(&self) -> Script {
+        Script::new(vec![
+            Op::If,
+            Op::Sha256,
+            Op::Push(self.secret_hash.clone()),
+            Op::EqualVerify,
+            Op::Dup,
+            Op::Hash160,
+            Op::Push(self.recipient.clone()),
+            Op::Else,
+            Op::push_number(self.lock_time),
+            Op::CheckLockTimeVerify,
+            Op::Drop,
+            Op::Dup,
+            Op::Hash160,
+            Op::Push(self.refund.clone()),
+            Op::EndIf,
+            Op::EqualVerify,
+            Op::CheckSig,
+        ])
+    }
+
+    /// The pay to script hash address to fund the contract at.
+    pub fn get_address(&self) -> String {
+        convert_script_address(self.to_script().hash().as_slice())
+    }
+
+    pub fn get_secret_hash(&self) -> &[u8] {
+        self.secret_hash.as_slice()
+    }
+
+    pub fn get_recipient_address(&self) -> String {
+        convert_address(self.recipient.as_slice())
+    }
+
+    pub fn get_refund_address(&self) -> String {
+        convert_address(self.refund.as_slice())
+    }
+
+    pub fn get_lock_time(&self) -> u32 {
+        self.lock_time
+    }
+
+    /// Spends the contract outputs of `contract_txid` to the recipient,
+    /// revealing `secret`.
+    pub fn new_redeem_transaction(
+        &self,
+        contract_txid: &[u8],
+        secret: &[u8],
+        wallet: &Wallet,
+        fee: i32,
+        utxo_set: &UTXOSet,
+    ) -> Result<Transaction, SwapError> {
+        if crate::sha256_digest(secret) != self.secret_hash {
+            return Err(SwapError::WrongSecret);
+        }
+        if hash_pub_key(wallet.get_public_key()) != self.recipient {
+            return Err(SwapError::WrongWallet);
+        }
+        let unlock = vec![Op::Push(secret.to_vec()), Op::Push(vec![1])];
+        self.new_spend(contract_txid, unlock, 0, wallet, fee, utxo_set)
+    }
+
+    /// Spends the contract outputs of `contract_txid` back to the refund
+    /// key. The spend is locked until the contract lock time, so it can
+    /// only be mined once that has passed.
+    pub fn new_refund_transaction(
+        &self,
+        contract_txid: &[u8],
+        wallet: &Wallet,
+        fee: i32,
+        utxo_set: &UTXOSet,
+    ) -> Result<Transaction, SwapError> {
+        if hash_pub_key(wallet.get_public_key()) != self.refund {
+            return Err(SwapError::WrongWallet);
+        }
+        let unlock = vec![Op::Push(vec![])];
+        self.new_spend(contract_txid, unlock, self.lock_time, wallet, fee, utxo_set)
+    }
+
+    /// Looks up the outputs of `contract_txid` paying to the contract, to
+    /// check the contract before participating in the swap.
+    pub fn audit(
+        &self,
+        contract_txid: &[u8],
+        utxo_set: &UTXOSet,
+    ) -> Result<ContractAudit, SwapError> {
+        let lock = Script::new_p2sh(self.to_script().hash().as_slice());
+        let amount: i32 = utxo_set
+            .find_outputs_locked_by(&lock)
+            .iter()
+            .filter(|(txid, _, _)| txid.as_slice() == contract_txid)
+            .map(|(_, _, output)| output.get_value())
+            .sum();
+        let confirmed = utxo_set
+            .find_confirmation(contract_txid)
+            .filter(|_| amount > 0)
+            .ok_or(SwapError::ContractNotFound)?;
+        let best_height = utxo_set.get_blockchain().get_best_height();
+        Ok(ContractAudit {
+            htlc: self.clone(),
+            contract_txid: contract_txid.to_vec(),
+            amount,
+            confirmations: best_height + 1 - confirmed.height,
+        })
+    }
+
+    /// Finds the secret in a transaction that redeemed the contract.
+    pub fn extract_secret(&self, tx: &Transaction) -> Option<Vec<u8>> {
+        let redeem_script = self.to_script().serialize();
+        tx.get_vin()
+            .iter()
+            .find_map(|vin| match vin.get_script_sig().get_ops() {
+                [_, _, Op::Push(secret), _, Op::Push(script)]
+                    if *script == redeem_script
+                        && crate::sha256_digest(secret.as_slice()) == self.secret_hash =>
+                {
+                    Some(secret.clone())
+                }
+                _ => None,
+            })
+    }
+
+    /// Searches the chain for the redemption of the contract and returns
+    /// the secret it revealed.
+    pub fn find_secret(&self, blockchain: &Blockchain) -> Option<Vec<u8>> {
+        let mut iterator = blockchain.iterator();
+        while let Some(block) = iterator.next() {
+            let secret = block
+                .get_transactions()
+                .iter()
+                .find_map(|tx| self.extract_secret(tx));
+            if secret.is_some() {
+                return secret;
+            }
+        }
+        None
+    }
+
+    fn new_spend(
+        &self,
+        contract_txid: &[u8],
+        unlock: Vec<Op>,
+        lock_time: u32,
+        wallet: &Wallet,
+        fee: i32,
+        utxo_set: &UTXOSet,
+    ) -> Result<Transaction, SwapError> {
+        let redeem_script = self.to_script();
+        let lock = Script::new_p2sh(redeem_script.hash().as_slice());
+        let contract_outputs: Vec<(usize, TXOutput)> = utxo_set
+            .find_outputs_locked_by(&lock)
+            .into_iter()
+            .filter(|(txid, _, _)| txid.as_slice() == contract_txid)
+            .map(|(_, vout, output)| (vout, output))
+            .collect();
+        if contract_outputs.is_empty() {
+            return Err(SwapError::ContractNotFound);
+        }
+        let value: i32 = contract_outputs
+            .iter()
+            .map(|(_, output)| output.get_value())
+            .sum();
+        if value <= fee {
+            return Err(SwapError::FeeTooHigh);
+        }
+
+        let inputs = contract_outputs
+            .iter()
+            .map(|(vout, _)| {
+                let mut input = TXInput::new(contract_txid, *vout);
+                if lock_time > 0 {
+                    input.set_sequence(MAX_NON_FINAL_SEQUENCE);
+                }
+                input
+            })
+            .collect();
+        let outputs = vec![TXOutput::new(value - fee, wallet.get_address().as_str())];
+        let mut tx = Transaction::new(inputs, outputs);
+        if lock_time > 0 {
+            tx.set_lock_time(lock_time);
+        }
+
+        for (idx, (_, output)) in contract_outputs.iter().enumerate() {
+            let signature =
+                tx.create_signature(idx, output.get_script_pubkey(), wallet, SigHashType::ALL);
+            let mut ops = vec![
+                Op::Push(signature),
+                Op::Push(wallet.get_public_key().to_vec()),
+            ];
+            ops.extend(unlock.iter().cloned());
+            ops.push(Op::Push(redeem_script.serialize()));
+            tx.set_script_sig(idx, Script::new(ops));
+        }
+        Ok(tx)
+    }
+}
+
+/// What `auditcontract` found of the initiator's contract on its chain,
+/// for `participate` to check on the other chain.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ContractAudit {
+    htlc: Htlc,
+    contract_txid: Vec<u8>,
+    amount: i32,
+    confirmations: usize,
+}
+
+impl ContractAudit {
+    pub fn serialize(&self) -> Vec<u8> {
+        bincode::serialize(self).unwrap()
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Option<ContractAudit> {
+        bincode::deserialize(bytes).ok()
+    }
+
+    pub fn get_htlc(&self) -> &Htlc {
+        &self.htlc
+    }
+
+    pub fn get_contract_txid(&self) -> &[u8] {
+        self.contract_txid.as_slice()
+    }
+
+    pub fn get_amount(&self) -> i32 {
+        self.amount
+    }
+
+    pub fn get_confirmations(&self) -> usize {
+        self.confirmations
+    }
+
+    /// Checks that the contract locks `amount` under at least
+    /// `min_confirmations` blocks, and times out at least
+    /// `MIN_LOCK_TIME_MARGIN` after a participant contract locked until
+    /// `lock_time`.
+    pub fn check_participation(
+        &self,
+        amount: i32,
+        min_confirmations: usize,
+        lock_time: u32,
+    ) -> Result<(), SwapError> {
+        if self.amount != amount {
+            return Err(SwapError::WrongAmount {
+                expected: amount,
+                found: self.amount,
+            });
+        }
+        if self.confirmations < min_confirmations {
+            return Err(SwapError::Unconfirmed {
+                confirmations: self.confirmations,
+                required: min_confirmations,
+            });
+        }
+        let required = lock_time.saturating_add(MIN_LOCK_TIME_MARGIN);
+        if self.htlc.lock_time < required {
+            return Err(SwapError::LockTimeTooClose {
+                lock_time: self.htlc.lock_time,
+                required,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Draws a new random swap secret.
+pub fn new_secret() -> Vec<u8> {
+    let mut secret = vec![0; SECRET_LEN];
+    SystemRandom::new().fill(&mut secret).unwrap();
+    secret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ContractAudit, Htlc, MIN_LOCK_TIME_MARGIN, SwapError, new_secret};
+    use crate::script::{Op, Script};
+    use crate::transaction::{BlockTime, SigHashType, TXInput, TXOutput};
+    use crate::{Transaction, Wallet, hash_pub_key};
+
+    #[test]
+    fn test_htlc_redeem_and_refund() {
+        let (recipient, refunder) = (Wallet::new(), Wallet::new());
+        let secret = new_secret();
+        let htlc = Htlc::new(
+            crate::sha256_digest(secret.as_slice()).as_slice(),
+            hash_pub_key(recipient.get_public_key()).as_slice(),
+            1_700_000_000,
+            hash_pub_key(refunder.get_public_key()).as_slice(),
+        );
+        assert_eq!(Htlc::from_script(&htlc.to_script()), Some(htlc.clone()));
+        assert_eq!(
+            Script::for_address(htlc.get_address().as_str()),
+            Script::new_p2sh(htlc.to_script().hash().as_slice())
+        );
+        let contract = TXOutput::new(10, htlc.get_address().as_str());
+
+        // Builds the spends new_redeem_transaction and
+        // new_refund_transaction would, without a UTXO set.
+        let spend = |wallet: &Wallet, unlock: Vec<Op>, lock_time: u32| {
+            let mut input = TXInput::new(b"contract", 0);
+            input.set_sequence(crate::transaction::MAX_NON_FINAL_SEQUENCE);
+            let mut tx = Transaction::new(
+                vec![input],
+                vec![TXOutput::new(9, wallet.get_address().as_str())],
+            );
+            tx.set_lock_time(lock_time);
+            let signature =
+                tx.create_signature(0, contract.get_script_pubkey(), wallet, SigHashType::ALL);
+            let mut ops = vec![
+                Op::Push(signature),
+                Op::Push(wallet.get_public_key().to_vec()),
+            ];
+            ops.extend(unlock);
+            ops.push(Op::Push(htlc.to_script().serialize()));
+            tx.set_script_sig(0, Script::new(ops));
+            tx
+        };
+        let redeem = |secret: &[u8]| vec![Op::Push(secret.to_vec()), Op::Push(vec![1])];
+        let refund = || vec![Op::Push(vec![])];
+        let outputs = [contract.clone()];
+
+        let redeemed = spend(&recipient, redeem(secret.as_slice()), 0);
+        assert!(redeemed.verify_with_outputs(&outputs));
+        assert_eq!(htlc.extract_secret(&redeemed), Some(secret.clone()));
+        assert!(!spend(&recipient, redeem(b"guess"), 0).verify_with_outputs(&outputs));
+        assert!(!spend(&refunder, redeem(secret.as_slice()), 0).verify_with_outputs(&outputs));
+
+        let refunded = spend(&refunder, refund(), htlc.get_lock_time());
+        assert!(refunded.verify_with_outputs(&outputs));
+        assert_eq!(htlc.extract_secret(&refunded), None);
+        // Locked until the contract times out, whoever signs.
+        assert!(
+            !spend(&refunder, refund(), htlc.get_lock_time() - 1).verify_with_outputs(&outputs)
+        );
+        assert!(!spend(&recipient, refund(), htlc.get_lock_time()).verify_with_outputs(&outputs));
+        let before = BlockTime {
+            height: 1,
+            time: htlc.get_lock_time() as i64,
+        };
+        let after = BlockTime {
+            height: 1,
+            time: htlc.get_lock_time() as i64 + 1,
+        };
+        assert!(!refunded.is_final(before, &[before]));
+        assert!(refunded.is_final(after, &[before]));
+    }
+
+    #[test]
+    fn test_check_participation() {
+        let htlc = Htlc::new(&[1; 32], &[2; 20], 1_700_000_000, &[3; 20]);
+        let audit = ContractAudit {
+            htlc,
+            contract_txid: vec![4; 32],
+            amount: 6,
+            confirmations: 1,
+        };
+        let audit = ContractAudit::deserialize(audit.serialize().as_slice()).unwrap();
+        let lock_time = 1_700_000_000 - MIN_LOCK_TIME_MARGIN;
+        assert_eq!(audit.check_participation(6, 1, lock_time), Ok(()));
+        assert_eq!(
+            audit.check_participation(7, 1, lock_time),
+            Err(SwapError::WrongAmount {
+                expected: 7,
+                found: 6
+            })
+        );
+        assert_eq!(
+            audit.check_participation(6, 2, lock_time),
+            Err(SwapError::Unconfirmed {
+                confirmations: 1,
+                required: 2
+            })
+        );
+        assert_eq!(
+            audit.check_participation(6, 1, lock_time + 1),
+            Err(SwapError::LockTimeTooClose {
+                lock_time: 1_700_000_000,
+                required: 1_700_000_001
+            })
+        );
+    }
+}
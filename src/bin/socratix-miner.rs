@@ -0,0 +1,68 @@
+use log::{LevelFilter, error, info};
+use socratix::{
+    CENTRAL_NODE, ProofOfWork, Transaction, get_block_template, submit_block, validate_address,
+};
+use std::thread;
+use std::time::Duration;
+use structopt::StructOpt;
+
+/// Pause before asking again when the node could not be reached.
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(StructOpt, Debug)]
+#[structopt(
+    name = "socratix-miner",
+    about = "Mine block templates served by a socratix node"
+)]
+struct Opt {
+    #[structopt(name = "address", help = "The address to send block rewards to")]
+    address: String,
+    #[structopt(long = "node", default_value = CENTRAL_NODE, help = "Node to fetch templates from")]
+    node: String,
+    #[structopt(long = "blocks", help = "Stop after this many accepted blocks")]
+    blocks: Option<usize>,
+}
+
+fn main() {
+    env_logger::Builder::new()
+        .filter(None, LevelFilter::Info)
+        .init();
+    let opt = Opt::from_args();
+    if !validate_address(opt.address.as_str()) {
+        panic!("ERROR: Address is not valid")
+    }
+
+    let mut accepted = 0;
+    while opt.blocks.is_none_or(|blocks| accepted < blocks) {
+        let Some(template) = get_block_template(opt.node.as_str()) else {
+            error!("No block template from {}", opt.node);
+            thread::sleep(RETRY_DELAY);
+            continue;
+        };
+        info!(
+            "Mining template {} at height {} with {} transactions for {} coins",
+            template.get_template_id(),
+            template.get_height(),
+            template.get_transactions().len(),
+            template.get_coinbase_value()
+        );
+
+        let coinbase_tx = Transaction::new_coinbase_tx_with_value(
+            opt.address.as_str(),
+            template.get_coinbase_value() as i32,
+        );
+        let block = ProofOfWork::new_proof_of_work(template.new_block(coinbase_tx)).run();
+
+        match submit_block(opt.node.as_str(), template.get_template_id(), &block) {
+            Ok(()) => {
+                accepted += 1;
+                println!(
+                    "Accepted block {} at height {}",
+                    block.get_hash(),
+                    block.get_height()
+                );
+            }
+            Err(reason) => println!("Rejected block {}: {}", block.get_hash(), reason),
+        }
+    }
+}
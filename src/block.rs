@@ -1,8 +1,16 @@
-use crate::{ProofOfWork, Transaction};
+use crate::merkle::{MerkleProof, merkle_root};
+use crate::transaction::WITNESS_SCALE_FACTOR;
+use crate::{MiningStats, Transaction, consensus};
+
+use std::collections::HashSet;
+use std::sync::atomic::AtomicBool;
 
 use serde::{Deserialize, Serialize};
 use sled::IVec;
 
+/// Largest serialized block, transactions included.
+pub const MAX_BLOCK_SIZE: usize = 1_000_000;
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Block {
     timestamp: i64,
@@ -11,6 +19,8 @@ pub struct Block {
     transactions: Vec<Transaction>,
     nonce: i64,
     height: usize,
+    /// Authority signature over the header hash, empty under proof of work.
+    signature: Vec<u8>,
 }
 
 impl Block {
@@ -19,20 +29,52 @@ impl Block {
         transactions: &[Transaction],
         height: usize,
     ) -> Block {
-        let mut block = Block {
+        let block = Block::new_template(prev_block_hash, transactions, height);
+
+        consensus::current()
+            .seal(block, &AtomicBool::new(false), &MiningStats::new())
+            .expect("This node cannot seal a block at this height")
+    }
+
+    pub fn new_template(
+        prev_block_hash: String,
+        transactions: &[Transaction],
+        height: usize,
+    ) -> Block {
+        Block {
             timestamp: crate::current_timestamp(),
             prev_block_hash,
             hash: String::new(),
             transactions: transactions.to_vec(),
             nonce: 0,
             height,
-        };
+            signature: vec![],
+        }
+    }
 
-        let pow = ProofOfWork::new_proof_of_work(block.clone());
-        let (nonce, hash) = pow.run();
-        block.hash = hash;
-        block.nonce = nonce;
-        block
+    pub fn seal(&mut self, nonce: i64, hash: String) {
+        self.nonce = nonce;
+        self.hash = hash;
+    }
+
+    /// Seals a block signed by an authority instead of mined.
+    pub fn seal_with_signature(&mut self, hash: String, signature: Vec<u8>) {
+        self.hash = hash;
+        self.signature = signature;
+    }
+
+    /// Sets the header time, as chosen by an external miner.
+    pub fn set_timestamp(&mut self, timestamp: i64) {
+        self.timestamp = timestamp;
+    }
+
+    /// Gives the header fresh search space once every nonce has been tried:
+    /// the timestamp moves forward and the coinbase extra nonce changes.
+    pub fn roll_header(&mut self, extra_nonce: u64) {
+        self.timestamp = crate::current_timestamp().max(self.timestamp + 1);
+        if let Some(coinbase_tx) = self.transactions.iter_mut().find(|tx| tx.is_coinbase()) {
+            coinbase_tx.set_extra_nonce(extra_nonce);
+        }
     }
 
     pub fn deserialize(bytes: &[u8]) -> Block {
@@ -43,18 +85,55 @@ impl Block {
         bincode::serialize(self).unwrap().to_vec()
     }
 
+    /// Serialized size in bytes.
+    pub fn get_size(&self) -> usize {
+        self.serialize().len()
+    }
+
+    /// Weight of the block: the header at full weight plus the weight of
+    /// each transaction.
+    pub fn get_weight(&self) -> usize {
+        let tx_sizes: usize = self.transactions.iter().map(|tx| tx.get_size()).sum();
+        let tx_weights: usize = self.transactions.iter().map(|tx| tx.get_weight()).sum();
+        (self.get_size() - tx_sizes) * WITNESS_SCALE_FACTOR + tx_weights
+    }
+
     pub fn generate_genesis_block(transaction: &Transaction) -> Block {
         let transactions = vec![transaction.clone()];
 
         Block::new_block(String::from("none"), &transactions, 0)
     }
 
+    /// Merkle root of the witness-inclusive transaction hashes, which the
+    /// header commits to. Txids leave out the unlocking scripts, so a root
+    /// of those would let the signatures of a block be swapped.
     pub fn hash_transactions(&self) -> Vec<u8> {
-        let mut txhashes = vec![];
-        for tx in &self.transactions {
-            txhashes.extend(tx.get_id());
-        }
-        crate::sha256_digest(txhashes.as_slice())
+        merkle_root(self.get_wtxids().as_slice())
+    }
+
+    /// Proves that transaction `txid` is in the block to anyone holding the
+    /// header. The proof is checked against its wtxid.
+    pub fn merkle_proof(&self, txid: &[u8]) -> Option<MerkleProof> {
+        let index = self
+            .transactions
+            .iter()
+            .position(|tx| tx.get_id() == txid)?;
+        Some(MerkleProof::new(self.get_wtxids().as_slice(), index))
+    }
+
+    /// Id of a transaction the block lists more than once. Repeating the
+    /// last transactions of a block can leave its Merkle root unchanged, so
+    /// such a block must be refused rather than its hash.
+    pub fn find_duplicate_transaction(&self) -> Option<&[u8]> {
+        let mut seen = HashSet::new();
+        self.transactions
+            .iter()
+            .map(|tx| tx.get_id())
+            .find(|txid| !seen.insert(*txid))
+    }
+
+    fn get_wtxids(&self) -> Vec<Vec<u8>> {
+        self.transactions.iter().map(|tx| tx.get_wtxid()).collect()
     }
 
     pub fn get_transactions(&self) -> &[Transaction] {
@@ -80,6 +159,14 @@ impl Block {
     pub fn get_height(&self) -> usize {
         self.height
     }
+
+    pub fn get_signature(&self) -> &[u8] {
+        self.signature.as_slice()
+    }
+
+    pub fn get_nonce(&self) -> i64 {
+        self.nonce
+    }
 }
 
 impl From<Block> for IVec {
@@ -92,7 +179,8 @@ impl From<Block> for IVec {
 #[cfg(test)]
 mod tests {
     use super::Block;
-    use crate::Transaction;
+    use crate::transaction::{TXInput, TXOutput, WITNESS_SCALE_FACTOR};
+    use crate::{Transaction, Wallet};
 
     #[test]
     fn test_new_block() {
@@ -116,4 +204,53 @@ mod tests {
         let desc_block = Block::deserialize(&block_bytes[..]);
         assert_eq!(block.hash, desc_block.hash)
     }
+
+    #[test]
+    fn test_size_and_weight() {
+        let wallet = Wallet::new();
+        let coinbase = Transaction::new_coinbase_tx(wallet.get_address().as_str());
+        let block = Block::new_template(String::from("none"), &[coinbase.clone()], 0);
+        // A coinbase script is not discounted.
+        assert_eq!(
+            coinbase.get_weight(),
+            coinbase.get_size() * WITNESS_SCALE_FACTOR
+        );
+        assert_eq!(block.get_weight(), block.get_size() * WITNESS_SCALE_FACTOR);
+
+        let mut tx = Transaction::new(
+            vec![TXInput::new(coinbase.get_id(), 0)],
+            vec![TXOutput::new(9, wallet.get_address().as_str())],
+        );
+        let unsigned_size = tx.get_size();
+        tx.sign_with_outputs(&wallet, coinbase.get_vout());
+        let script_sig_size = tx.get_size() - unsigned_size;
+        assert_eq!(tx.get_base_size(), unsigned_size);
+        assert_eq!(
+            tx.get_weight(),
+            unsigned_size * WITNESS_SCALE_FACTOR + script_sig_size
+        );
+
+        let with_tx = Block::new_template(String::from("none"), &[coinbase, tx.clone()], 0);
+        assert_eq!(with_tx.get_size(), block.get_size() + tx.get_size());
+        assert_eq!(with_tx.get_weight(), block.get_weight() + tx.get_weight());
+    }
+
+    #[test]
+    fn test_find_duplicate_transaction() {
+        let transactions: Vec<Transaction> = (0..3)
+            .map(|_| Transaction::new_coinbase_tx("1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa"))
+            .collect();
+        let block = Block::new_template(String::from("none"), &transactions, 0);
+        assert_eq!(block.find_duplicate_transaction(), None);
+
+        // Repeating the odd transaction out keeps the root.
+        let mut mutated = transactions.clone();
+        mutated.push(transactions[2].clone());
+        let mutated = Block::new_template(String::from("none"), &mutated, 0);
+        assert_eq!(mutated.hash_transactions(), block.hash_transactions());
+        assert_eq!(
+            mutated.find_duplicate_transaction(),
+            Some(transactions[2].get_id())
+        );
+    }
 }
@@ -0,0 +1,153 @@
+use crate::block::MAX_BLOCK_SIZE;
+use crate::transaction::SUBSIDY;
+use crate::{Block, Blockchain, MemoryPool, ProofOfWork, Transaction, sha256_digest};
+
+use data_encoding::HEXLOWER;
+use serde::{Deserialize, Serialize};
+
+/// Largest total size of pool transactions placed in a block template,
+/// leaving room under `MAX_BLOCK_SIZE` for the header and the coinbase.
+pub const BLOCK_TEMPLATE_MAX_SIZE: usize = MAX_BLOCK_SIZE - 10_000;
+
+/// Everything an external miner needs to assemble and grind a block: the
+/// parent, the height, the target and the pool transactions to include after
+/// a coinbase paying `coinbase_value`. The block timestamp must be at least
+/// `min_timestamp`.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct BlockTemplate {
+    template_id: String,
+    prev_block_hash: String,
+    height: usize,
+    target: String,
+    transactions: Vec<Transaction>,
+    coinbase_value: i64,
+    min_timestamp: i64,
+}
+
+impl BlockTemplate {
+    /// Builds a template on the current tip from the best fee-rate packages
+    /// in the memory pool. Transactions whose fees would take the coinbase
+    /// value past what an output can hold are left out.
+    pub fn new(blockchain: &Blockchain, mempool: &MemoryPool) -> BlockTemplate {
+        let prev_block_hash = blockchain.get_tip_hash();
+        let height = blockchain.get_best_height() + 1;
+        let mut transactions = mempool.select_for_block(BLOCK_TEMPLATE_MAX_SIZE);
+        let mut coinbase_value = SUBSIDY as i64;
+        // Every transaction comes after those it spends, so any prefix of
+        // the selection is still a valid block.
+        let fitting = transactions
+            .iter()
+            .position(|tx| {
+                let fee = mempool
+                    .get_fee(HEXLOWER.encode(tx.get_id()).as_str())
+                    .unwrap_or(0);
+                match coinbase_value
+                    .checked_add(fee)
+                    .filter(|value| *value <= i64::from(i32::MAX))
+                {
+                    Some(value) => {
+                        coinbase_value = value;
+                        false
+                    }
+                    None => true,
+                }
+            })
+            .unwrap_or(transactions.len());
+        transactions.truncate(fitting);
+        let mut template =
+            BlockTemplate::with_transactions(prev_block_hash, height, transactions, coinbase_value);
+        template.min_timestamp = blockchain.get_median_time_past() + 1;
+        template
+    }
+
+    pub fn with_transactions(
+        prev_block_hash: String,
+        height: usize,
+        transactions: Vec<Transaction>,
+        coinbase_value: i64,
+    ) -> BlockTemplate {
+        let mut data = vec![];
+        data.extend(prev_block_hash.as_bytes());
+        data.extend(height.to_be_bytes());
+        for tx in &transactions {
+            data.extend(tx.get_id());
+        }
+        data.extend(coinbase_value.to_be_bytes());
+
+        BlockTemplate {
+            template_id: HEXLOWER.encode(sha256_digest(data.as_slice()).as_slice()),
+            prev_block_hash,
+            height,
+            target: HEXLOWER.encode(&ProofOfWork::target()),
+            transactions,
+            coinbase_value,
+            min_timestamp: 0,
+        }
+    }
+
+    /// Puts `coinbase_tx` in front of the template transactions. The result
+    /// still needs its proof of work.
+    pub fn new_block(&self, coinbase_tx: Transaction) -> Block {
+        let mut txs = vec![coinbase_tx];
+        txs.extend(self.transactions.iter().cloned());
+        let mut block = Block::new_template(self.prev_block_hash.clone(), &txs, self.height);
+        block.set_timestamp(block.get_timestamp().max(self.min_timestamp));
+        block
+    }
+
+    pub fn get_template_id(&self) -> &str {
+        self.template_id.as_str()
+    }
+
+    pub fn get_prev_block_hash(&self) -> &str {
+        self.prev_block_hash.as_str()
+    }
+
+    pub fn get_height(&self) -> usize {
+        self.height
+    }
+
+    pub fn get_target(&self) -> &str {
+        self.target.as_str()
+    }
+
+    pub fn get_transactions(&self) -> &[Transaction] {
+        self.transactions.as_slice()
+    }
+
+    pub fn get_coinbase_value(&self) -> i64 {
+        self.coinbase_value
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        bincode::serialize(self).unwrap()
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> BlockTemplate {
+        bincode::deserialize(bytes).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BlockTemplate;
+    use crate::{Transaction, Wallet};
+
+    #[test]
+    fn test_template_id_tracks_contents() {
+        let address = Wallet::new().get_address();
+        let tx = Transaction::new_coinbase_tx(address.as_str());
+        let a = BlockTemplate::with_transactions("00".to_string(), 1, vec![], 10);
+        let b = BlockTemplate::with_transactions("00".to_string(), 1, vec![], 10);
+        let c = BlockTemplate::with_transactions("00".to_string(), 1, vec![tx.clone()], 10);
+        let d = BlockTemplate::with_transactions("00".to_string(), 1, vec![], 11);
+        assert_eq!(a.get_template_id(), b.get_template_id());
+        assert_ne!(a.get_template_id(), c.get_template_id());
+        assert_ne!(a.get_template_id(), d.get_template_id());
+
+        let block = c.new_block(Transaction::new_coinbase_tx(address.as_str()));
+        assert_eq!(block.get_height(), 1);
+        assert_eq!(block.get_transactions().len(), 2);
+        assert_eq!(block.get_transactions()[1].get_id(), tx.get_id());
+    }
+}
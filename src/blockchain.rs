@@ -1,9 +1,12 @@
-use crate::transaction::TXOutput;
-use crate::{Block, Transaction};
+use crate::block::MAX_BLOCK_SIZE;
+use crate::transaction::{BlockTime, MAX_TX_SIZE, SUBSIDY, TXOutput};
+use crate::{Block, MiningStats, Transaction, UTXOSet, consensus, current_timestamp};
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::env::current_dir;
-use std::sync::{Arc, RwLock};
+use std::fmt;
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex, RwLock};
 
 use data_encoding::HEXLOWER;
 use sled::transaction::TransactionResult;
@@ -11,10 +14,97 @@ use sled::{Db, Tree};
 
 const TIP_BLOCK_HASH_KEY: &str = "tip_block_hash";
 const BLOCKS_TREE: &str = "blocks";
+/// Number of recent blocks whose median timestamp the next block must pass.
+const MEDIAN_TIME_SPAN: usize = 11;
+/// How far a block timestamp may run ahead of the local clock, in
+/// milliseconds.
+pub const MAX_FUTURE_BLOCK_TIME: i64 = 2 * 60 * 60 * 1000;
+
+/// Why a block was refused by [`Blockchain::validate_block`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum BlockError {
+    AlreadyKnown,
+    UnknownParent,
+    NotOnTip,
+    BadHeight,
+    BadSeal,
+    /// The timestamp is not after the median time past of the chain.
+    TimeTooOld,
+    /// The timestamp is more than `MAX_FUTURE_BLOCK_TIME` ahead of the
+    /// local clock.
+    TimeTooNew,
+    BadCoinbase,
+    /// The serialized block is larger than `MAX_BLOCK_SIZE`.
+    TooLarge(usize),
+    MissingInputs,
+    DoubleSpend,
+    InvalidTransaction(String),
+    /// A transaction listed twice, which could leave the Merkle root as it
+    /// is (CVE-2012-2459).
+    DuplicateTransaction(String),
+    /// A transaction larger than `MAX_TX_SIZE`.
+    TransactionTooLarge(String),
+    /// A transaction whose lock time or relative locks have not passed.
+    NonFinalTransaction(String),
+    /// A transaction with a negative output or outputs that overflow.
+    InvalidOutputValue(String),
+    BadCoinbaseValue {
+        value: i64,
+        allowed: i64,
+    },
+}
+
+impl fmt::Display for BlockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlockError::AlreadyKnown => write!(f, "block already known"),
+            BlockError::UnknownParent => write!(f, "previous block is unknown"),
+            BlockError::NotOnTip => write!(f, "block does not extend the current tip"),
+            BlockError::BadHeight => write!(f, "block height does not follow its parent"),
+            BlockError::BadSeal => write!(f, "block seal is not valid"),
+            BlockError::TimeTooOld => {
+                write!(
+                    f,
+                    "block timestamp is not after the median of the last blocks"
+                )
+            }
+            BlockError::TimeTooNew => write!(f, "block timestamp is too far in the future"),
+            BlockError::BadCoinbase => write!(f, "block must start with its only coinbase"),
+            BlockError::TooLarge(size) => write!(f, "block too large: {} bytes", size),
+            BlockError::MissingInputs => write!(f, "transaction inputs are missing"),
+            BlockError::DoubleSpend => write!(f, "an output is spent twice"),
+            BlockError::InvalidTransaction(txid_hex) => {
+                write!(f, "invalid transaction {}", txid_hex)
+            }
+            BlockError::DuplicateTransaction(txid_hex) => {
+                write!(f, "transaction {} is listed twice", txid_hex)
+            }
+            BlockError::TransactionTooLarge(txid_hex) => {
+                write!(f, "transaction {} is too large", txid_hex)
+            }
+            BlockError::NonFinalTransaction(txid_hex) => {
+                write!(f, "transaction {} is still locked", txid_hex)
+            }
+            BlockError::InvalidOutputValue(txid_hex) => {
+                write!(f, "transaction {} has an invalid output value", txid_hex)
+            }
+            BlockError::BadCoinbaseValue { value, allowed } => {
+                write!(
+                    f,
+                    "coinbase pays {} but at most {} is allowed",
+                    value, allowed
+                )
+            }
+        }
+    }
+}
 
 #[derive(Clone)]
 pub struct Blockchain {
     tip_hash: Arc<RwLock<String>>,
+    /// Held while a block is validated and connected, so that two blocks
+    /// cannot both pass the tip check and be applied to the UTXO set.
+    connect_lock: Arc<Mutex<()>>,
     db: Db,
 }
 
@@ -36,6 +126,7 @@ impl Blockchain {
         }
         Blockchain {
             tip_hash: Arc::new(RwLock::new(tip_hash)),
+            connect_lock: Arc::new(Mutex::new(())),
             db,
         }
     }
@@ -60,6 +151,7 @@ impl Blockchain {
 
         Blockchain {
             tip_hash: Arc::new(RwLock::new(tip_hash)),
+            connect_lock: Arc::new(Mutex::new(())),
             db,
         }
     }
@@ -77,29 +169,140 @@ impl Blockchain {
         *tip_hash = String::from(new_tip_hash)
     }
 
-    pub fn mine_block(&self, transactions: &[Transaction]) -> Block {
-        for transaction in transactions {
-            if !transaction.verify(self) {
-                panic!("Error: Invalid transaction")
+    /// Seals `transactions`, coinbase first, on the tip and connects the
+    /// block through the same checks as one from the network.
+    pub fn mine_block(&self, transactions: &[Transaction]) -> Result<Block, BlockError> {
+        let best_height = self.get_best_height();
+
+        let mut block = Block::new_template(self.get_tip_hash(), transactions, best_height + 1);
+        block.set_timestamp(block.get_timestamp().max(self.get_median_time_past() + 1));
+        let block = consensus::current()
+            .seal(block, &AtomicBool::new(false), &MiningStats::new())
+            .expect("This node cannot seal a block at this height");
+        self.connect_block(&block)?;
+        Ok(block)
+    }
+
+    /// Fully checks a block that extends the current tip: its seal, size,
+    /// coinbase, signatures, inputs against the UTXO set, lock times and the
+    /// coinbase value against the subsidy plus fees.
+    pub fn validate_block(&self, block: &Block, utxo_set: &UTXOSet) -> Result<(), BlockError> {
+        if self.get_block(block.get_hash().as_bytes()).is_some() {
+            return Err(BlockError::AlreadyKnown);
+        }
+        let parent = self
+            .get_block(block.get_prev_block_hash().as_bytes())
+            .ok_or(BlockError::UnknownParent)?;
+        if block.get_height() != parent.get_height() + 1 {
+            return Err(BlockError::BadHeight);
+        }
+        if block.get_prev_block_hash().ne(&self.get_tip_hash()) {
+            return Err(BlockError::NotOnTip);
+        }
+        let median_time_past = self.get_median_time_past();
+        if block.get_timestamp() <= median_time_past {
+            return Err(BlockError::TimeTooOld);
+        }
+        if block.get_timestamp() > current_timestamp() + MAX_FUTURE_BLOCK_TIME {
+            return Err(BlockError::TimeTooNew);
+        }
+        if !consensus::current().validate_seal(block) {
+            return Err(BlockError::BadSeal);
+        }
+
+        let size = block.get_size();
+        if size > MAX_BLOCK_SIZE {
+            return Err(BlockError::TooLarge(size));
+        }
+        Self::check_transactions(block)?;
+        let transactions = block.get_transactions();
+
+        let mut block_outputs: HashMap<Vec<u8>, &[TXOutput]> = HashMap::new();
+        let mut spent = HashSet::new();
+        let mut fees: i64 = 0;
+        // The block extends the tip, so this is `get_block_time(block)`.
+        let at = BlockTime {
+            height: block.get_height(),
+            time: median_time_past / 1000,
+        };
+        for tx in &transactions[1..] {
+            let txid_hex = HEXLOWER.encode(tx.get_id());
+            let mut prev_outputs = vec![];
+            let mut confirmed = vec![];
+            for vin in tx.get_vin() {
+                if !spent.insert((vin.get_txid().to_vec(), vin.get_vout())) {
+                    return Err(BlockError::DoubleSpend);
+                }
+                let prev_output = match block_outputs.get(vin.get_txid()) {
+                    Some(outs) => outs.get(vin.get_vout()).cloned().map(|out| (out, at)),
+                    None => utxo_set
+                        .find_output(vin.get_txid(), vin.get_vout())
+                        .zip(utxo_set.find_confirmation(vin.get_txid())),
+                };
+                let (prev_output, confirmation) = prev_output.ok_or(BlockError::MissingInputs)?;
+                prev_outputs.push(prev_output);
+                confirmed.push(confirmation);
+            }
+            if !tx.is_final(at, confirmed.as_slice()) {
+                return Err(BlockError::NonFinalTransaction(txid_hex));
+            }
+            if !tx.verify_with_outputs(prev_outputs.as_slice()) {
+                return Err(BlockError::InvalidTransaction(txid_hex));
             }
+            let fee = tx.get_fee(prev_outputs.as_slice());
+            if fee < 0 {
+                return Err(BlockError::InvalidTransaction(txid_hex));
+            }
+            fees = fees
+                .checked_add(fee)
+                .ok_or(BlockError::InvalidTransaction(txid_hex))?;
+            block_outputs.insert(tx.get_id_bytes(), tx.get_vout());
         }
-        let best_height = self.get_best_height();
 
-        let block = Block::new_block(self.get_tip_hash(), transactions, best_height + 1);
-        let block_hahs = block.get_hash();
+        let value = transactions[0].get_output_value();
+        let allowed = SUBSIDY as i64 + fees;
+        if value > allowed {
+            return Err(BlockError::BadCoinbaseValue { value, allowed });
+        }
+        Ok(())
+    }
 
-        let blocks_tree = self.db.open_tree(BLOCKS_TREE).unwrap();
-        Self::update_blocks_tree(&blocks_tree, &block);
-        self.set_tip_hash(block_hahs);
-        block
+    /// Checks of the block body that do not depend on the chain: valid and
+    /// distinct transactions, a single coinbase in front, and outputs that
+    /// neither go negative nor overflow.
+    fn check_transactions(block: &Block) -> Result<(), BlockError> {
+        let transactions = block.get_transactions();
+        if let Some(tx) = transactions.iter().find(|tx| !tx.has_valid_id()) {
+            return Err(BlockError::InvalidTransaction(HEXLOWER.encode(tx.get_id())));
+        }
+        if let Some(txid) = block.find_duplicate_transaction() {
+            return Err(BlockError::DuplicateTransaction(HEXLOWER.encode(txid)));
+        }
+        if let Some(tx) = transactions.iter().find(|tx| tx.get_size() > MAX_TX_SIZE) {
+            return Err(BlockError::TransactionTooLarge(
+                HEXLOWER.encode(tx.get_id()),
+            ));
+        }
+        let coinbase_count = transactions.iter().filter(|tx| tx.is_coinbase()).count();
+        if coinbase_count != 1 || !transactions[0].is_coinbase() {
+            return Err(BlockError::BadCoinbase);
+        }
+        if let Some(tx) = transactions
+            .iter()
+            .find(|tx| tx.checked_output_value().is_none())
+        {
+            return Err(BlockError::InvalidOutputValue(HEXLOWER.encode(tx.get_id())));
+        }
+        Ok(())
     }
 
     pub fn iterator(&self) -> BlockchainIterator {
         BlockchainIterator::new(self.get_tip_hash(), self.db.clone())
     }
 
-    pub fn find_utxo(&self) -> HashMap<String, Vec<TXOutput>> {
-        let mut utxo: HashMap<String, Vec<TXOutput>> = HashMap::new();
+    /// Unspent outputs of every transaction, with the block confirming it.
+    pub fn find_utxo(&self) -> HashMap<String, (BlockTime, BTreeMap<usize, TXOutput>)> {
+        let mut utxo: HashMap<String, (BlockTime, BTreeMap<usize, TXOutput>)> = HashMap::new();
         let mut spent_txos: HashMap<String, Vec<usize>> = HashMap::new();
 
         let mut iterator = self.iterator();
@@ -109,21 +312,21 @@ impl Blockchain {
                 break;
             }
             let block = option.unwrap();
-            'outer: for tx in block.get_transactions() {
+            for tx in block.get_transactions().iter().rev() {
                 let txid_hex = HEXLOWER.encode(tx.get_id());
                 for (idx, out) in tx.get_vout().iter().enumerate() {
-                    if let Some(outs) = spent_txos.get(txid_hex.as_str()) {
-                        for spend_out_idx in outs {
-                            if idx.eq(spend_out_idx) {
-                                continue 'outer;
-                            }
-                        }
+                    if !out.is_spendable() {
+                        continue;
                     }
-                    if utxo.contains_key(txid_hex.as_str()) {
-                        utxo.get_mut(txid_hex.as_str()).unwrap().push(out.clone());
-                    } else {
-                        utxo.insert(txid_hex.clone(), vec![out.clone()]);
+                    if let Some(outs) = spent_txos.get(txid_hex.as_str())
+                        && outs.contains(&idx)
+                    {
+                        continue;
                     }
+                    utxo.entry(txid_hex.clone())
+                        .or_insert_with(|| (self.get_block_time(&block), BTreeMap::new()))
+                        .1
+                        .insert(idx, out.clone());
                 }
                 if tx.is_coinbase() {
                     continue;
@@ -164,6 +367,51 @@ impl Blockchain {
         None
     }
 
+    /// The earliest transaction with a data output carrying `data`, and the
+    /// block it is in.
+    pub fn find_data(&self, data: &[u8]) -> Option<(Block, Transaction)> {
+        let mut found = None;
+        let mut iterator = self.iterator();
+        while let Some(block) = iterator.next() {
+            let tx = block.get_transactions().iter().find(|tx| {
+                tx.get_vout()
+                    .iter()
+                    .any(|out| out.get_script_pubkey().get_data() == Some(data))
+            });
+            if let Some(tx) = tx {
+                found = Some((block.clone(), tx.clone()));
+            }
+        }
+        found
+    }
+
+    pub fn find_used_addresses(&self) -> HashSet<String> {
+        let mut addresses = HashSet::new();
+        let mut iterator = self.iterator();
+        while let Some(block) = iterator.next() {
+            for tx in block.get_transactions() {
+                addresses.extend(
+                    tx.get_vout()
+                        .iter()
+                        .filter_map(|out| out.get_script_pubkey().get_address()),
+                );
+            }
+        }
+        addresses
+    }
+
+    /// Validates `block` against the current tip and the UTXO set and, if
+    /// it passes, makes it the new tip and applies it to the UTXO set. Every
+    /// path that extends the chain goes through here, one block at a time.
+    pub fn connect_block(&self, block: &Block) -> Result<(), BlockError> {
+        let _guard = self.connect_lock.lock().unwrap();
+        let utxo_set = UTXOSet::new(self.clone());
+        self.validate_block(block, &utxo_set)?;
+        self.add_block(block);
+        utxo_set.update(block);
+        Ok(())
+    }
+
     pub fn add_block(&self, block: &Block) {
         let block_tree = self.db.open_tree(BLOCKS_TREE).unwrap();
         if let Some(_) = block_tree.get(block.get_hash()).unwrap() {
@@ -196,6 +444,38 @@ impl Blockchain {
         tip_block.get_height()
     }
 
+    /// Median timestamp of the last blocks up to the tip, which the
+    /// timestamp of the next block must exceed.
+    pub fn get_median_time_past(&self) -> i64 {
+        self.median_time_past_at(self.get_tip_hash())
+    }
+
+    /// Median timestamp of the last blocks up to `block_hash`, or 0 before
+    /// the genesis block.
+    fn median_time_past_at(&self, block_hash: String) -> i64 {
+        let mut iterator = BlockchainIterator::new(block_hash, self.db.clone());
+        let mut timestamps = vec![];
+        while timestamps.len() < MEDIAN_TIME_SPAN {
+            match iterator.next() {
+                Some(block) => timestamps.push(block.get_timestamp()),
+                None => break,
+            }
+        }
+        timestamps.sort_unstable();
+        timestamps.get(timestamps.len() / 2).copied().unwrap_or(0)
+    }
+
+    /// Height and time that lock times in `block` are checked at, and that
+    /// relative lock times of its outputs count from. The time is the median
+    /// time past of its parent, which, unlike the block's own timestamp,
+    /// its miner cannot move ahead.
+    pub fn get_block_time(&self, block: &Block) -> BlockTime {
+        BlockTime {
+            height: block.get_height(),
+            time: self.median_time_past_at(block.get_prev_block_hash()) / 1000,
+        }
+    }
+
     pub fn get_block(&self, block_hash: &[u8]) -> Option<Block> {
         let block_tree = self.db.open_tree(BLOCKS_TREE).unwrap();
         if let Some(block_bytes) = block_tree.get(block_hash).unwrap() {
@@ -257,7 +537,9 @@ mod tests {
     #[test]
     fn test_mine_block() {
         let blockchain = super::Blockchain::new_blockchain();
-        let _ = blockchain.mine_block(&vec![]);
+        let address = crate::Wallet::new().get_address();
+        let coinbase_tx = crate::Transaction::new_coinbase_tx(address.as_str());
+        let _ = blockchain.mine_block(&[coinbase_tx]);
     }
 
     #[test]
@@ -283,6 +565,38 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_validate_block() {
+        let blockchain = super::Blockchain::new_blockchain();
+        let utxo_set = crate::UTXOSet::new(blockchain.clone());
+        let tip = blockchain
+            .get_block(blockchain.get_tip_hash().as_bytes())
+            .unwrap();
+        assert_eq!(
+            blockchain.validate_block(&tip, &utxo_set),
+            Err(super::BlockError::AlreadyKnown)
+        );
+
+        let orphan = Block::new_template("00".repeat(32), &vec![], 1);
+        assert_eq!(
+            blockchain.validate_block(&orphan, &utxo_set),
+            Err(super::BlockError::UnknownParent)
+        );
+    }
+
+    #[test]
+    fn test_validate_block_negative_output() {
+        let address = crate::Wallet::new().get_address();
+        let coinbase = crate::Transaction::new_coinbase_tx_with_value(address.as_str(), -5);
+        let block = Block::new_template("00".repeat(32), &[coinbase.clone()], 1);
+        assert_eq!(
+            super::Blockchain::check_transactions(&block),
+            Err(super::BlockError::InvalidOutputValue(
+                data_encoding::HEXLOWER.encode(coinbase.get_id())
+            ))
+        );
+    }
+
     #[test]
     fn test_get_block_hashes() {
         let blockchain = super::Blockchain::new_blockchain();
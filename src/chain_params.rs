@@ -0,0 +1,117 @@
+use crate::GLOBAL_CONFIG;
+use crate::pow_algorithm::{DoubleSha256Pow, PowAlgorithm, ScryptPow, Sha256Pow};
+use crate::proof_of_work::TARGET_BITS;
+
+use once_cell::sync::Lazy;
+
+/// How a chain agrees on its blocks, see [`crate::consensus`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConsensusKind {
+    ProofOfWork,
+    ProofOfAuthority,
+}
+
+/// Consensus settings that differ between chains. Every node, miner and
+/// pool worker of a chain must run with the same parameters, chosen by the
+/// `CHAIN` environment variable for the whole life of the chain.
+pub struct ChainParams {
+    name: &'static str,
+    consensus: ConsensusKind,
+    pow: &'static dyn PowAlgorithm,
+    target_bits: i32,
+}
+
+/// The chain as it has always been: single SHA-256.
+pub const MAIN: ChainParams = ChainParams {
+    name: "main",
+    consensus: ConsensusKind::ProofOfWork,
+    pow: &Sha256Pow,
+    target_bits: TARGET_BITS,
+};
+
+/// Private chain hashing headers with double SHA-256.
+pub const SHA256D: ChainParams = ChainParams {
+    name: "sha256d",
+    consensus: ConsensusKind::ProofOfWork,
+    pow: &DoubleSha256Pow,
+    target_bits: TARGET_BITS,
+};
+
+/// Private chain with memory-hard scrypt (N = 1024, r = 1, p = 1). Each
+/// hash is far slower, so the target is easier.
+pub const SCRYPT: ChainParams = ChainParams {
+    name: "scrypt",
+    consensus: ConsensusKind::ProofOfWork,
+    pow: &ScryptPow {
+        log_n: 10,
+        r: 1,
+        p: 1,
+    },
+    target_bits: 4,
+};
+
+/// Private chain whose blocks are signed in turn by the authorities listed
+/// in `AUTHORITIES` instead of mined. The hash settings are unused.
+pub const POA: ChainParams = ChainParams {
+    name: "poa",
+    consensus: ConsensusKind::ProofOfAuthority,
+    pow: &Sha256Pow,
+    target_bits: TARGET_BITS,
+};
+
+const ALL_CHAINS: [&ChainParams; 4] = [&MAIN, &SHA256D, &SCRYPT, &POA];
+
+static CURRENT: Lazy<&'static ChainParams> = Lazy::new(|| {
+    let name = GLOBAL_CONFIG.get_chain();
+    ChainParams::from_name(name.as_str()).unwrap_or_else(|| panic!("Unknown chain {}", name))
+});
+
+impl ChainParams {
+    /// Parameters of the chain this process runs on.
+    pub fn current() -> &'static ChainParams {
+        *CURRENT
+    }
+
+    pub fn from_name(name: &str) -> Option<&'static ChainParams> {
+        ALL_CHAINS.into_iter().find(|params| params.name == name)
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn get_consensus(&self) -> ConsensusKind {
+        self.consensus
+    }
+
+    pub fn get_pow(&self) -> &'static dyn PowAlgorithm {
+        self.pow
+    }
+
+    /// Leading zero bits a block hash needs.
+    pub fn get_target_bits(&self) -> i32 {
+        self.target_bits
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ChainParams, ConsensusKind};
+
+    #[test]
+    fn test_from_name() {
+        for name in ["main", "sha256d", "scrypt"] {
+            let params = ChainParams::from_name(name).unwrap();
+            assert_eq!(params.get_name(), name);
+            assert_eq!(
+                params.get_pow().name(),
+                if name == "main" { "sha256" } else { name }
+            );
+        }
+        assert!(ChainParams::from_name("unknown").is_none());
+        assert_eq!(
+            ChainParams::from_name("poa").unwrap().get_consensus(),
+            ConsensusKind::ProofOfAuthority
+        );
+    }
+}
@@ -0,0 +1,348 @@
+use crate::transaction::TXOutput;
+
+use data_encoding::HEXLOWER;
+use ring::rand::{SecureRandom, SystemRandom};
+
+/// Most inputs consolidation adds to a transaction, which keeps it well
+/// under the standard size.
+pub const MAX_CONSOLIDATION_INPUTS: usize = 200;
+/// Subsets branch and bound tries before it gives up on an exact match.
+const MAX_BNB_TRIES: usize = 100_000;
+
+#[derive(Clone)]
+pub struct Coin {
+    txid: Vec<u8>,
+    vout: usize,
+    output: TXOutput,
+}
+
+#[derive(Clone)]
+pub struct Selection {
+    coins: Vec<Coin>,
+    fee: i32,
+    change: i32,
+}
+
+pub trait CoinSelection: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn select(&self, coins: &[Coin], target: i32) -> Option<Vec<Coin>>;
+}
+
+pub struct LargestFirst;
+
+/// Searches for coins adding up to exactly the target, so no change output
+/// is needed, and falls back to largest first when there are none.
+pub struct BranchAndBound;
+
+/// Picks random coins until the target is covered, then adds more random
+/// coins while they bring the change closer to the target itself, up to
+/// twice the target. Change about the size of the payment keeps the wallet
+/// able to make similar payments without fragmenting it into dust.
+pub struct RandomImprove;
+
+/// Pays with the coins needed, largest first, then sweeps in the smallest
+/// coins up to [`MAX_CONSOLIDATION_INPUTS`], merging them into the change
+/// while fees are low.
+pub struct Consolidation;
+
+const ALL_STRATEGIES: [&dyn CoinSelection; 4] = [
+    &BranchAndBound,
+    &LargestFirst,
+    &RandomImprove,
+    &Consolidation,
+];
+
+pub const DEFAULT: &dyn CoinSelection = &BranchAndBound;
+
+pub fn from_name(name: &str) -> Option<&'static dyn CoinSelection> {
+    ALL_STRATEGIES
+        .into_iter()
+        .find(|strategy| strategy.name() == name)
+}
+
+pub fn names() -> Vec<&'static str> {
+    ALL_STRATEGIES
+        .iter()
+        .map(|strategy| strategy.name())
+        .collect()
+}
+
+impl Coin {
+    pub fn new(txid: &[u8], vout: usize, output: TXOutput) -> Coin {
+        Coin {
+            txid: txid.to_vec(),
+            vout,
+            output,
+        }
+    }
+
+    pub fn get_txid(&self) -> &[u8] {
+        self.txid.as_slice()
+    }
+
+    pub fn get_vout(&self) -> usize {
+        self.vout
+    }
+
+    pub fn get_output(&self) -> &TXOutput {
+        &self.output
+    }
+
+    pub fn get_value(&self) -> i32 {
+        self.output.get_value()
+    }
+}
+
+impl Selection {
+    /// Chooses coins with `strategy` to pay `amount` and `fee`, or `None`
+    /// if they are not worth enough or the change does not fit an amount.
+    pub fn new(
+        strategy: &dyn CoinSelection,
+        coins: &[Coin],
+        amount: i32,
+        fee: i32,
+    ) -> Option<Selection> {
+        let target = amount.checked_add(fee)?;
+        let coins = strategy.select(coins, target)?;
+        let change = i32::try_from(total(coins.as_slice()) - target as i64).ok()?;
+        Some(Selection { coins, fee, change })
+    }
+
+    pub fn get_coins(&self) -> &[Coin] {
+        self.coins.as_slice()
+    }
+
+    pub fn get_fee(&self) -> i32 {
+        self.fee
+    }
+
+    pub fn get_change(&self) -> i32 {
+        self.change
+    }
+}
+
+impl std::fmt::Display for Selection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for coin in &self.coins {
+            writeln!(
+                f,
+                "Input: {}:{} value = {}",
+                HEXLOWER.encode(coin.get_txid()),
+                coin.get_vout(),
+                coin.get_value()
+            )?;
+        }
+        writeln!(f, "Fee: {}", self.fee)?;
+        write!(f, "Change: {}", self.change)
+    }
+}
+
+impl CoinSelection for LargestFirst {
+    fn name(&self) -> &'static str {
+        "largest-first"
+    }
+
+    fn select(&self, coins: &[Coin], target: i32) -> Option<Vec<Coin>> {
+        let mut sorted = coins.to_vec();
+        sorted.sort_by_key(|coin| std::cmp::Reverse(coin.get_value()));
+        let mut selected = vec![];
+        let mut value = 0;
+        for coin in sorted {
+            if value >= target as i64 {
+                break;
+            }
+            value += coin.get_value() as i64;
+            selected.push(coin);
+        }
+        (value >= target as i64).then_some(selected)
+    }
+}
+
+impl CoinSelection for BranchAndBound {
+    fn name(&self) -> &'static str {
+        "bnb"
+    }
+
+    fn select(&self, coins: &[Coin], target: i32) -> Option<Vec<Coin>> {
+        let mut sorted = coins.to_vec();
+        sorted.sort_by_key(|coin| std::cmp::Reverse(coin.get_value()));
+        // What the coins from each position on are worth together, to
+        // stop exploring a branch that can no longer reach the target.
+        let mut remaining = vec![0; sorted.len() + 1];
+        for idx in (0..sorted.len()).rev() {
+            remaining[idx] = remaining[idx + 1] + sorted[idx].get_value() as i64;
+        }
+
+        let mut included = vec![];
+        let mut tries = 0;
+        if exact_match(&sorted, &remaining, 0, target, &mut included, &mut tries) {
+            return Some(included.iter().map(|idx| sorted[*idx].clone()).collect());
+        }
+        LargestFirst.select(coins, target)
+    }
+}
+
+/// Depth-first search for coins from `idx` on worth exactly `target`,
+/// trying each coin first with and then without it.
+fn exact_match(
+    sorted: &[Coin],
+    remaining: &[i64],
+    idx: usize,
+    target: i32,
+    included: &mut Vec<usize>,
+    tries: &mut usize,
+) -> bool {
+    if target == 0 {
+        return true;
+    }
+    *tries += 1;
+    if idx == sorted.len() || target < 0 || remaining[idx] < target as i64 || *tries > MAX_BNB_TRIES
+    {
+        return false;
+    }
+    included.push(idx);
+    if exact_match(
+        sorted,
+        remaining,
+        idx + 1,
+        target - sorted[idx].get_value(),
+        included,
+        tries,
+    ) {
+        return true;
+    }
+    included.pop();
+    exact_match(sorted, remaining, idx + 1, target, included, tries)
+}
+
+impl CoinSelection for RandomImprove {
+    fn name(&self) -> &'static str {
+        "random-improve"
+    }
+
+    fn select(&self, coins: &[Coin], target: i32) -> Option<Vec<Coin>> {
+        let mut shuffled = coins.to_vec();
+        shuffle(shuffled.as_mut_slice());
+        let mut selected = vec![];
+        let mut value = 0;
+        while value < target as i64 {
+            let coin = shuffled.pop()?;
+            value += coin.get_value() as i64;
+            selected.push(coin);
+        }
+
+        let ideal = target as i64 * 2;
+        let limit = target as i64 * 3;
+        for coin in shuffled {
+            let improved = value + coin.get_value() as i64;
+            if improved <= limit && (ideal - improved).abs() < (ideal - value).abs() {
+                value = improved;
+                selected.push(coin);
+            }
+        }
+        Some(selected)
+    }
+}
+
+impl CoinSelection for Consolidation {
+    fn name(&self) -> &'static str {
+        "consolidate"
+    }
+
+    fn select(&self, coins: &[Coin], target: i32) -> Option<Vec<Coin>> {
+        let mut selected = LargestFirst.select(coins, target)?;
+        let mut rest: Vec<Coin> = coins
+            .iter()
+            .filter(|coin| {
+                !selected
+                    .iter()
+                    .any(|chosen| chosen.txid == coin.txid && chosen.vout == coin.vout)
+            })
+            .cloned()
+            .collect();
+        rest.sort_by_key(|coin| coin.get_value());
+        let room = MAX_CONSOLIDATION_INPUTS.saturating_sub(selected.len());
+        selected.extend(rest.into_iter().take(room));
+        Some(selected)
+    }
+}
+
+fn total(coins: &[Coin]) -> i64 {
+    coins.iter().map(|coin| coin.get_value() as i64).sum()
+}
+
+fn shuffle(coins: &mut [Coin]) {
+    let rng = SystemRandom::new();
+    for idx in (1..coins.len()).rev() {
+        let mut bytes = [0; 8];
+        rng.fill(&mut bytes).unwrap();
+        let other = (u64::from_le_bytes(bytes) % (idx as u64 + 1)) as usize;
+        coins.swap(idx, other);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        BranchAndBound, Coin, CoinSelection, Consolidation, LargestFirst, RandomImprove, Selection,
+        from_name, total,
+    };
+    use crate::Wallet;
+    use crate::transaction::TXOutput;
+
+    fn coins(values: &[i32]) -> Vec<Coin> {
+        let address = Wallet::new().get_address();
+        values
+            .iter()
+            .enumerate()
+            .map(|(vout, value)| {
+                Coin::new(b"funding", vout, TXOutput::new(*value, address.as_str()))
+            })
+            .collect()
+    }
+
+    fn values(coins: &[Coin]) -> Vec<i32> {
+        let mut values: Vec<i32> = coins.iter().map(|coin| coin.get_value()).collect();
+        values.sort();
+        values
+    }
+
+    #[test]
+    fn test_strategies() {
+        let wallet = coins(&[1, 2, 5, 10, 20, 3]);
+
+        let largest = LargestFirst.select(&wallet, 25).unwrap();
+        assert_eq!(values(&largest), vec![10, 20]);
+        assert!(LargestFirst.select(&wallet, 42).is_none());
+
+        // 5 + 3 + 1 needs no change where largest first would.
+        let exact = BranchAndBound.select(&wallet, 9).unwrap();
+        assert_eq!(total(&exact), 9);
+        let selection = Selection::new(&BranchAndBound, &wallet, 8, 1).unwrap();
+        assert_eq!(selection.get_change(), 0);
+        // No subset adds up to 42 - 1, so it falls back.
+        let fallback = BranchAndBound.select(&coins(&[20, 20, 2]), 41).unwrap();
+        assert_eq!(values(&fallback), vec![2, 20, 20]);
+
+        // Coins keep being added while they bring the change closer to
+        // the payment.
+        let random = RandomImprove.select(&coins(&[1; 30]), 5).unwrap();
+        assert_eq!(total(&random), 10);
+        assert!(RandomImprove.select(&wallet, 42).is_none());
+
+        let consolidated = Consolidation.select(&wallet, 12).unwrap();
+        assert_eq!(consolidated.len(), wallet.len());
+        let selection = Selection::new(&Consolidation, &wallet, 12, 1).unwrap();
+        assert_eq!(selection.get_change(), 41 - 13);
+
+        // Totals past what an amount holds are refused rather than wrapped.
+        let large = coins(&[i32::MAX, i32::MAX]);
+        assert!(Selection::new(&LargestFirst, &large, i32::MAX, 1).is_none());
+        let selection = Selection::new(&BranchAndBound, &large, i32::MAX - 1, 1).unwrap();
+        assert_eq!(selection.get_change(), 0);
+
+        assert_eq!(from_name("bnb").unwrap().name(), "bnb");
+        assert!(from_name("unknown").is_none());
+    }
+}
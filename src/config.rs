@@ -1,7 +1,10 @@
 use std::collections::HashMap;
 use std::env;
+use std::str::FromStr;
 use std::sync::RwLock;
+use std::thread;
 
+use log::warn;
 use once_cell::sync::Lazy;
 
 pub static GLOBAL_CONFIG: Lazy<Config> = Lazy::new(|| Config::new());
@@ -10,6 +13,26 @@ static DEFAULT_NODE_ADDR: &str = "127.0.0.1:42069";
 
 const NODE_ADDRESS_KEY: &str = "NODE_ADDRESS";
 const MINING_ADDRESS_KEY: &str = "MINING_ADDRESS";
+const MIN_RELAY_FEE_KEY: &str = "MIN_RELAY_FEE";
+const MEMPOOL_MAX_SIZE_KEY: &str = "MEMPOOL_MAX_SIZE";
+const MEMPOOL_EXPIRY_KEY: &str = "MEMPOOL_EXPIRY";
+const MINING_BLOCK_INTERVAL_KEY: &str = "MINING_BLOCK_INTERVAL";
+const MINING_MIN_TXS_KEY: &str = "MINING_MIN_TXS";
+const MINING_THREADS_KEY: &str = "MINING_THREADS";
+const POOL_ADDRESS_KEY: &str = "POOL_ADDRESS";
+const METRICS_ADDRESS_KEY: &str = "METRICS_ADDRESS";
+const CHAIN_KEY: &str = "CHAIN";
+const AUTHORITIES_KEY: &str = "AUTHORITIES";
+
+/// Default minimum relay fee, in coins per 1000 serialized bytes.
+const DEFAULT_MIN_RELAY_FEE: i64 = 1;
+const DEFAULT_MEMPOOL_MAX_SIZE: usize = 50_000_000;
+const DEFAULT_MEMPOOL_EXPIRY: i64 = 14 * 24 * 60 * 60;
+const DEFAULT_MINING_BLOCK_INTERVAL: u64 = 30;
+const DEFAULT_MINING_MIN_TXS: usize = 2;
+
+/// Chain parameters used unless `CHAIN` picks others.
+const DEFAULT_CHAIN: &str = "main";
 
 pub struct Config {
     inner: RwLock<HashMap<String, String>>,
@@ -26,6 +49,23 @@ impl Config {
         let mut map = HashMap::new();
         map.insert(String::from(NODE_ADDRESS_KEY), node_adddr);
 
+        for key in [
+            MIN_RELAY_FEE_KEY,
+            MEMPOOL_MAX_SIZE_KEY,
+            MEMPOOL_EXPIRY_KEY,
+            MINING_BLOCK_INTERVAL_KEY,
+            MINING_MIN_TXS_KEY,
+            MINING_THREADS_KEY,
+            POOL_ADDRESS_KEY,
+            METRICS_ADDRESS_KEY,
+            CHAIN_KEY,
+            AUTHORITIES_KEY,
+        ] {
+            if let Ok(value) = env::var(key) {
+                map.insert(String::from(key), value);
+            }
+        }
+
         Config {
             inner: RwLock::new(map),
         }
@@ -54,11 +94,89 @@ impl Config {
         let inner = self.inner.read().unwrap();
         inner.contains_key(MINING_ADDRESS_KEY)
     }
+
+    pub fn get_min_relay_fee(&self) -> i64 {
+        self.parse_or(MIN_RELAY_FEE_KEY, DEFAULT_MIN_RELAY_FEE)
+    }
+
+    pub fn get_mempool_max_size(&self) -> usize {
+        self.parse_or(MEMPOOL_MAX_SIZE_KEY, DEFAULT_MEMPOOL_MAX_SIZE)
+    }
+
+    pub fn get_mempool_expiry(&self) -> i64 {
+        self.parse_or(MEMPOOL_EXPIRY_KEY, DEFAULT_MEMPOOL_EXPIRY)
+    }
+
+    pub fn get_mining_block_interval(&self) -> u64 {
+        self.parse_or(MINING_BLOCK_INTERVAL_KEY, DEFAULT_MINING_BLOCK_INTERVAL)
+    }
+
+    pub fn get_mining_min_txs(&self) -> usize {
+        self.parse_or(MINING_MIN_TXS_KEY, DEFAULT_MINING_MIN_TXS)
+    }
+
+    /// Number of threads searching for proof of work, one per CPU by default.
+    pub fn get_mining_threads(&self) -> usize {
+        let default = thread::available_parallelism().map_or(1, |n| n.get());
+        self.parse_or(MINING_THREADS_KEY, default)
+    }
+
+    /// Address the mining pool listens on for workers, if pool mode is on.
+    pub fn get_pool_addr(&self) -> Option<String> {
+        let inner = self.inner.read().unwrap();
+        inner.get(POOL_ADDRESS_KEY).cloned()
+    }
+
+    /// Name of the chain parameters to run with, `main` by default.
+    pub fn get_chain(&self) -> String {
+        let inner = self.inner.read().unwrap();
+        inner
+            .get(CHAIN_KEY)
+            .cloned()
+            .unwrap_or_else(|| String::from(DEFAULT_CHAIN))
+    }
+
+    /// Hex public keys of the proof-of-authority signers, in turn order.
+    pub fn get_authorities(&self) -> Vec<String> {
+        let inner = self.inner.read().unwrap();
+        match inner.get(AUTHORITIES_KEY) {
+            Some(keys) => keys
+                .split(',')
+                .map(|key| key.trim().to_string())
+                .filter(|key| !key.is_empty())
+                .collect(),
+            None => vec![],
+        }
+    }
+
+    /// The value of `key`, or `default` if it is unset or does not parse.
+    fn parse_or<T: FromStr>(&self, key: &str, default: T) -> T {
+        let inner = self.inner.read().unwrap();
+        let Some(value) = inner.get(key) else {
+            return default;
+        };
+        match value.parse() {
+            Ok(value) => value,
+            Err(_) => {
+                warn!(
+                    "{} is not a valid number: {}, using the default",
+                    key, value
+                );
+                default
+            }
+        }
+    }
+
+    /// Address serving node metrics over HTTP, if enabled.
+    pub fn get_metrics_addr(&self) -> Option<String> {
+        let inner = self.inner.read().unwrap();
+        inner.get(METRICS_ADDRESS_KEY).cloned()
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::NODE_ADDRESS_KEY;
+    use super::{DEFAULT_MIN_RELAY_FEE, MIN_RELAY_FEE_KEY, NODE_ADDRESS_KEY};
     use crate::Config;
     use std::env;
 
@@ -72,4 +190,15 @@ mod tests {
         let node_addr = config.get_node_addr();
         println!("{}", node_addr)
     }
+
+    #[test]
+    fn test_malformed_value_falls_back_to_default() {
+        let config = Config::new();
+        config
+            .inner
+            .write()
+            .unwrap()
+            .insert(String::from(MIN_RELAY_FEE_KEY), String::from("cheap"));
+        assert_eq!(config.get_min_relay_fee(), DEFAULT_MIN_RELAY_FEE);
+    }
 }
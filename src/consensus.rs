@@ -0,0 +1,226 @@
+use crate::chain_params::ConsensusKind;
+use crate::{
+    Block, ChainParams, GLOBAL_CONFIG, MiningStats, ProofOfWork, Wallets,
+    ecdsa_p256_sha256_sign_digest, ecdsa_p256_sha256_sign_verify, sha256_digest,
+};
+
+use std::collections::HashMap;
+use std::sync::atomic::AtomicBool;
+
+use data_encoding::HEXLOWER;
+use once_cell::sync::Lazy;
+
+/// How blocks are sealed and how their seals are checked.
+pub trait Consensus: Send + Sync {
+    /// Seals `block` so it can extend the chain, or returns `None` if
+    /// `cancel` was set or this node may not seal at the block's height.
+    /// Work done on the way is added to `stats`.
+    fn seal(&self, block: Block, cancel: &AtomicBool, stats: &MiningStats) -> Option<Block>;
+
+    /// Whether this node is able to seal a block at `height`.
+    fn can_seal(&self, height: usize) -> bool;
+
+    /// Checks the seal of a block received from elsewhere.
+    fn validate_seal(&self, block: &Block) -> bool;
+}
+
+/// Blocks are sealed by searching for a nonce, see [`ProofOfWork`].
+pub struct ProofOfWorkConsensus;
+
+impl Consensus for ProofOfWorkConsensus {
+    fn seal(&self, block: Block, cancel: &AtomicBool, stats: &MiningStats) -> Option<Block> {
+        let started = std::time::Instant::now();
+        let mut pow = ProofOfWork::new_proof_of_work(block);
+        let result = pow.run_until(cancel);
+        stats.record_hashes(pow.get_hash_count(), started.elapsed());
+        result
+    }
+
+    fn can_seal(&self, _height: usize) -> bool {
+        true
+    }
+
+    fn validate_seal(&self, block: &Block) -> bool {
+        ProofOfWork::new_proof_of_work(block.clone()).validate()
+    }
+}
+
+/// Blocks are signed by a fixed list of authorities taking turns: the block
+/// at height `h` must carry the signature of authority `h % n` over its
+/// header hash. A node seals the blocks whose authority key is in its
+/// wallet file, once unlocked if the wallet is encrypted. If an authority
+/// is offline the chain waits for it.
+pub struct ProofOfAuthority {
+    /// Public keys of the authorities, in signing order.
+    authorities: Vec<Vec<u8>>,
+    /// PKCS#8 keys held by this node, by public key. `None` looks them up
+    /// in the wallet for every block, since `walletpassphrase` and
+    /// `walletlock` change what it holds while the node runs.
+    keys: Option<HashMap<Vec<u8>, Vec<u8>>>,
+}
+
+impl ProofOfAuthority {
+    /// `keys` are the (public key, PKCS#8) pairs this node may sign with.
+    pub fn new(authorities: Vec<Vec<u8>>, keys: Vec<(Vec<u8>, Vec<u8>)>) -> ProofOfAuthority {
+        if authorities.is_empty() {
+            panic!("Proof of authority needs at least one authority")
+        }
+        ProofOfAuthority {
+            authorities,
+            keys: Some(keys.into_iter().collect()),
+        }
+    }
+
+    /// Reads the authorities from `AUTHORITIES`, comma-separated hex public
+    /// keys, and signs with any of them found in the local wallet file.
+    pub fn from_config() -> ProofOfAuthority {
+        let authorities: Vec<Vec<u8>> = GLOBAL_CONFIG
+            .get_authorities()
+            .iter()
+            .map(|key| {
+                HEXLOWER
+                    .decode(key.as_bytes())
+                    .expect("AUTHORITIES must be hex public keys")
+            })
+            .collect();
+        if authorities.is_empty() {
+            panic!("Proof of authority needs at least one authority")
+        }
+        ProofOfAuthority {
+            authorities,
+            keys: None,
+        }
+    }
+
+    fn authority_at(&self, height: usize) -> &[u8] {
+        self.authorities[height % self.authorities.len()].as_slice()
+    }
+
+    /// PKCS#8 key of the authority at `height`, if this node holds it.
+    fn signing_key(&self, height: usize) -> Option<Vec<u8>> {
+        let authority = self.authority_at(height);
+        if let Some(keys) = self.keys.as_ref() {
+            return keys.get(authority).cloned();
+        }
+        let wallets = Wallets::new();
+        wallets
+            .get_addresses()
+            .iter()
+            .filter_map(|address| wallets.get_wallet(address))
+            .find(|wallet| wallet.get_public_key() == authority)
+            .map(|wallet| wallet.get_pkcs8().to_vec())
+    }
+
+    /// The block id. It leaves out the signature, as a txid leaves out the
+    /// unlocking scripts: the authority may sign the same header in more
+    /// than one valid way, and the id stays that of the header.
+    fn header_hash(block: &Block) -> Vec<u8> {
+        let mut data = vec![];
+        data.extend(block.get_prev_block_hash().as_bytes());
+        data.extend(block.hash_transactions());
+        data.extend(block.get_timestamp().to_be_bytes());
+        data.extend(block.get_height().to_be_bytes());
+        sha256_digest(data.as_slice())
+    }
+}
+
+impl Consensus for ProofOfAuthority {
+    fn seal(&self, mut block: Block, _cancel: &AtomicBool, _stats: &MiningStats) -> Option<Block> {
+        let pkcs8 = self.signing_key(block.get_height())?;
+        let hash = ProofOfAuthority::header_hash(&block);
+        let signature = ecdsa_p256_sha256_sign_digest(pkcs8.as_slice(), hash.as_slice());
+        block.seal_with_signature(HEXLOWER.encode(hash.as_slice()), signature);
+        Some(block)
+    }
+
+    fn can_seal(&self, height: usize) -> bool {
+        self.signing_key(height).is_some()
+    }
+
+    fn validate_seal(&self, block: &Block) -> bool {
+        let hash = ProofOfAuthority::header_hash(block);
+        HEXLOWER.encode(hash.as_slice()).eq(block.get_hash())
+            && ecdsa_p256_sha256_sign_verify(
+                self.authority_at(block.get_height()),
+                block.get_signature(),
+                hash.as_slice(),
+            )
+    }
+}
+
+static CURRENT: Lazy<Box<dyn Consensus>> =
+    Lazy::new(|| match ChainParams::current().get_consensus() {
+        ConsensusKind::ProofOfWork => Box::new(ProofOfWorkConsensus),
+        ConsensusKind::ProofOfAuthority => Box::new(ProofOfAuthority::from_config()),
+    });
+
+/// Consensus of the chain this process runs on.
+pub fn current() -> &'static dyn Consensus {
+    CURRENT.as_ref()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Consensus, ProofOfAuthority};
+    use crate::{Block, MiningStats, Wallet};
+    use std::sync::atomic::AtomicBool;
+
+    fn key(wallet: &Wallet) -> (Vec<u8>, Vec<u8>) {
+        (
+            wallet.get_public_key().to_vec(),
+            wallet.get_pkcs8().to_vec(),
+        )
+    }
+
+    #[test]
+    fn test_proof_of_authority_round_robin() {
+        let (alice, bob) = (Wallet::new(), Wallet::new());
+        let authorities = vec![
+            alice.get_public_key().to_vec(),
+            bob.get_public_key().to_vec(),
+        ];
+        let alice_node = ProofOfAuthority::new(authorities.clone(), vec![key(&alice)]);
+        let bob_node = ProofOfAuthority::new(authorities.clone(), vec![key(&bob)]);
+        let (cancel, stats) = (AtomicBool::new(false), MiningStats::new());
+
+        assert!(alice_node.can_seal(0) && !alice_node.can_seal(1));
+        assert!(bob_node.can_seal(1) && !bob_node.can_seal(2));
+
+        let block = Block::new_template(String::from("none"), &[], 2);
+        assert!(bob_node.seal(block.clone(), &cancel, &stats).is_none());
+        let sealed = alice_node.seal(block, &cancel, &stats).unwrap();
+        assert!(alice_node.validate_seal(&sealed));
+        assert!(bob_node.validate_seal(&sealed));
+
+        // A valid signature, but from the authority whose turn it is not.
+        let reversed = vec![
+            bob.get_public_key().to_vec(),
+            alice.get_public_key().to_vec(),
+        ];
+        let impostor = ProofOfAuthority::new(reversed, vec![key(&bob)]);
+        let block = Block::new_template(String::from("none"), &[], 2);
+        let forged = impostor.seal(block, &cancel, &stats).unwrap();
+        assert!(impostor.validate_seal(&forged));
+        assert!(!alice_node.validate_seal(&forged));
+
+        let mut tampered = sealed.clone();
+        tampered.set_timestamp(sealed.get_timestamp() + 1);
+        assert!(!alice_node.validate_seal(&tampered));
+    }
+
+    #[test]
+    fn test_proof_of_authority_id_ignores_signature() {
+        let alice = Wallet::new();
+        let node = ProofOfAuthority::new(vec![alice.get_public_key().to_vec()], vec![key(&alice)]);
+        let (cancel, stats) = (AtomicBool::new(false), MiningStats::new());
+
+        let block = Block::new_template(String::from("none"), &[], 1);
+        let first = node.seal(block.clone(), &cancel, &stats).unwrap();
+        let second = node.seal(block, &cancel, &stats).unwrap();
+        // ECDSA signing is randomized, so the two seals differ.
+        assert_ne!(first.get_signature(), second.get_signature());
+        assert_eq!(first.get_hash(), second.get_hash());
+        assert!(node.validate_seal(&first));
+        assert!(node.validate_seal(&second));
+    }
+}
@@ -0,0 +1,176 @@
+use crate::Wallet;
+
+use p256::elliptic_curve::sec1::ToEncodedPoint;
+use p256::elliptic_curve::{Field, PrimeField};
+use p256::{FieldBytes, Scalar, SecretKey};
+use ring::hmac;
+
+/// Child indexes from this one on are hardened: derived from the parent
+/// private key, so a leaked child key and the parent chain code do not give
+/// away the parent.
+pub const HARDENED: u32 = 0x8000_0000;
+/// Path of the receiving addresses, laid out as in BIP44: purpose, coin
+/// type, account and external chain. The address index comes last.
+pub const RECEIVE_PATH: [u32; 4] = [44 | HARDENED, HARDENED, HARDENED, 0];
+/// Unused addresses looked at past the last used one before a scan stops.
+pub const DEFAULT_GAP_LIMIT: u32 = 20;
+
+/// HMAC key of the master key, as SLIP-10 defines it for P-256.
+const MASTER_KEY: &[u8] = b"Nist256p1 seed";
+const KEY_LEN: usize = 32;
+
+pub fn public_key_of(private_key: &[u8]) -> Vec<u8> {
+    encoded_public_key_of(private_key, false)
+}
+
+/// Compressed SEC1 public key, which child derivation commits to.
+fn compressed_public_key_of(private_key: &[u8]) -> Vec<u8> {
+    encoded_public_key_of(private_key, true)
+}
+
+fn encoded_public_key_of(private_key: &[u8], compress: bool) -> Vec<u8> {
+    SecretKey::from_slice(private_key)
+        .expect("Invalid private key")
+        .public_key()
+        .to_encoded_point(compress)
+        .as_bytes()
+        .to_vec()
+}
+
+fn scalar_of(bytes: &[u8]) -> Option<Scalar> {
+    let bytes: [u8; KEY_LEN] = bytes.try_into().ok()?;
+    Scalar::from_repr(FieldBytes::from(bytes)).into()
+}
+
+/// A P-256 private key with the chain code that derives its children, as
+/// in SLIP-10.
+#[derive(Clone)]
+pub struct ExtendedKey {
+    private_key: Vec<u8>,
+    chain_code: Vec<u8>,
+}
+
+impl ExtendedKey {
+    pub fn from_seed(seed: &[u8]) -> ExtendedKey {
+        let key = hmac::Key::new(hmac::HMAC_SHA512, MASTER_KEY);
+        let mut data = seed.to_vec();
+        loop {
+            let tag = hmac::sign(&key, data.as_slice());
+            let (left, right) = tag.as_ref().split_at(KEY_LEN);
+            if scalar_of(left).is_some_and(|private_key| !bool::from(private_key.is_zero())) {
+                return ExtendedKey {
+                    private_key: left.to_vec(),
+                    chain_code: right.to_vec(),
+                };
+            }
+            data = tag.as_ref().to_vec();
+        }
+    }
+
+    pub fn derive_child(&self, index: u32) -> ExtendedKey {
+        let parent = scalar_of(self.private_key.as_slice()).unwrap();
+        let key = hmac::Key::new(hmac::HMAC_SHA512, self.chain_code.as_slice());
+        let mut data = if index >= HARDENED {
+            let mut data = vec![0];
+            data.extend(self.private_key.as_slice());
+            data
+        } else {
+            compressed_public_key_of(self.private_key.as_slice())
+        };
+        data.extend(index.to_be_bytes());
+        loop {
+            let tag = hmac::sign(&key, data.as_slice());
+            let (left, right) = tag.as_ref().split_at(KEY_LEN);
+            let child = scalar_of(left)
+                .map(|tweak| tweak + parent)
+                .filter(|child| !bool::from(child.is_zero()));
+            if let Some(child) = child {
+                return ExtendedKey {
+                    private_key: child.to_repr().to_vec(),
+                    chain_code: right.to_vec(),
+                };
+            }
+            data = vec![1];
+            data.extend(right);
+            data.extend(index.to_be_bytes());
+        }
+    }
+
+    pub fn derive_path(&self, path: &[u32]) -> ExtendedKey {
+        path.iter()
+            .fold(self.clone(), |key, index| key.derive_child(*index))
+    }
+
+    pub fn get_private_key(&self) -> &[u8] {
+        self.private_key.as_slice()
+    }
+
+    pub fn get_chain_code(&self) -> &[u8] {
+        self.chain_code.as_slice()
+    }
+
+    pub fn to_wallet(&self) -> Wallet {
+        let public_key = public_key_of(self.private_key.as_slice());
+        Wallet::from_private_key(self.private_key.as_slice(), public_key.as_slice())
+            .expect("Derived an invalid key pair")
+    }
+}
+
+/// Parent key of the receiving addresses of `seed`: the address at index
+/// `i` is that of its child `i`.
+pub fn receive_chain(seed: &[u8]) -> ExtendedKey {
+    ExtendedKey::from_seed(seed).derive_path(&RECEIVE_PATH)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ExtendedKey, HARDENED, public_key_of};
+    use crate::Wallet;
+    use data_encoding::HEXLOWER;
+
+    #[test]
+    fn test_public_key_of() {
+        let wallet = Wallet::new();
+        // The private key follows the fixed PKCS#8 prefix.
+        let private_key = &wallet.get_pkcs8()[36..68];
+        assert_eq!(public_key_of(private_key), wallet.get_public_key());
+    }
+
+    #[test]
+    fn test_derive_slip10_vector() {
+        let seed = HEXLOWER
+            .decode(b"000102030405060708090a0b0c0d0e0f")
+            .unwrap();
+        let master = ExtendedKey::from_seed(seed.as_slice());
+        assert_eq!(
+            HEXLOWER.encode(master.get_chain_code()),
+            "beeb672fe4621673f722f38529c07392fecaa61015c80c34f29ce8b41b3cb6ea"
+        );
+        assert_eq!(
+            HEXLOWER.encode(master.get_private_key()),
+            "612091aaa12e22dd2abef664f8a01a82cae99ad7441b7ef8110424915c268bc2"
+        );
+        let child = master.derive_child(HARDENED);
+        assert_eq!(
+            HEXLOWER.encode(child.get_chain_code()),
+            "3460cea53e6a6bb5fb391eeef3237ffd8724bf0a40e94943c98b83825342ee11"
+        );
+        assert_eq!(
+            HEXLOWER.encode(child.get_private_key()),
+            "6939694369114c67917a182c59ddb8cafc3004e63ca5d3b84403ba8613debc0c"
+        );
+        // Normal derivation commits to the compressed parent public key.
+        let grandchild = child.derive_child(1);
+        assert_eq!(
+            HEXLOWER.encode(grandchild.get_chain_code()),
+            "4187afff1aafa8445010097fb99d23aee9f599450c7bd140b6826ac22ba21d0c"
+        );
+        assert_eq!(
+            HEXLOWER.encode(grandchild.get_private_key()),
+            "284e9d38d07d21e4e281b645089a94f4cf5a5a81369acf151a1c3a57f18b2129"
+        );
+
+        let wallet = master.derive_path(&[HARDENED, 1]).to_wallet();
+        assert_eq!(wallet.get_pkcs8(), grandchild.to_wallet().get_pkcs8());
+    }
+}
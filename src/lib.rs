@@ -1,14 +1,47 @@
+mod atomic_swap;
+pub use atomic_swap::ContractAudit;
+pub use atomic_swap::Htlc;
+pub use atomic_swap::SwapError;
+pub use atomic_swap::new_secret;
+
 mod block;
 pub use block::Block;
+pub use block::MAX_BLOCK_SIZE;
+
+mod block_template;
+pub use block_template::BlockTemplate;
 
 mod blockchain;
+pub use blockchain::BlockError;
 pub use blockchain::Blockchain;
+pub use blockchain::MAX_FUTURE_BLOCK_TIME;
+
+pub mod merkle;
+pub use merkle::MerkleProof;
 
 mod proof_of_work;
 pub use proof_of_work::ProofOfWork;
 
+pub mod pow_algorithm;
+pub use pow_algorithm::PowAlgorithm;
+
+pub mod chain_params;
+pub use chain_params::ChainParams;
+
+pub mod consensus;
+pub use consensus::Consensus;
+
+pub mod coin_selection;
+pub use coin_selection::CoinSelection;
+
+pub mod script;
+pub use script::Script;
+
 mod transaction;
+pub use transaction::FeeBumpError;
+pub use transaction::SigHashType;
 pub use transaction::Transaction;
+pub use transaction::TransactionError;
 
 pub mod utils;
 use utils::*;
@@ -17,13 +50,49 @@ mod config;
 pub use config::Config;
 pub use config::GLOBAL_CONFIG;
 
+pub mod hd_wallet;
+pub use hd_wallet::ExtendedKey;
+
 mod memory_pool;
 pub use memory_pool::BlockInTransit;
 pub use memory_pool::MemoryPool;
+pub use memory_pool::RejectReason;
+
+mod miner;
+pub use miner::Miner;
+
+mod mining_info;
+pub use mining_info::MiningInfo;
+pub use mining_info::MiningStats;
+
+mod mining_pool;
+pub use mining_pool::MiningPool;
+pub use mining_pool::PoolClient;
+pub use mining_pool::PoolMessage;
+
+mod mnemonic;
+pub use mnemonic::Mnemonic;
+pub use mnemonic::MnemonicError;
 
 mod node;
 pub use node::Nodes;
 
+mod partial_transaction;
+pub use partial_transaction::PartialError;
+pub use partial_transaction::PartialTransaction;
+
+mod payments;
+pub use payments::PaymentsError;
+pub use payments::parse_payments;
+
+mod raw_transaction;
+pub use raw_transaction::RawTransactionError;
+pub use raw_transaction::create_raw_transaction;
+pub use raw_transaction::decode_raw_transaction;
+pub use raw_transaction::encode_raw_transaction;
+pub use raw_transaction::raw_transaction_to_json;
+pub use raw_transaction::sign_raw_transaction;
+
 mod utxo_set;
 pub use utxo_set::UTXOSet;
 
@@ -31,14 +100,24 @@ mod wallet;
 pub use wallet::ADDRESS_CHECK_SUM_LEN;
 pub use wallet::Wallet;
 pub use wallet::convert_address;
+pub use wallet::convert_script_address;
 pub use wallet::hash_pub_key;
 pub use wallet::validate_address;
 
+mod wallet_encryption;
+
 mod wallets;
+pub use wallets::WalletError;
+pub use wallets::WalletTransactions;
 pub use wallets::Wallets;
 
 mod server;
 pub use server::CENTRAL_NODE;
 pub use server::Package;
 pub use server::Server;
+pub use server::get_block_template;
+pub use server::get_mining_info;
 pub use server::send_tx;
+pub use server::submit_block;
+pub use server::wallet_lock;
+pub use server::wallet_passphrase;
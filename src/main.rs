@@ -1,9 +1,17 @@
 use data_encoding::HEXLOWER;
 use log::LevelFilter;
+use socratix::coin_selection::{self, Selection};
+use socratix::utils::{current_timestamp, sha256_digest};
 use socratix::{
-    ADDRESS_CHECK_SUM_LEN, Blockchain, CENTRAL_NODE, GLOBAL_CONFIG, Server, Transaction, UTXOSet,
-    Wallets, convert_address, hash_pub_key, send_tx, utils, validate_address,
+    Blockchain, CENTRAL_NODE, ContractAudit, GLOBAL_CONFIG, Htlc, MemoryPool, Mnemonic,
+    PartialError, PartialTransaction, Script, Server, SigHashType, Transaction, UTXOSet,
+    WalletError, WalletTransactions, Wallets, consensus, convert_script_address,
+    create_raw_transaction, decode_raw_transaction, encode_raw_transaction, get_mining_info,
+    new_secret, parse_payments, raw_transaction_to_json, send_tx, sign_raw_transaction,
+    validate_address, wallet_lock, wallet_passphrase,
 };
+use std::fs;
+use std::io::{self, BufRead, Write};
 use structopt::StructOpt;
 
 const MINE_TRUE: usize = 1;
@@ -34,6 +42,77 @@ enum Command {
     },
     #[structopt(name = "listaddresses", about = "Print local wallet addres")]
     ListAddresses,
+    #[structopt(
+        name = "getpubkey",
+        about = "Print the public key of a wallet, as listed in AUTHORITIES"
+    )]
+    GetPubKey {
+        #[structopt(name = "address", help = "The wallet address")]
+        address: String,
+    },
+    #[structopt(
+        name = "encryptwallet",
+        about = "Encrypt the private keys of the wallet with a passphrase"
+    )]
+    EncryptWallet,
+    #[structopt(
+        name = "walletpassphrase",
+        about = "Unlock the wallet of a running node for a number of seconds"
+    )]
+    WalletPassphrase {
+        #[structopt(name = "timeout", help = "Seconds before the wallet locks again")]
+        timeout: u64,
+        #[structopt(long = "node", default_value = CENTRAL_NODE, help = "Node to unlock")]
+        node: String,
+    },
+    #[structopt(
+        name = "walletlock",
+        about = "Lock the wallet of a running node before its timeout"
+    )]
+    WalletLock {
+        #[structopt(long = "node", default_value = CENTRAL_NODE, help = "Node to lock")]
+        node: String,
+    },
+    #[structopt(name = "changepassphrase", about = "Change the wallet passphrase")]
+    ChangePassphrase,
+    #[structopt(
+        name = "newmnemonic",
+        about = "Derive new addresses from the seed of a fresh mnemonic"
+    )]
+    NewMnemonic {
+        #[structopt(
+            long = "words",
+            default_value = "12",
+            help = "Number of words: 12, 15, 18, 21 or 24"
+        )]
+        words: usize,
+        #[structopt(
+            long = "mnemonic-passphrase",
+            default_value = "",
+            help = "Passphrase extending the mnemonic, needed to restore it"
+        )]
+        mnemonic_passphrase: String,
+    },
+    #[structopt(
+        name = "restoremnemonic",
+        about = "Restore the seed of a mnemonic and recover its used addresses"
+    )]
+    RestoreMnemonic {
+        #[structopt(name = "mnemonic", help = "The words of the mnemonic, quoted")]
+        mnemonic: String,
+        #[structopt(
+            long = "mnemonic-passphrase",
+            default_value = "",
+            help = "Passphrase extending the mnemonic"
+        )]
+        mnemonic_passphrase: String,
+        #[structopt(
+            long = "gap-limit",
+            default_value = "20",
+            help = "Unused addresses to look at past the last used one"
+        )]
+        gap_limit: u32,
+    },
     #[structopt(name = "send", about = "Add new block to chain")]
     Send {
         #[structopt(name = "from", help = "Source wallet address")]
@@ -44,6 +123,315 @@ enum Command {
         amount: i32,
         #[structopt(name = "mine", help = "Mine immediately on the same node")]
         mine: usize,
+        #[structopt(long = "fee", default_value = "1", help = "Fee paid to the miner")]
+        fee: i32,
+        #[structopt(long = "rbf", help = "Allow the transaction to be replaced by fee")]
+        rbf: bool,
+        #[structopt(
+            long = "lock-time",
+            default_value = "0",
+            help = "Height, or Unix time from 500000000 on, before which it cannot be mined"
+        )]
+        lock_time: u32,
+        #[structopt(
+            long = "coin-selection",
+            default_value = "bnb",
+            help = "How to choose the coins spent: bnb, largest-first, random-improve or consolidate"
+        )]
+        coin_selection: String,
+    },
+    #[structopt(
+        name = "sendmany",
+        about = "Pay every address listed in a JSON or CSV file in one transaction"
+    )]
+    SendMany {
+        #[structopt(name = "from", help = "Source wallet address, which gets the change")]
+        from: String,
+        #[structopt(name = "file", help = "Payments as address and amount pairs")]
+        file: String,
+        #[structopt(long = "fee", help = "Total fee, estimated from the size if not given")]
+        fee: Option<i32>,
+        #[structopt(
+            long = "fee-rate",
+            help = "Fee per 1000 bytes to estimate with, defaults to MIN_RELAY_FEE"
+        )]
+        fee_rate: Option<i64>,
+        #[structopt(long = "rbf", help = "Allow the transaction to be replaced by fee")]
+        rbf: bool,
+        #[structopt(long = "mine", help = "Mine immediately on the same node")]
+        mine: bool,
+        #[structopt(long = "node", default_value = CENTRAL_NODE, help = "Node to send to")]
+        node: String,
+    },
+    #[structopt(
+        name = "bumpfee",
+        about = "Replace an unconfirmed transaction with a higher fee"
+    )]
+    BumpFee {
+        #[structopt(name = "txid", help = "Id of the transaction to replace")]
+        txid: String,
+        #[structopt(
+            long = "fee",
+            help = "New total fee, defaults to one more coin per 1000 bytes"
+        )]
+        fee: Option<i32>,
+        #[structopt(long = "node", default_value = CENTRAL_NODE, help = "Node to send it to")]
+        node: String,
+    },
+    #[structopt(
+        name = "createmultisig",
+        about = "Create a pay to script hash address needing M of N signatures"
+    )]
+    CreateMultisig {
+        #[structopt(name = "required", help = "Signatures needed to spend")]
+        required: usize,
+        #[structopt(
+            name = "pubkeys",
+            required = true,
+            help = "Hex public keys of the signers"
+        )]
+        pub_keys: Vec<String>,
+    },
+    #[structopt(
+        name = "signpartial",
+        about = "Sign a partial transaction, or start a multisig spend with --redeem-script"
+    )]
+    SignPartial {
+        #[structopt(name = "file", help = "Partial transaction file, updated in place")]
+        file: String,
+        #[structopt(name = "signer", help = "Wallet address of the signer")]
+        signer: String,
+        #[structopt(long = "redeem-script", help = "Start a new spend from this script")]
+        redeem_script: Option<String>,
+        #[structopt(long = "to", help = "Destination of a new spend")]
+        to: Option<String>,
+        #[structopt(long = "amount", help = "Amount of a new spend")]
+        amount: Option<i32>,
+        #[structopt(long = "fee", default_value = "1", help = "Fee of a new spend")]
+        fee: i32,
+    },
+    #[structopt(
+        name = "createunsigned",
+        about = "Build a payment from an address for a wallet on another machine to sign"
+    )]
+    CreateUnsigned {
+        #[structopt(name = "from", help = "Source address, which gets the change")]
+        from: String,
+        #[structopt(name = "to", help = "Destination wallet address")]
+        to: String,
+        #[structopt(name = "amount", help = "Amount to send")]
+        amount: i32,
+        #[structopt(name = "file", help = "Where to write the partial transaction")]
+        file: String,
+        #[structopt(long = "fee", default_value = "1", help = "Fee paid to the miner")]
+        fee: i32,
+        #[structopt(
+            long = "coin-selection",
+            default_value = "bnb",
+            help = "How to choose the coins spent: bnb, largest-first, random-improve or consolidate"
+        )]
+        coin_selection: String,
+    },
+    #[structopt(
+        name = "decodepartial",
+        about = "Show what a partial transaction spends and pays before signing it"
+    )]
+    DecodePartial {
+        #[structopt(name = "file", help = "Partial transaction file")]
+        file: String,
+    },
+    #[structopt(name = "broadcast", about = "Send a fully signed partial transaction")]
+    Broadcast {
+        #[structopt(name = "file", help = "Partial transaction file")]
+        file: String,
+        #[structopt(long = "node", default_value = CENTRAL_NODE, help = "Node to send it to")]
+        node: String,
+    },
+    #[structopt(
+        name = "createrawtransaction",
+        about = "Build an unsigned transaction and print it as hex"
+    )]
+    CreateRawTransaction {
+        #[structopt(long = "input", required = true, help = "Output spent, as txid:vout")]
+        inputs: Vec<String>,
+        #[structopt(long = "output", required = true, help = "Payment, as address:amount")]
+        outputs: Vec<String>,
+        #[structopt(
+            long = "lock-time",
+            default_value = "0",
+            help = "Height, or Unix time from 500000000 on, before which it cannot be mined"
+        )]
+        lock_time: u32,
+        #[structopt(long = "rbf", help = "Allow the transaction to be replaced by fee")]
+        rbf: bool,
+    },
+    #[structopt(
+        name = "decoderawtransaction",
+        about = "Show a hex transaction as JSON"
+    )]
+    DecodeRawTransaction {
+        #[structopt(name = "hex", help = "Hex-encoded transaction")]
+        hex: String,
+    },
+    #[structopt(
+        name = "signrawtransaction",
+        about = "Sign the inputs of a hex transaction spending from local wallets"
+    )]
+    SignRawTransaction {
+        #[structopt(name = "hex", help = "Hex-encoded transaction")]
+        hex: String,
+        #[structopt(
+            long = "sighash",
+            default_value = "ALL",
+            help = "What the signatures commit to: ALL, NONE or SINGLE, with an optional |ANYONECANPAY"
+        )]
+        sighash: String,
+    },
+    #[structopt(name = "sendrawtransaction", about = "Send a signed hex transaction")]
+    SendRawTransaction {
+        #[structopt(name = "hex", help = "Hex-encoded transaction")]
+        hex: String,
+        #[structopt(long = "node", default_value = CENTRAL_NODE, help = "Node to send it to")]
+        node: String,
+    },
+    #[structopt(
+        name = "combine",
+        about = "Merge signed copies of a spend and send it once complete"
+    )]
+    Combine {
+        #[structopt(name = "files", required = true, help = "Partial transaction files")]
+        files: Vec<String>,
+        #[structopt(long = "out", help = "Where to write the merged copy if incomplete")]
+        out: Option<String>,
+        #[structopt(long = "node", default_value = CENTRAL_NODE, help = "Node to send it to")]
+        node: String,
+    },
+    #[structopt(
+        name = "initiate",
+        about = "Start an atomic swap by locking coins to a new secret"
+    )]
+    Initiate {
+        #[structopt(name = "from", help = "Source wallet address, refunded on timeout")]
+        from: String,
+        #[structopt(
+            name = "participant",
+            help = "Address of the participant on this chain"
+        )]
+        participant: String,
+        #[structopt(name = "amount", help = "Amount to lock")]
+        amount: i32,
+        #[structopt(
+            long = "timeout",
+            default_value = "172800",
+            help = "Seconds before the coins can be refunded"
+        )]
+        timeout: u32,
+        #[structopt(flatten)]
+        publish: PublishOpt,
+    },
+    #[structopt(
+        name = "auditcontract",
+        about = "Check an initiated swap contract before participating"
+    )]
+    AuditContract {
+        #[structopt(name = "contract", help = "Hex contract script")]
+        contract: String,
+        #[structopt(name = "contract_txid", help = "Id of the transaction funding it")]
+        contract_txid: String,
+        #[structopt(name = "file", help = "Where to write the audit for participate")]
+        file: String,
+    },
+    #[structopt(
+        name = "participate",
+        about = "Lock coins to the secret hash of an audited swap contract"
+    )]
+    Participate {
+        #[structopt(name = "from", help = "Source wallet address, refunded on timeout")]
+        from: String,
+        #[structopt(name = "initiator", help = "Address of the initiator on this chain")]
+        initiator: String,
+        #[structopt(name = "amount", help = "Amount to lock")]
+        amount: i32,
+        #[structopt(name = "audit", help = "Audit of the initiator's contract")]
+        audit: String,
+        #[structopt(
+            long = "expect-amount",
+            help = "Amount the initiator's contract must lock"
+        )]
+        expect_amount: i32,
+        #[structopt(
+            long = "min-confirmations",
+            default_value = "1",
+            help = "Blocks the initiator's contract must be buried under"
+        )]
+        min_confirmations: usize,
+        #[structopt(
+            long = "timeout",
+            default_value = "86400",
+            help = "Seconds before the coins can be refunded, shorter than the initiator's"
+        )]
+        timeout: u32,
+        #[structopt(flatten)]
+        publish: PublishOpt,
+    },
+    #[structopt(
+        name = "redeem",
+        about = "Take the coins of a swap contract with its secret"
+    )]
+    Redeem {
+        #[structopt(name = "contract", help = "Hex contract script")]
+        contract: String,
+        #[structopt(name = "contract_txid", help = "Id of the transaction funding it")]
+        contract_txid: String,
+        #[structopt(name = "secret", help = "Hex secret")]
+        secret: String,
+        #[structopt(flatten)]
+        publish: PublishOpt,
+    },
+    #[structopt(
+        name = "refund",
+        about = "Take back the coins of a timed out swap contract"
+    )]
+    Refund {
+        #[structopt(name = "contract", help = "Hex contract script")]
+        contract: String,
+        #[structopt(name = "contract_txid", help = "Id of the transaction funding it")]
+        contract_txid: String,
+        #[structopt(flatten)]
+        publish: PublishOpt,
+    },
+    #[structopt(
+        name = "extractsecret",
+        about = "Find the secret revealed by the redemption of a swap contract"
+    )]
+    ExtractSecret {
+        #[structopt(name = "contract", help = "Hex contract script")]
+        contract: String,
+    },
+    #[structopt(
+        name = "timestamp",
+        about = "Anchor the hash of a file on chain in a data output"
+    )]
+    Timestamp {
+        #[structopt(name = "file", help = "File to timestamp")]
+        file: String,
+        #[structopt(name = "from", help = "Wallet address paying the fee")]
+        from: String,
+        #[structopt(flatten)]
+        publish: PublishOpt,
+    },
+    #[structopt(
+        name = "verifytimestamp",
+        about = "Find the block anchoring the hash of a file and prove it"
+    )]
+    VerifyTimestamp {
+        #[structopt(name = "file", help = "File to look for")]
+        file: String,
+    },
+    #[structopt(name = "getmininginfo", about = "Show mining statistics of a node")]
+    GetMiningInfo {
+        #[structopt(long = "node", default_value = CENTRAL_NODE, help = "Node to query")]
+        node: String,
     },
     #[structopt(name = "printchain", about = "Print blockchain all block")]
     Printchain,
@@ -56,12 +444,26 @@ enum Command {
     },
 }
 
+/// Where the transactions of the swap and timestamp commands go.
+#[derive(StructOpt, Debug)]
+struct PublishOpt {
+    #[structopt(long = "fee", default_value = "1", help = "Fee paid to the miner")]
+    fee: i32,
+    #[structopt(long = "mine", help = "Mine immediately on the same node")]
+    mine: bool,
+    #[structopt(long = "node", default_value = CENTRAL_NODE, help = "Node to send to")]
+    node: String,
+}
+
 fn main() {
     env_logger::Builder::new()
         .filter(None, LevelFilter::Info)
         .init();
 
     let opt = Opt::from_args();
+    if opt.command.signs() {
+        unlock_for_signing();
+    }
 
     match opt.command {
         Command::Createblockchain { address } => {
@@ -72,7 +474,9 @@ fn main() {
         }
         Command::Createwallet => {
             let mut wallet = Wallets::new();
-            let address = wallet.create_wallet();
+            let address = wallet
+                .create_wallet()
+                .unwrap_or_else(|err| panic!("ERROR: {}", err));
             println!("Your new address: {}", address)
         }
         Command::GetBalance { address } => {
@@ -80,14 +484,13 @@ fn main() {
             if address_valid == false {
                 panic!("ERROR: Address is not valid")
             }
-            let payload = utils::base58_decode(address.as_str());
-            let pub_key_hash = &payload[1..payload.len() - ADDRESS_CHECK_SUM_LEN];
+            let lock = Script::for_address(address.as_str());
 
             let blockchain = Blockchain::new_blockchain();
             let utxo_set = UTXOSet::new(blockchain);
-            let utxos = utxo_set.find_utxo(pub_key_hash);
+            let utxos = utxo_set.find_outputs_locked_by(&lock);
             let mut balance = 0;
-            for utxo in utxos {
+            for (_, _, utxo) in utxos {
                 balance += utxo.get_value();
             }
             println!("Balance of {}: {}", address, balance);
@@ -98,11 +501,89 @@ fn main() {
                 println!("{}", address)
             }
         }
+        Command::GetPubKey { address } => {
+            let wallets = Wallets::new();
+            let public_key = wallets
+                .get_public_key(address.as_str())
+                .expect("ERROR: No wallet for this address");
+            println!("{}", HEXLOWER.encode(public_key));
+        }
+        Command::EncryptWallet => {
+            let passphrase = read_passphrase("New passphrase");
+            let mut wallets = Wallets::new();
+            wallets
+                .encrypt(passphrase.as_str())
+                .unwrap_or_else(|err| panic!("ERROR: {}", err));
+            println!("Wallet encrypted and locked");
+        }
+        Command::WalletPassphrase { timeout, node } => {
+            let passphrase = read_passphrase("Passphrase");
+            if let Err(reason) = wallet_passphrase(node.as_str(), passphrase.as_str(), timeout) {
+                panic!("ERROR: {}", reason)
+            }
+            println!("Wallet of {} unlocked for {} seconds", node, timeout);
+        }
+        Command::WalletLock { node } => {
+            if let Err(reason) = wallet_lock(node.as_str()) {
+                panic!("ERROR: {}", reason)
+            }
+            println!("Wallet of {} locked", node);
+        }
+        Command::ChangePassphrase => {
+            let old = read_passphrase("Current passphrase");
+            let new = read_passphrase("New passphrase");
+            let mut wallets = Wallets::new();
+            wallets
+                .change_passphrase(old.as_str(), new.as_str())
+                .unwrap_or_else(|err| panic!("ERROR: {}", err));
+            println!("Passphrase changed, the wallet is locked");
+        }
+        Command::NewMnemonic {
+            words,
+            mnemonic_passphrase,
+        } => {
+            let mnemonic = Mnemonic::generate(words).unwrap_or_else(|err| panic!("ERROR: {}", err));
+            let mut wallets = Wallets::new();
+            wallets
+                .set_hd_seed(mnemonic.to_seed(mnemonic_passphrase.as_str()))
+                .unwrap_or_else(|err| panic!("ERROR: {}", err));
+            println!("Your mnemonic: {}", mnemonic.get_phrase());
+            println!("Write it down, it restores every address created from now on");
+            let random = wallets.get_addresses().len();
+            if random > 0 {
+                println!("The {} existing addresses are not covered by it", random);
+            }
+        }
+        Command::RestoreMnemonic {
+            mnemonic,
+            mnemonic_passphrase,
+            gap_limit,
+        } => {
+            let mnemonic = Mnemonic::from_phrase(mnemonic.as_str())
+                .unwrap_or_else(|err| panic!("ERROR: {}", err));
+            let used = Blockchain::new_blockchain().find_used_addresses();
+            let mut wallets = Wallets::new();
+            let addresses = wallets
+                .recover_hd_seed(
+                    mnemonic.to_seed(mnemonic_passphrase.as_str()),
+                    gap_limit,
+                    |address| used.contains(address),
+                )
+                .unwrap_or_else(|err| panic!("ERROR: {}", err));
+            for address in &addresses {
+                println!("{}", address);
+            }
+            println!("Recovered {} addresses", addresses.len());
+        }
         Command::Send {
             from,
             to,
             amount,
             mine,
+            fee,
+            rbf,
+            lock_time,
+            coin_selection,
         } => {
             if !validate_address(from.as_str()) {
                 panic!("ERROR: Sender address is not valid")
@@ -110,23 +591,518 @@ fn main() {
             if !validate_address(to.as_str()) {
                 panic!("ERROR: Recipient address is not valid")
             }
+            let strategy =
+                coin_selection::from_name(coin_selection.as_str()).unwrap_or_else(|| {
+                    panic!(
+                        "ERROR: Unknown coin selection {}, use one of {}",
+                        coin_selection,
+                        coin_selection::names().join(", ")
+                    )
+                });
             let blockchain = Blockchain::new_blockchain();
             let utxo_set = UTXOSet::new(blockchain.clone());
 
-            let transaction =
-                Transaction::new_utxo_transaction(from.as_str(), to.as_str(), amount, &utxo_set);
-
-            if mine == MINE_TRUE {
-                let coinbase_tx = Transaction::new_coinbase_tx(from.as_str());
+            let coins = utxo_set.find_coins(from.as_str());
+            let selection = Selection::new(strategy, coins.as_slice(), amount, fee)
+                .expect("ERROR: Not enough funds");
+            println!("Coin selection: {}", strategy.name());
+            println!("{}", selection);
+            let transaction = Transaction::new_selected_transaction(
+                from.as_str(),
+                to.as_str(),
+                amount,
+                &selection,
+                rbf,
+                lock_time,
+            )
+            .unwrap_or_else(|err| panic!("ERROR: {}", err));
+            let change = transaction.get_change_index(1);
+            publish_transaction(
+                &utxo_set,
+                transaction,
+                change,
+                from.as_str(),
+                mine == MINE_TRUE,
+                CENTRAL_NODE,
+            );
+            println!("Success!")
+        }
+        Command::SendMany {
+            from,
+            file,
+            fee,
+            fee_rate,
+            rbf,
+            mine,
+            node,
+        } => {
+            if !validate_address(from.as_str()) {
+                panic!("ERROR: Sender address is not valid")
+            }
+            let contents = fs::read_to_string(file.as_str()).expect("Unable to read the payments");
+            let payments =
+                parse_payments(contents.as_str()).unwrap_or_else(|err| panic!("ERROR: {}", err));
+            let utxo_set = UTXOSet::new(Blockchain::new_blockchain());
+            let transaction = match fee {
+                Some(fee) => Transaction::new_batch_transaction(
+                    from.as_str(),
+                    payments.as_slice(),
+                    fee,
+                    rbf,
+                    &utxo_set,
+                ),
+                None => Transaction::new_batch_transaction_at_rate(
+                    from.as_str(),
+                    payments.as_slice(),
+                    fee_rate.unwrap_or_else(|| GLOBAL_CONFIG.get_min_relay_fee()),
+                    rbf,
+                    &utxo_set,
+                ),
+            }
+            .unwrap_or_else(|err| panic!("ERROR: {}", err));
+            let prev_outputs: Vec<_> = transaction
+                .get_vin()
+                .iter()
+                .map(|vin| {
+                    utxo_set
+                        .find_output(vin.get_txid(), vin.get_vout())
+                        .unwrap()
+                })
+                .collect();
+            let total: i32 = payments.iter().map(|(_, amount)| amount).sum();
+            println!("Payments: {}", payments.len());
+            println!("Total: {}", total);
+            println!("Size: {} bytes", transaction.get_size());
+            println!("Fee: {}", transaction.get_fee(prev_outputs.as_slice()));
+            let change = transaction.get_change_index(payments.len());
+            publish_transaction(
+                &utxo_set,
+                transaction,
+                change,
+                from.as_str(),
+                mine,
+                node.as_str(),
+            );
+        }
+        Command::BumpFee { txid, fee, node } => {
+            let blockchain = Blockchain::new_blockchain();
+            let utxo_set = UTXOSet::new(blockchain.clone());
+            let wallet_txs = WalletTransactions::new(blockchain);
 
-                let block = blockchain.mine_block(&vec![transaction, coinbase_tx]);
+            let txid = HEXLOWER.decode(txid.as_bytes()).expect("Invalid txid");
+            let original = wallet_txs
+                .get(txid.as_slice())
+                .expect("Transaction not found");
+            if !original.signals_rbf() {
+                panic!("ERROR: Transaction does not signal replace-by-fee")
+            }
 
-                utxo_set.update(&block);
-            } else {
-                send_tx(CENTRAL_NODE, &transaction);
+            let change = wallet_txs.get_change(txid.as_slice());
+            let replacement =
+                Transaction::new_fee_bump_transaction(&original, change, fee, &utxo_set)
+                    .unwrap_or_else(|err| panic!("ERROR: {}", err));
+            send_transaction(node.as_str(), &replacement);
+            wallet_txs.add(&replacement, change);
+            println!(
+                "Replacement txid: {}",
+                HEXLOWER.encode(replacement.get_id())
+            );
+        }
+        Command::CreateMultisig { required, pub_keys } => {
+            let pub_keys: Vec<Vec<u8>> = pub_keys
+                .iter()
+                .map(|key| HEXLOWER.decode(key.as_bytes()).expect("Invalid public key"))
+                .collect();
+            let redeem_script = Script::new_multisig(required, pub_keys.as_slice());
+            println!(
+                "Address: {}",
+                convert_script_address(redeem_script.hash().as_slice())
+            );
+            println!(
+                "Redeem script: {}",
+                HEXLOWER.encode(redeem_script.serialize().as_slice())
+            );
+        }
+        Command::SignPartial {
+            file,
+            signer,
+            redeem_script,
+            to,
+            amount,
+            fee,
+        } => {
+            let wallets = Wallets::new();
+            let wallet = wallets
+                .get_signing_wallet(signer.as_str())
+                .unwrap_or_else(|err| panic!("ERROR: {}", err));
+            let mut partial = match redeem_script {
+                Some(redeem_script) => {
+                    let bytes = HEXLOWER
+                        .decode(redeem_script.as_bytes())
+                        .expect("Invalid redeem script");
+                    let redeem_script =
+                        Script::deserialize(bytes.as_slice()).expect("Invalid redeem script");
+                    let to = to.expect("ERROR: A new spend needs --to");
+                    if !validate_address(to.as_str()) {
+                        panic!("ERROR: Recipient address is not valid")
+                    }
+                    let amount = amount.expect("ERROR: A new spend needs --amount");
+                    let utxo_set = UTXOSet::new(Blockchain::new_blockchain());
+                    PartialTransaction::new_multisig_spend(
+                        redeem_script,
+                        to.as_str(),
+                        amount,
+                        fee,
+                        &utxo_set,
+                    )
+                }
+                None => read_partial(file.as_str()),
+            };
+            match partial.sign(wallet) {
+                Ok(inputs) => println!("Signed {} inputs", inputs),
+                Err(err) => panic!("ERROR: {}", err),
             }
-            println!("Success!")
+            fs::write(file.as_str(), partial.serialize())
+                .expect("Unable to write the partial transaction");
+            println!(
+                "{} signatures still missing",
+                partial.get_missing_signatures()
+            );
+        }
+        Command::CreateUnsigned {
+            from,
+            to,
+            amount,
+            file,
+            fee,
+            coin_selection,
+        } => {
+            if !validate_address(from.as_str()) {
+                panic!("ERROR: Sender address is not valid")
+            }
+            if !validate_address(to.as_str()) {
+                panic!("ERROR: Recipient address is not valid")
+            }
+            let strategy =
+                coin_selection::from_name(coin_selection.as_str()).unwrap_or_else(|| {
+                    panic!(
+                        "ERROR: Unknown coin selection {}, use one of {}",
+                        coin_selection,
+                        coin_selection::names().join(", ")
+                    )
+                });
+            let utxo_set = UTXOSet::new(Blockchain::new_blockchain());
+            let coins = utxo_set.find_coins(from.as_str());
+            let selection = Selection::new(strategy, coins.as_slice(), amount, fee)
+                .expect("ERROR: Not enough funds");
+            let partial =
+                PartialTransaction::new_spend(from.as_str(), to.as_str(), amount, &selection);
+            fs::write(file.as_str(), partial.serialize())
+                .expect("Unable to write the partial transaction");
+            println!("{}", partial);
+        }
+        Command::DecodePartial { file } => {
+            let partial = read_partial(file.as_str());
+            println!("{}", partial);
+            println!(
+                "{} signatures still missing",
+                partial.get_missing_signatures()
+            );
+        }
+        Command::Broadcast { file, node } => match read_partial(file.as_str()).finalize() {
+            Ok(transaction) => {
+                send_transaction(node.as_str(), &transaction);
+                println!("Sent txid: {}", HEXLOWER.encode(transaction.get_id()));
+            }
+            Err(err) => panic!("ERROR: {}", err),
+        },
+        Command::CreateRawTransaction {
+            inputs,
+            outputs,
+            lock_time,
+            rbf,
+        } => match create_raw_transaction(inputs.as_slice(), outputs.as_slice(), lock_time, rbf) {
+            Ok(transaction) => println!("{}", encode_raw_transaction(&transaction)),
+            Err(err) => panic!("ERROR: {}", err),
+        },
+        Command::DecodeRawTransaction { hex } => {
+            let transaction = read_raw_transaction(hex.as_str());
+            let json = raw_transaction_to_json(&transaction);
+            println!("{}", serde_json::to_string_pretty(&json).unwrap());
+        }
+        Command::SignRawTransaction { hex, sighash } => {
+            let hash_type = SigHashType::from_name(sighash.as_str())
+                .unwrap_or_else(|| panic!("ERROR: Unknown signature hash type {}", sighash));
+            let mut transaction = read_raw_transaction(hex.as_str());
+            let utxo_set = UTXOSet::new(Blockchain::new_blockchain());
+            let prev_outputs: Vec<_> = transaction
+                .get_vin()
+                .iter()
+                .map(|vin| {
+                    utxo_set
+                        .find_output(vin.get_txid(), vin.get_vout())
+                        .unwrap_or_else(|| {
+                            panic!(
+                                "ERROR: {}:{} is not an unspent output",
+                                HEXLOWER.encode(vin.get_txid()),
+                                vin.get_vout()
+                            )
+                        })
+                })
+                .collect();
+            let wallets = Wallets::new();
+            if wallets.is_locked() {
+                panic!("ERROR: {}", WalletError::Locked)
+            }
+            let signers: Vec<_> = wallets
+                .get_addresses()
+                .iter()
+                .filter_map(|address| wallets.get_wallet(address.as_str()))
+                .collect();
+            let complete = sign_raw_transaction(
+                &mut transaction,
+                prev_outputs.as_slice(),
+                signers.as_slice(),
+                hash_type,
+            );
+            println!("{}", encode_raw_transaction(&transaction));
+            println!("Complete: {}", complete);
+        }
+        Command::SendRawTransaction { hex, node } => {
+            let transaction = read_raw_transaction(hex.as_str());
+            send_transaction(node.as_str(), &transaction);
+            println!("Sent txid: {}", HEXLOWER.encode(transaction.get_id()));
+        }
+        Command::Combine { files, out, node } => {
+            let mut partials = files.iter().map(|file| read_partial(file.as_str()));
+            let mut combined = partials.next().unwrap();
+            for partial in partials {
+                if let Err(err) = combined.combine(&partial) {
+                    panic!("ERROR: {}", err)
+                }
+            }
+            match combined.finalize() {
+                Ok(transaction) => {
+                    send_transaction(node.as_str(), &transaction);
+                    println!("Sent txid: {}", HEXLOWER.encode(transaction.get_id()));
+                }
+                Err(PartialError::Incomplete { missing }) => {
+                    if let Some(out) = out {
+                        fs::write(out.as_str(), combined.serialize())
+                            .expect("Unable to write the partial transaction");
+                    }
+                    println!("{} signatures still missing", missing);
+                }
+                Err(err) => panic!("ERROR: {}", err),
+            }
+        }
+        Command::Initiate {
+            from,
+            participant,
+            amount,
+            timeout,
+            publish,
+        } => {
+            let secret = new_secret();
+            let htlc = Htlc::new(
+                sha256_digest(secret.as_slice()).as_slice(),
+                address_key_hash(participant.as_str()).as_slice(),
+                swap_lock_time(timeout),
+                address_key_hash(from.as_str()).as_slice(),
+            );
+            println!("Secret: {}", HEXLOWER.encode(secret.as_slice()));
+            fund_contract(from.as_str(), &htlc, amount, &publish);
+        }
+        Command::AuditContract {
+            contract,
+            contract_txid,
+            file,
+        } => {
+            let htlc = parse_contract(contract.as_str());
+            let contract_txid = HEXLOWER
+                .decode(contract_txid.as_bytes())
+                .expect("Invalid txid");
+            let utxo_set = UTXOSet::new(Blockchain::new_blockchain());
+            let audit = htlc
+                .audit(contract_txid.as_slice(), &utxo_set)
+                .unwrap_or_else(|err| panic!("ERROR: {}", err));
+            println!("Contract address: {}", htlc.get_address());
+            println!("Amount: {}", audit.get_amount());
+            println!("Confirmations: {}", audit.get_confirmations());
+            println!("Recipient: {}", htlc.get_recipient_address());
+            println!("Refund: {}", htlc.get_refund_address());
+            println!("Lock time: {}", htlc.get_lock_time());
+            println!("Secret hash: {}", HEXLOWER.encode(htlc.get_secret_hash()));
+            if !Wallets::new()
+                .get_addresses()
+                .contains(&htlc.get_recipient_address())
+            {
+                panic!("ERROR: The contract does not pay to a key of this wallet")
+            }
+            fs::write(file.as_str(), audit.serialize()).expect("Unable to write the audit");
+        }
+        Command::Participate {
+            from,
+            initiator,
+            amount,
+            audit,
+            expect_amount,
+            min_confirmations,
+            timeout,
+            publish,
+        } => {
+            let bytes = fs::read(audit.as_str()).expect("Unable to read the audit");
+            let audit = ContractAudit::deserialize(bytes.as_slice())
+                .unwrap_or_else(|| panic!("ERROR: {} is not a contract audit", audit));
+            let lock_time = swap_lock_time(timeout);
+            audit
+                .check_participation(expect_amount, min_confirmations, lock_time)
+                .unwrap_or_else(|err| panic!("ERROR: {}", err));
+            let htlc = Htlc::new(
+                audit.get_htlc().get_secret_hash(),
+                address_key_hash(initiator.as_str()).as_slice(),
+                lock_time,
+                address_key_hash(from.as_str()).as_slice(),
+            );
+            fund_contract(from.as_str(), &htlc, amount, &publish);
+        }
+        Command::Redeem {
+            contract,
+            contract_txid,
+            secret,
+            publish,
+        } => {
+            let htlc = parse_contract(contract.as_str());
+            let contract_txid = HEXLOWER
+                .decode(contract_txid.as_bytes())
+                .expect("Invalid txid");
+            let secret = HEXLOWER.decode(secret.as_bytes()).expect("Invalid secret");
+            let recipient = htlc.get_recipient_address();
+            let wallets = Wallets::new();
+            let wallet = wallets
+                .get_signing_wallet(recipient.as_str())
+                .unwrap_or_else(|err| panic!("ERROR: {}", err));
+            let blockchain = Blockchain::new_blockchain();
+            let utxo_set = UTXOSet::new(blockchain.clone());
+            let transaction = htlc
+                .new_redeem_transaction(
+                    contract_txid.as_slice(),
+                    secret.as_slice(),
+                    wallet,
+                    publish.fee,
+                    &utxo_set,
+                )
+                .unwrap_or_else(|err| panic!("ERROR: {}", err));
+            publish_transaction(
+                &utxo_set,
+                transaction,
+                None,
+                recipient.as_str(),
+                publish.mine,
+                publish.node.as_str(),
+            );
+        }
+        Command::Refund {
+            contract,
+            contract_txid,
+            publish,
+        } => {
+            let htlc = parse_contract(contract.as_str());
+            let contract_txid = HEXLOWER
+                .decode(contract_txid.as_bytes())
+                .expect("Invalid txid");
+            let refund = htlc.get_refund_address();
+            let wallets = Wallets::new();
+            let wallet = wallets
+                .get_signing_wallet(refund.as_str())
+                .unwrap_or_else(|err| panic!("ERROR: {}", err));
+            let utxo_set = UTXOSet::new(Blockchain::new_blockchain());
+            let transaction = htlc
+                .new_refund_transaction(contract_txid.as_slice(), wallet, publish.fee, &utxo_set)
+                .unwrap_or_else(|err| panic!("ERROR: {}", err));
+            publish_transaction(
+                &utxo_set,
+                transaction,
+                None,
+                refund.as_str(),
+                publish.mine,
+                publish.node.as_str(),
+            );
         }
+        Command::ExtractSecret { contract } => {
+            let htlc = parse_contract(contract.as_str());
+            match htlc.find_secret(&Blockchain::new_blockchain()) {
+                Some(secret) => println!("Secret: {}", HEXLOWER.encode(secret.as_slice())),
+                None => panic!("ERROR: The contract has not been redeemed"),
+            }
+        }
+        Command::Timestamp {
+            file,
+            from,
+            publish,
+        } => {
+            if !validate_address(from.as_str()) {
+                panic!("ERROR: Sender address is not valid")
+            }
+            let hash = sha256_digest(
+                fs::read(file.as_str())
+                    .expect("Unable to read the file")
+                    .as_slice(),
+            );
+            let utxo_set = UTXOSet::new(Blockchain::new_blockchain());
+            let transaction = Transaction::new_data_transaction(
+                from.as_str(),
+                hash.as_slice(),
+                publish.fee,
+                &utxo_set,
+            )
+            .unwrap_or_else(|err| panic!("ERROR: {}", err));
+            println!("File hash: {}", HEXLOWER.encode(hash.as_slice()));
+            let change = transaction.get_change_index(1);
+            publish_transaction(
+                &utxo_set,
+                transaction,
+                change,
+                from.as_str(),
+                publish.mine,
+                publish.node.as_str(),
+            );
+        }
+        Command::VerifyTimestamp { file } => {
+            let hash = sha256_digest(
+                fs::read(file.as_str())
+                    .expect("Unable to read the file")
+                    .as_slice(),
+            );
+            println!("File hash: {}", HEXLOWER.encode(hash.as_slice()));
+            let Some((block, tx)) = Blockchain::new_blockchain().find_data(hash.as_slice()) else {
+                panic!("ERROR: The file hash is not anchored on chain")
+            };
+            let proof = block.merkle_proof(tx.get_id()).unwrap();
+            let merkle_root = block.hash_transactions();
+            println!("Txid: {}", HEXLOWER.encode(tx.get_id()));
+            println!("Wtxid: {}", HEXLOWER.encode(tx.get_wtxid().as_slice()));
+            println!("Block hash: {}", block.get_hash());
+            println!("Block height: {}", block.get_height());
+            println!("Block timestamp: {}", block.get_timestamp());
+            println!("Merkle root: {}", HEXLOWER.encode(merkle_root.as_slice()));
+            println!("Merkle index: {}", proof.get_index());
+            for sibling in proof.get_siblings() {
+                println!("Merkle sibling: {}", HEXLOWER.encode(sibling.as_slice()));
+            }
+            // The root commits to the transaction, and the sealed header to
+            // the root.
+            if !proof.verify(tx.get_wtxid().as_slice(), merkle_root.as_slice())
+                || !consensus::current().validate_seal(&block)
+            {
+                panic!("ERROR: The proof does not verify")
+            }
+            println!("Verified");
+        }
+        Command::GetMiningInfo { node } => match get_mining_info(node.as_str()) {
+            Some(info) => println!("{}", info),
+            None => panic!("ERROR: No answer from {}", node),
+        },
         Command::Printchain => {
             let mut block_iterator = Blockchain::new_blockchain().iterator();
             loop {
@@ -138,27 +1114,44 @@ fn main() {
                 println!("Pre block hash: {}", block.get_prev_block_hash());
                 println!("Cur block hash: {}", block.get_hash());
                 println!("Cur block Timestamp: {}", block.get_timestamp());
+                println!(
+                    "Cur block Size: {} bytes, weight: {}",
+                    block.get_size(),
+                    block.get_weight()
+                );
                 for tx in block.get_transactions() {
                     let cur_txid_hex = HEXLOWER.encode(tx.get_id());
                     println!("- Transaction txid_hex: {}", cur_txid_hex);
+                    println!(
+                        "-- Size: {} bytes, weight: {}",
+                        tx.get_size(),
+                        tx.get_weight()
+                    );
 
                     if tx.is_coinbase() == false {
                         for input in tx.get_vin() {
                             let txid_hex = HEXLOWER.encode(input.get_txid());
-                            let pub_key_hash = hash_pub_key(input.get_pub_key());
-                            let address = convert_address(pub_key_hash.as_slice());
                             println!(
-                                "-- Input txid = {}, vout = {}, from = {}",
+                                "-- Input txid = {}, vout = {}, script = {}",
                                 txid_hex,
                                 input.get_vout(),
-                                address,
+                                input.get_script_sig(),
                             )
                         }
                     }
                     for output in tx.get_vout() {
-                        let pub_key_hash = output.get_pub_key_hash();
-                        let address = convert_address(pub_key_hash);
-                        println!("-- Output value = {}, to = {}", output.get_value(), address,)
+                        match output.get_script_pubkey().get_address() {
+                            Some(address) => println!(
+                                "-- Output value = {}, to = {}",
+                                output.get_value(),
+                                address,
+                            ),
+                            None => println!(
+                                "-- Output value = {}, script = {}",
+                                output.get_value(),
+                                output.get_script_pubkey(),
+                            ),
+                        }
                     }
                 }
                 println!()
@@ -185,3 +1178,150 @@ fn main() {
         }
     }
 }
+
+fn read_partial(file: &str) -> PartialTransaction {
+    let bytes = fs::read(file).expect("Unable to read the partial transaction");
+    PartialTransaction::deserialize(bytes.as_slice())
+        .unwrap_or_else(|err| panic!("ERROR: {}: {}", file, err))
+}
+
+fn read_raw_transaction(hex: &str) -> Transaction {
+    decode_raw_transaction(hex).unwrap_or_else(|err| panic!("ERROR: {}", err))
+}
+
+/// Lock time of a swap contract timing out `timeout` seconds from now.
+fn swap_lock_time(timeout: u32) -> u32 {
+    (current_timestamp() / 1000) as u32 + timeout
+}
+
+fn address_key_hash(address: &str) -> Vec<u8> {
+    if !validate_address(address) {
+        panic!("ERROR: Address {} is not valid", address)
+    }
+    Script::for_address(address)
+        .get_pub_key_hash()
+        .expect("ERROR: Swaps need wallet addresses")
+        .to_vec()
+}
+
+fn parse_contract(contract: &str) -> Htlc {
+    let bytes = HEXLOWER
+        .decode(contract.as_bytes())
+        .expect("Invalid contract");
+    Script::deserialize(bytes.as_slice())
+        .as_ref()
+        .and_then(Htlc::from_script)
+        .expect("ERROR: Not a swap contract")
+}
+
+/// Pays `amount` from `from` to the contract and prints what the other
+/// side of the swap needs to check and redeem it.
+fn fund_contract(from: &str, htlc: &Htlc, amount: i32, publish: &PublishOpt) {
+    let blockchain = Blockchain::new_blockchain();
+    let utxo_set = UTXOSet::new(blockchain);
+    let transaction = Transaction::new_utxo_transaction(
+        from,
+        htlc.get_address().as_str(),
+        amount,
+        publish.fee,
+        false,
+        0,
+        &utxo_set,
+    )
+    .unwrap_or_else(|err| panic!("ERROR: {}", err));
+    println!("Secret hash: {}", HEXLOWER.encode(htlc.get_secret_hash()));
+    println!(
+        "Contract: {}",
+        HEXLOWER.encode(htlc.to_script().serialize().as_slice())
+    );
+    println!("Contract address: {}", htlc.get_address());
+    println!("Lock time: {}", htlc.get_lock_time());
+    let change = transaction.get_change_index(1);
+    publish_transaction(
+        &utxo_set,
+        transaction,
+        change,
+        from,
+        publish.mine,
+        publish.node.as_str(),
+    );
+}
+
+/// Sends `transaction` to `node`, or with `mine` checks it as a node would
+/// and mines it right away, paying the reward to `miner`. The wallet keeps
+/// it along with the index of its `change` output.
+fn publish_transaction(
+    utxo_set: &UTXOSet,
+    transaction: Transaction,
+    change: Option<usize>,
+    miner: &str,
+    mine: bool,
+    node: &str,
+) {
+    let blockchain = utxo_set.get_blockchain();
+    if mine {
+        if let Err(reason) = MemoryPool::new().accept(transaction.clone(), utxo_set) {
+            panic!("ERROR: Transaction rejected: {}", reason)
+        }
+        let coinbase_tx = Transaction::new_coinbase_tx(miner);
+        if let Err(reason) = blockchain.mine_block(&[coinbase_tx, transaction.clone()]) {
+            panic!("ERROR: Block rejected: {}", reason)
+        }
+    } else {
+        send_transaction(node, &transaction);
+    }
+    WalletTransactions::new(blockchain.clone()).add(&transaction, change);
+    println!("Txid: {}", HEXLOWER.encode(transaction.get_id()));
+}
+
+impl Command {
+    fn signs(&self) -> bool {
+        matches!(
+            self,
+            Command::Createwallet
+                | Command::NewMnemonic { .. }
+                | Command::RestoreMnemonic { .. }
+                | Command::Send { .. }
+                | Command::SendMany { .. }
+                | Command::BumpFee { .. }
+                | Command::SignPartial { .. }
+                | Command::SignRawTransaction { .. }
+                | Command::Initiate { .. }
+                | Command::Participate { .. }
+                | Command::Redeem { .. }
+                | Command::Refund { .. }
+                | Command::Timestamp { .. }
+        )
+    }
+}
+
+/// Reads a passphrase from standard input rather than the command line,
+/// where other users could see it.
+fn read_passphrase(prompt: &str) -> String {
+    eprint!("{}: ", prompt);
+    let _ = io::stderr().flush();
+    let mut line = String::new();
+    io::stdin()
+        .lock()
+        .read_line(&mut line)
+        .expect("Unable to read the passphrase");
+    line.trim_end_matches(['\r', '\n']).to_string()
+}
+
+fn unlock_for_signing() {
+    let mut wallets = Wallets::new();
+    if wallets.is_locked() {
+        let passphrase = read_passphrase("Wallet passphrase");
+        wallets
+            .unlock_until_exit(passphrase.as_str())
+            .unwrap_or_else(|err| panic!("ERROR: {}", err));
+    }
+}
+
+/// Sends `transaction` to `node` and stops with the reason the node gives
+/// if it refuses it.
+fn send_transaction(node: &str, transaction: &Transaction) {
+    if let Err(reason) = send_tx(node, transaction) {
+        panic!("ERROR: Transaction not accepted: {}", reason)
+    }
+}
@@ -1,40 +1,727 @@
-use crate::Transaction;
+use crate::script::MAX_DATA_SIZE;
+use crate::transaction::{BlockTime, TXOutput};
+use crate::{Block, GLOBAL_CONFIG, Transaction, UTXOSet};
 
-use std::collections::HashMap;
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, BinaryHeap, HashMap, HashSet};
+use std::fmt;
 use std::sync::RwLock;
 
 use data_encoding::HEXLOWER;
 
+/// Smallest serialized transaction the pool accepts.
+pub const MIN_TX_SIZE: usize = 65;
+/// Largest serialized transaction the pool accepts.
+pub const MAX_STANDARD_TX_SIZE: usize = 100_000;
+/// Largest unlocking script the pool accepts, above that of a pay to script
+/// hash spend with the largest redeem script a script can push.
+pub const MAX_STANDARD_SCRIPT_SIG_SIZE: usize = 1_650;
+/// Added to the fee rate of an evicted package to get the new rolling minimum.
+const INCREMENTAL_RELAY_FEE: i64 = 1;
+/// Most pool transactions a single replacement may evict (BIP 125 rule 5).
+const MAX_REPLACEMENT_EVICTIONS: usize = 100;
+/// Candidates in a row that may fail to fit before a block is considered full.
+const MAX_CONSECUTIVE_FAILURES: usize = 1000;
+/// Time for the rolling minimum fee to decay by half, in milliseconds.
+const ROLLING_FEE_HALFLIFE: i64 = 12 * 60 * 60 * 1000;
+
+/// Why a transaction was refused by [`MemoryPool::accept`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum RejectReason {
+    Coinbase,
+    AlreadyKnown,
+    MissingInputs,
+    DuplicateInputs,
+    Conflict(String),
+    InvalidSignature,
+    /// The id carried along is not that of the transaction.
+    InvalidTxid,
+    /// The lock time or a relative lock has not passed for the next block.
+    NonFinal,
+    InvalidOutputValue,
+    /// More than one data output, one carrying too much, or unspendable
+    /// outputs of another form.
+    NonStandardData,
+    NegativeFee,
+    TooSmall(usize),
+    TooLarge(usize),
+    /// The unlocking script of the input, counted from 0, is larger than
+    /// `MAX_STANDARD_SCRIPT_SIG_SIZE`.
+    ScriptSigTooLarge(usize),
+    FeeTooLow {
+        fee: i64,
+        required: i64,
+    },
+    MempoolFull,
+    NotReplaceable(String),
+    ReplacementFeeTooLow {
+        fee: i64,
+        required: i64,
+    },
+    ReplacementFeeRateTooLow(String),
+    TooManyReplacements(usize),
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RejectReason::Coinbase => write!(f, "coinbase transactions are not relayed"),
+            RejectReason::AlreadyKnown => write!(f, "transaction already in pool"),
+            RejectReason::MissingInputs => write!(f, "inputs are missing or already spent"),
+            RejectReason::DuplicateInputs => write!(f, "transaction spends an output twice"),
+            RejectReason::Conflict(txid_hex) => {
+                write!(f, "conflicts with pool transaction {}", txid_hex)
+            }
+            RejectReason::InvalidSignature => write!(f, "invalid input signature"),
+            RejectReason::InvalidTxid => write!(f, "txid does not match the transaction"),
+            RejectReason::NonFinal => write!(f, "transaction is locked past the next block"),
+            RejectReason::InvalidOutputValue => write!(f, "output value must be positive"),
+            RejectReason::NonStandardData => write!(
+                f,
+                "only one data output of at most {} bytes is relayed",
+                MAX_DATA_SIZE
+            ),
+            RejectReason::NegativeFee => write!(f, "outputs exceed inputs"),
+            RejectReason::TooSmall(size) => write!(f, "transaction too small: {} bytes", size),
+            RejectReason::TooLarge(size) => write!(f, "transaction too large: {} bytes", size),
+            RejectReason::ScriptSigTooLarge(idx) => {
+                write!(f, "unlocking script of input {} is too large", idx)
+            }
+            RejectReason::FeeTooLow { fee, required } => {
+                write!(f, "fee {} below minimum relay fee {}", fee, required)
+            }
+            RejectReason::MempoolFull => write!(f, "memory pool is full"),
+            RejectReason::NotReplaceable(txid_hex) => {
+                write!(f, "conflicts with non-replaceable transaction {}", txid_hex)
+            }
+            RejectReason::ReplacementFeeTooLow { fee, required } => {
+                write!(f, "replacement fee {} below required {}", fee, required)
+            }
+            RejectReason::ReplacementFeeRateTooLow(txid_hex) => {
+                write!(f, "replacement fee rate not above that of {}", txid_hex)
+            }
+            RejectReason::TooManyReplacements(count) => {
+                write!(f, "replacement would evict {} transactions", count)
+            }
+        }
+    }
+}
+
+fn fee_rate(fee: i64, size: usize) -> i64 {
+    fee * 1000 / size.max(1) as i64
+}
+
+struct PoolEntry {
+    tx: Transaction,
+    fee: i64,
+    size: usize,
+    time: i64,
+    parents: HashSet<String>,
+    children: HashSet<String>,
+    /// Fee and size of the entry together with all of its descendants.
+    descendant_fee: i64,
+    descendant_size: usize,
+    /// Fee, size and number of transactions of the entry together with all
+    /// of its ancestors.
+    ancestor_fee: i64,
+    ancestor_size: usize,
+    ancestor_count: usize,
+}
+
+impl PoolEntry {
+    fn new(tx: Transaction, fee: i64, size: usize, time: i64) -> PoolEntry {
+        PoolEntry {
+            tx,
+            fee,
+            size,
+            time,
+            parents: HashSet::new(),
+            children: HashSet::new(),
+            descendant_fee: fee,
+            descendant_size: size,
+            ancestor_fee: fee,
+            ancestor_size: size,
+            ancestor_count: 1,
+        }
+    }
+
+    /// Eviction score: the higher of the entry's own fee rate and the fee
+    /// rate of the package formed with all of its descendants.
+    fn descendant_score(&self) -> i64 {
+        fee_rate(self.fee, self.size).max(fee_rate(self.descendant_fee, self.descendant_size))
+    }
+}
+
+/// Transactions in the pool, plus the outpoints they spend, keyed as
+/// `(txid_hex, vout)` and mapped to the spending transaction.
+struct PoolState {
+    txs: HashMap<String, PoolEntry>,
+    spent_outpoints: HashMap<(String, usize), String>,
+    /// Every entry as `(descendant_score, txid_hex)`, lowest first, to
+    /// evict from.
+    by_descendant_score: BTreeSet<(i64, String)>,
+    total_size: usize,
+    rolling_min_fee: i64,
+    rolling_fee_time: i64,
+}
+
+impl PoolState {
+    fn update_entry<F>(&mut self, txid_hex: &str, update: F)
+    where
+        F: FnOnce(&mut PoolEntry),
+    {
+        let Some(entry) = self.txs.get_mut(txid_hex) else {
+            return;
+        };
+        let old_score = entry.descendant_score();
+        update(entry);
+        let new_score = entry.descendant_score();
+        if new_score != old_score {
+            self.by_descendant_score
+                .remove(&(old_score, txid_hex.to_string()));
+            self.by_descendant_score
+                .insert((new_score, txid_hex.to_string()));
+        }
+    }
+
+    fn insert(&mut self, mut entry: PoolEntry) {
+        let txid_hex = HEXLOWER.encode(entry.tx.get_id());
+        let (fee, size) = (entry.fee, entry.size);
+        self.total_size += entry.size;
+        if !entry.tx.is_coinbase() {
+            for vin in entry.tx.get_vin() {
+                let outpoint = (HEXLOWER.encode(vin.get_txid()), vin.get_vout());
+                if let Some(parent) = self.txs.get_mut(outpoint.0.as_str()) {
+                    parent.children.insert(txid_hex.clone());
+                    entry.parents.insert(outpoint.0.clone());
+                }
+                self.spent_outpoints.insert(outpoint, txid_hex.clone());
+            }
+        }
+        self.by_descendant_score
+            .insert((entry.descendant_score(), txid_hex.clone()));
+        self.txs.insert(txid_hex.clone(), entry);
+        let ancestors = self.ancestors(txid_hex.as_str());
+        let (mut ancestor_fee, mut ancestor_size) = (fee, size);
+        for ancestor in &ancestors {
+            self.update_entry(ancestor.as_str(), |ancestor| {
+                ancestor.descendant_fee += fee;
+                ancestor.descendant_size += size;
+                ancestor_fee += ancestor.fee;
+                ancestor_size += ancestor.size;
+            });
+        }
+        let entry = self.txs.get_mut(txid_hex.as_str()).unwrap();
+        entry.ancestor_fee = ancestor_fee;
+        entry.ancestor_size = ancestor_size;
+        entry.ancestor_count = ancestors.len() + 1;
+    }
+
+    fn remove(&mut self, txid_hex: &str) -> Option<PoolEntry> {
+        let ancestors = self.ancestors(txid_hex);
+        let descendants = self.descendants(txid_hex);
+        let entry = self.txs.remove(txid_hex)?;
+        self.by_descendant_score
+            .remove(&(entry.descendant_score(), txid_hex.to_string()));
+        for ancestor in ancestors {
+            self.update_entry(ancestor.as_str(), |ancestor| {
+                ancestor.descendant_fee -= entry.fee;
+                ancestor.descendant_size -= entry.size;
+            });
+        }
+        // Left behind when a block confirms the entry before its children.
+        for descendant in descendants {
+            if let Some(descendant) = self.txs.get_mut(descendant.as_str()) {
+                descendant.ancestor_fee -= entry.fee;
+                descendant.ancestor_size -= entry.size;
+                descendant.ancestor_count -= 1;
+            }
+        }
+        self.total_size -= entry.size;
+        if !entry.tx.is_coinbase() {
+            for vin in entry.tx.get_vin() {
+                let outpoint = (HEXLOWER.encode(vin.get_txid()), vin.get_vout());
+                if let Some(spender) = self.spent_outpoints.get(&outpoint)
+                    && spender.eq(txid_hex)
+                {
+                    self.spent_outpoints.remove(&outpoint);
+                }
+            }
+        }
+        for parent in &entry.parents {
+            if let Some(parent) = self.txs.get_mut(parent.as_str()) {
+                parent.children.remove(txid_hex);
+            }
+        }
+        for child in &entry.children {
+            if let Some(child) = self.txs.get_mut(child.as_str()) {
+                child.parents.remove(txid_hex);
+            }
+        }
+        Some(entry)
+    }
+
+    /// Pool transactions that spend outputs of `txid_hex`, directly or through
+    /// other pool transactions.
+    fn descendants(&self, txid_hex: &str) -> Vec<String> {
+        self.walk(txid_hex, |entry| &entry.children)
+    }
+
+    /// Pool transactions whose outputs `txid_hex` spends, directly or through
+    /// other pool transactions.
+    fn ancestors(&self, txid_hex: &str) -> Vec<String> {
+        self.walk(txid_hex, |entry| &entry.parents)
+    }
+
+    fn walk<F>(&self, txid_hex: &str, next: F) -> Vec<String>
+    where
+        F: Fn(&PoolEntry) -> &HashSet<String>,
+    {
+        let mut visited = HashSet::new();
+        let mut stack = vec![txid_hex.to_string()];
+        while let Some(current) = stack.pop() {
+            let Some(entry) = self.txs.get(current.as_str()) else {
+                continue;
+            };
+            for linked in next(entry) {
+                if visited.insert(linked.clone()) {
+                    stack.push(linked.clone());
+                }
+            }
+        }
+        visited.into_iter().collect()
+    }
+
+    fn remove_with_descendants(&mut self, txid_hex: &str) -> Vec<PoolEntry> {
+        let mut package = self.descendants(txid_hex);
+        package.push(txid_hex.to_string());
+        self.remove_package(package)
+    }
+
+    /// Removes `txids`, which hold the descendants of each of them, children
+    /// before parents, so that no entry is left counting a removed
+    /// descendant in its package. The entries are returned in that order.
+    fn remove_package(&mut self, mut txids: Vec<String>) -> Vec<PoolEntry> {
+        // A child has more ancestors than any of its parents.
+        txids.sort_by_key(|txid_hex| {
+            Reverse(
+                self.txs
+                    .get(txid_hex.as_str())
+                    .map_or(0, |entry| entry.ancestor_count),
+            )
+        });
+        txids
+            .iter()
+            .filter_map(|txid_hex| self.remove(txid_hex.as_str()))
+            .collect()
+    }
+
+    /// Puts back `removed`, entries taken out children first, so the pool
+    /// is as it was before.
+    fn restore(&mut self, removed: Vec<PoolEntry>) {
+        for entry in removed.into_iter().rev() {
+            self.insert(PoolEntry::new(entry.tx, entry.fee, entry.size, entry.time));
+        }
+    }
+
+    /// Evicts the lowest scoring packages until the pool fits in `max_size`
+    /// bytes, raising the rolling minimum fee above each evicted package.
+    /// The evicted entries are returned children first.
+    fn trim_to_size(&mut self, max_size: usize, now: i64) -> Vec<PoolEntry> {
+        let mut evicted = vec![];
+        while self.total_size > max_size {
+            let Some((score, txid_hex)) = self.by_descendant_score.first().cloned() else {
+                break;
+            };
+            let rolling_min_fee = self.rolling_min_fee_at(now);
+            self.rolling_min_fee = rolling_min_fee.max(score + INCREMENTAL_RELAY_FEE);
+            self.rolling_fee_time = now;
+
+            evicted.extend(self.remove_with_descendants(txid_hex.as_str()));
+        }
+        evicted
+    }
+
+    fn expire(&mut self, cutoff: i64) -> Vec<String> {
+        let expired: Vec<String> = self
+            .txs
+            .iter()
+            .filter(|(_, entry)| entry.time < cutoff)
+            .map(|(txid_hex, _)| txid_hex.clone())
+            .collect();
+        let mut removed = vec![];
+        for txid_hex in expired {
+            removed.extend(
+                self.remove_with_descendants(txid_hex.as_str())
+                    .into_iter()
+                    .map(|entry| HEXLOWER.encode(entry.tx.get_id())),
+            );
+        }
+        removed
+    }
+
+    /// The rolling minimum fee rate, halved for every elapsed half-life since
+    /// it was last raised.
+    fn rolling_min_fee_at(&self, now: i64) -> i64 {
+        let halflives = (now - self.rolling_fee_time) / ROLLING_FEE_HALFLIFE;
+        if halflives >= 63 {
+            return 0;
+        }
+        self.rolling_min_fee >> halflives.max(0)
+    }
+}
+
 pub struct MemoryPool {
-    inner: RwLock<HashMap<String, Transaction>>,
+    inner: RwLock<PoolState>,
+    min_relay_fee: i64,
+    max_size: usize,
+    expiry: i64,
 }
 
 impl MemoryPool {
     pub fn new() -> MemoryPool {
+        MemoryPool::with_limits(
+            GLOBAL_CONFIG.get_mempool_max_size(),
+            GLOBAL_CONFIG.get_mempool_expiry(),
+        )
+    }
+
+    pub fn with_limits(max_size: usize, expiry: i64) -> MemoryPool {
         MemoryPool {
-            inner: RwLock::new(HashMap::new()),
+            inner: RwLock::new(PoolState {
+                txs: HashMap::new(),
+                spent_outpoints: HashMap::new(),
+                by_descendant_score: BTreeSet::new(),
+                total_size: 0,
+                rolling_min_fee: 0,
+                rolling_fee_time: 0,
+            }),
+            min_relay_fee: GLOBAL_CONFIG.get_min_relay_fee(),
+            max_size,
+            expiry,
         }
     }
 
     pub fn containes(&self, txid_hex: &str) -> bool {
-        self.inner.read().unwrap().contains_key(txid_hex)
+        self.inner.read().unwrap().txs.contains_key(txid_hex)
+    }
+
+    /// Inserts a transaction without running any acceptance checks.
+    #[cfg(test)]
+    fn add(&self, tx: Transaction) {
+        let size = tx.get_size();
+        let time = crate::current_timestamp();
+        let mut inner = self.inner.write().unwrap();
+        inner.insert(PoolEntry::new(tx, 0, size, time));
+    }
+
+    /// Validates `tx` against the UTXO set and the transactions already in
+    /// the pool, and inserts it if it passes the relay policy. A transaction
+    /// that conflicts only with entries signalling replace-by-fee replaces
+    /// them when it pays more; the ids of the replaced entries are returned.
+    pub fn accept(&self, tx: Transaction, utxo_set: &UTXOSet) -> Result<Vec<String>, RejectReason> {
+        let blockchain = utxo_set.get_blockchain();
+        let next_block = BlockTime {
+            height: blockchain.get_best_height() + 1,
+            time: blockchain.get_median_time_past() / 1000,
+        };
+        self.accept_with(tx, next_block, |txid, vout| {
+            utxo_set
+                .find_output(txid, vout)
+                .zip(utxo_set.find_confirmation(txid))
+        })
     }
 
-    pub fn add(&self, tx: Transaction) {
+    /// Accepts `tx` for a block at `next_block`. `find_output` looks up an
+    /// unspent output and the block that confirmed it.
+    fn accept_with<F>(
+        &self,
+        tx: Transaction,
+        next_block: BlockTime,
+        find_output: F,
+    ) -> Result<Vec<String>, RejectReason>
+    where
+        F: Fn(&[u8], usize) -> Option<(TXOutput, BlockTime)>,
+    {
+        if tx.is_coinbase() {
+            return Err(RejectReason::Coinbase);
+        }
+        if !tx.has_valid_id() {
+            return Err(RejectReason::InvalidTxid);
+        }
+        let size = tx.get_size();
+        if size < MIN_TX_SIZE {
+            return Err(RejectReason::TooSmall(size));
+        }
+        if size > MAX_STANDARD_TX_SIZE {
+            return Err(RejectReason::TooLarge(size));
+        }
+        if let Some(idx) = tx
+            .get_vin()
+            .iter()
+            .position(|vin| vin.get_script_sig().serialize().len() > MAX_STANDARD_SCRIPT_SIG_SIZE)
+        {
+            return Err(RejectReason::ScriptSigTooLarge(idx));
+        }
+        if tx
+            .get_vout()
+            .iter()
+            .any(|out| out.get_value() < 0 || (out.is_spendable() && out.get_value() == 0))
+        {
+            return Err(RejectReason::InvalidOutputValue);
+        }
+        let mut data_outputs = tx.get_vout().iter().filter(|out| !out.is_spendable());
+        let standard = |out: &TXOutput| matches!(out.get_script_pubkey().get_data(), Some(data) if data.len() <= MAX_DATA_SIZE);
+        if !data_outputs.next().is_none_or(standard) || data_outputs.next().is_some() {
+            return Err(RejectReason::NonStandardData);
+        }
+
         let txid_hex = HEXLOWER.encode(tx.get_id());
-        self.inner.write().unwrap().insert(txid_hex, tx);
+        let now = crate::current_timestamp();
+        let mut inner = self.inner.write().unwrap();
+        inner.expire(now - self.expiry * 1000);
+        if inner.txs.contains_key(txid_hex.as_str()) {
+            return Err(RejectReason::AlreadyKnown);
+        }
+
+        let mut seen = HashSet::new();
+        let mut conflicts = vec![];
+        for vin in tx.get_vin() {
+            let outpoint = (HEXLOWER.encode(vin.get_txid()), vin.get_vout());
+            if !seen.insert(outpoint.clone()) {
+                return Err(RejectReason::DuplicateInputs);
+            }
+            if let Some(spender) = inner.spent_outpoints.get(&outpoint)
+                && !conflicts.contains(spender)
+            {
+                if !inner.txs[spender.as_str()].tx.signals_rbf() {
+                    return Err(RejectReason::Conflict(spender.clone()));
+                }
+                conflicts.push(spender.clone());
+            }
+        }
+
+        let mut replaced = conflicts.clone();
+        for conflict in &conflicts {
+            for descendant in inner.descendants(conflict.as_str()) {
+                if !replaced.contains(&descendant) {
+                    replaced.push(descendant);
+                }
+            }
+        }
+        if replaced.len() > MAX_REPLACEMENT_EVICTIONS {
+            return Err(RejectReason::TooManyReplacements(replaced.len()));
+        }
+
+        let mut prev_outputs = vec![];
+        let mut confirmed = vec![];
+        for vin in tx.get_vin() {
+            let parent_txid_hex = HEXLOWER.encode(vin.get_txid());
+            // Outputs of pool transactions count as confirmed in the next block.
+            let prev_output = match inner.txs.get(parent_txid_hex.as_str()) {
+                Some(_) if replaced.contains(&parent_txid_hex) => None,
+                Some(parent) => parent
+                    .tx
+                    .get_vout()
+                    .get(vin.get_vout())
+                    .cloned()
+                    .map(|out| (out, next_block)),
+                None => find_output(vin.get_txid(), vin.get_vout()),
+            };
+            match prev_output {
+                Some((out, confirmation)) => {
+                    prev_outputs.push(out);
+                    confirmed.push(confirmation);
+                }
+                None => return Err(RejectReason::MissingInputs),
+            }
+        }
+
+        if !tx.is_final(next_block, confirmed.as_slice()) {
+            return Err(RejectReason::NonFinal);
+        }
+
+        if !tx.verify_with_outputs(prev_outputs.as_slice()) {
+            return Err(RejectReason::InvalidSignature);
+        }
+        let fee = tx.get_fee(prev_outputs.as_slice());
+        if fee < 0 {
+            return Err(RejectReason::NegativeFee);
+        }
+        let min_fee_rate = self.min_relay_fee.max(inner.rolling_min_fee_at(now));
+        let required = (size as i64 * min_fee_rate + 999) / 1000;
+        if fee < required {
+            return Err(RejectReason::FeeTooLow { fee, required });
+        }
+
+        if !conflicts.is_empty() {
+            for conflict in &conflicts {
+                let entry = &inner.txs[conflict.as_str()];
+                if fee_rate(fee, size) <= fee_rate(entry.fee, entry.size) {
+                    return Err(RejectReason::ReplacementFeeRateTooLow(conflict.clone()));
+                }
+            }
+            let replaced_fee: i64 = replaced
+                .iter()
+                .map(|txid_hex| inner.txs[txid_hex.as_str()].fee)
+                .sum();
+            let required = replaced_fee + (size as i64 * INCREMENTAL_RELAY_FEE + 999) / 1000;
+            if fee < required {
+                return Err(RejectReason::ReplacementFeeTooLow { fee, required });
+            }
+        }
+
+        let mut removed = inner.remove_package(replaced.clone());
+        inner.insert(PoolEntry::new(tx, fee, size, now));
+        removed.extend(inner.trim_to_size(self.max_size, now));
+        if let Some(idx) = removed
+            .iter()
+            .position(|entry| HEXLOWER.encode(entry.tx.get_id()) == txid_hex)
+        {
+            // The transaction does not stay, so neither the entries it
+            // replaced nor those evicted to make room for it have to go.
+            removed.remove(idx);
+            inner.restore(removed);
+            return Err(RejectReason::MempoolFull);
+        }
+        Ok(replaced)
+    }
+
+    /// Current minimum fee rate for acceptance, in coins per 1000 bytes. It
+    /// rises above the configured relay fee while the pool is evicting.
+    pub fn get_min_fee_rate(&self) -> i64 {
+        let inner = self.inner.read().unwrap();
+        let rolling_min_fee = inner.rolling_min_fee_at(crate::current_timestamp());
+        self.min_relay_fee.max(rolling_min_fee)
+    }
+
+    /// Minimum fee a transaction of `size` bytes must pay to be accepted.
+    pub fn required_fee(&self, size: usize) -> i64 {
+        (size as i64 * self.get_min_fee_rate() + 999) / 1000
+    }
+
+    pub fn expire(&self) -> Vec<String> {
+        let cutoff = crate::current_timestamp() - self.expiry * 1000;
+        self.inner.write().unwrap().expire(cutoff)
     }
 
     pub fn get(&self, txid_hex: &str) -> Option<Transaction> {
-        if let Some(tx) = self.inner.read().unwrap().get(txid_hex) {
-            return Some(tx.clone());
+        if let Some(entry) = self.inner.read().unwrap().txs.get(txid_hex) {
+            return Some(entry.tx.clone());
         }
         None
     }
 
+    pub fn get_fee(&self, txid_hex: &str) -> Option<i64> {
+        let inner = self.inner.read().unwrap();
+        inner.txs.get(txid_hex).map(|entry| entry.fee)
+    }
+
     pub fn remove(&self, txid_hex: &str) {
         let mut inner = self.inner.write().unwrap();
-        inner.remove(txid_hex);
+        inner.remove_with_descendants(txid_hex);
+    }
+
+    /// Drops transactions confirmed by `block`, along with any pool
+    /// transactions that spend the same outputs and their descendants.
+    pub fn remove_block_transactions(&self, block: &Block) {
+        let mut inner = self.inner.write().unwrap();
+        for tx in block.get_transactions() {
+            let txid_hex = HEXLOWER.encode(tx.get_id());
+            inner.remove(txid_hex.as_str());
+            if tx.is_coinbase() {
+                continue;
+            }
+            for vin in tx.get_vin() {
+                let outpoint = (HEXLOWER.encode(vin.get_txid()), vin.get_vout());
+                if let Some(spender) = inner.spent_outpoints.get(&outpoint).cloned() {
+                    inner.remove_with_descendants(spender.as_str());
+                }
+            }
+        }
+    }
+
+    /// Picks transactions for a block of at most `max_size` bytes. Candidates
+    /// are ranked by the fee rate of the package formed with their unselected
+    /// ancestors, so a high-fee child pulls in its low-fee parents, and every
+    /// transaction is placed after the pool transactions it spends. The
+    /// search stops once the block has no room left for another transaction,
+    /// or after `MAX_CONSECUTIVE_FAILURES` candidates in a row do not fit.
+    pub fn select_for_block(&self, max_size: usize) -> Vec<Transaction> {
+        let inner = self.inner.read().unwrap();
+
+        // Ancestor fee and size of the entries some of whose ancestors are
+        // already in the block, without those.
+        let mut modified: HashMap<&str, (i64, usize)> = HashMap::new();
+        let package_of = |modified: &HashMap<&str, (i64, usize)>, txid_hex: &str| {
+            modified.get(txid_hex).copied().unwrap_or_else(|| {
+                let entry = &inner.txs[txid_hex];
+                (entry.ancestor_fee, entry.ancestor_size)
+            })
+        };
+        // Entries are queued again when their package shrinks, so a popped
+        // score that no longer matches the package is stale.
+        let mut queue: BinaryHeap<(i64, &str)> = inner
+            .txs
+            .iter()
+            .map(|(txid_hex, entry)| {
+                (
+                    fee_rate(entry.ancestor_fee, entry.ancestor_size),
+                    txid_hex.as_str(),
+                )
+            })
+            .collect();
+
+        let mut selected: HashSet<&str> = HashSet::new();
+        let mut block_size = 0;
+        let mut failures = 0;
+        let mut txs = vec![];
+
+        while let Some((score, txid_hex)) = queue.pop() {
+            if max_size - block_size < MIN_TX_SIZE || failures >= MAX_CONSECUTIVE_FAILURES {
+                break;
+            }
+            let (fee, size) = package_of(&modified, txid_hex);
+            if selected.contains(txid_hex) || score != fee_rate(fee, size) {
+                continue;
+            }
+            if block_size + size > max_size {
+                failures += 1;
+                continue;
+            }
+            failures = 0;
+
+            let mut package: Vec<&str> = inner
+                .ancestors(txid_hex)
+                .iter()
+                .filter_map(|ancestor| inner.txs.get_key_value(ancestor.as_str()))
+                .map(|(ancestor, _)| ancestor.as_str())
+                .filter(|ancestor| !selected.contains(ancestor))
+                .collect();
+            package.push(txid_hex);
+            // A child has more ancestors than any of its parents.
+            package.sort_by_key(|id| inner.txs[*id].ancestor_count);
+            for id in package {
+                let entry = &inner.txs[id];
+                txs.push(entry.tx.clone());
+                selected.insert(id);
+                for descendant in inner.descendants(id) {
+                    let Some((descendant, _)) = inner.txs.get_key_value(descendant.as_str()) else {
+                        continue;
+                    };
+                    let descendant = descendant.as_str();
+                    if selected.contains(descendant) {
+                        continue;
+                    }
+                    let (fee, size) = package_of(&modified, descendant);
+                    let reduced = (fee - entry.fee, size - entry.size);
+                    modified.insert(descendant, reduced);
+                    queue.push((fee_rate(reduced.0, reduced.1), descendant));
+                }
+            }
+            block_size += size;
+        }
+        txs
     }
 
     pub fn get_all(&self) -> Vec<Transaction> {
@@ -42,14 +729,19 @@ impl MemoryPool {
 
         let mut txs = Vec::new();
 
-        for (_, tx) in inner.iter() {
-            txs.push(tx.clone());
+        for entry in inner.txs.values() {
+            txs.push(entry.tx.clone());
         }
         txs
     }
 
     pub fn len(&self) -> usize {
-        self.inner.read().unwrap().len()
+        self.inner.read().unwrap().txs.len()
+    }
+
+    /// Total serialized size of the pooled transactions, in bytes.
+    pub fn get_size(&self) -> usize {
+        self.inner.read().unwrap().total_size
     }
 }
 
@@ -98,10 +790,47 @@ impl BlockInTransit {
 
 #[cfg(test)]
 mod tests {
-    use super::{BlockInTransit, MemoryPool};
-    use crate::Transaction;
+    use super::{BlockInTransit, MAX_STANDARD_SCRIPT_SIG_SIZE, MemoryPool, RejectReason};
+    use crate::script::{MAX_DATA_SIZE, MAX_PUSH_SIZE, Op, Script};
+    use crate::transaction::{
+        BlockTime, MAX_NON_FINAL_SEQUENCE, MAX_RBF_SEQUENCE, SEQUENCE_FINAL, TXInput, TXOutput,
+    };
+    use crate::{PartialTransaction, Transaction, Wallet};
     use data_encoding::HEXLOWER;
 
+    /// Next block for the outputs the tests spend, confirmed at height 0.
+    const TIP: BlockTime = BlockTime {
+        height: 1,
+        time: 1_700_000_000,
+    };
+
+    fn spend(
+        wallet: &Wallet,
+        prevs: &[(&[u8], usize, &TXOutput)],
+        outs: Vec<TXOutput>,
+    ) -> Transaction {
+        spend_with_sequence(wallet, prevs, outs, SEQUENCE_FINAL)
+    }
+
+    fn spend_with_sequence(
+        wallet: &Wallet,
+        prevs: &[(&[u8], usize, &TXOutput)],
+        outs: Vec<TXOutput>,
+        sequence: u32,
+    ) -> Transaction {
+        let mut vin = vec![];
+        let mut prev_outputs = vec![];
+        for (txid, vout, out) in prevs {
+            let mut input = TXInput::new(txid, *vout);
+            input.set_sequence(sequence);
+            vin.push(input);
+            prev_outputs.push((*out).clone());
+        }
+        let mut tx = Transaction::new(vin, outs);
+        tx.sign_with_outputs(wallet, prev_outputs.as_slice());
+        tx
+    }
+
     #[test]
     fn test_memory_pool() {
         let pool = MemoryPool::new();
@@ -116,6 +845,544 @@ mod tests {
         assert!(option.is_none());
     }
 
+    #[test]
+    fn test_accept_rejects_double_spend() {
+        let pool = MemoryPool::new();
+        let wallet = Wallet::new();
+        let address = wallet.get_address();
+        let funding = TXOutput::new(10, address.as_str());
+        let funding_txid = crate::sha256_digest(b"funding");
+        let find_output = |txid: &[u8], vout: usize| {
+            if txid.eq(funding_txid.as_slice()) && vout == 0 {
+                return Some((funding.clone(), BlockTime::default()));
+            }
+            None
+        };
+
+        let first = spend(
+            &wallet,
+            &[(&funding_txid, 0, &funding)],
+            vec![TXOutput::new(9, address.as_str())],
+        );
+        assert_eq!(
+            pool.accept_with(first.clone(), TIP, find_output),
+            Ok(vec![])
+        );
+
+        let second = spend(
+            &wallet,
+            &[(&funding_txid, 0, &funding)],
+            vec![TXOutput::new(8, address.as_str())],
+        );
+        let first_txid_hex = HEXLOWER.encode(first.get_id());
+        assert_eq!(
+            pool.accept_with(second, TIP, find_output),
+            Err(RejectReason::Conflict(first_txid_hex))
+        );
+
+        let child = spend(
+            &wallet,
+            &[(first.get_id(), 0, &first.get_vout()[0])],
+            vec![TXOutput::new(8, address.as_str())],
+        );
+        assert_eq!(pool.accept_with(child, TIP, find_output), Ok(vec![]));
+        assert_eq!(pool.len(), 2);
+    }
+
+    #[test]
+    fn test_accept_checks_signature_and_fee() {
+        let pool = MemoryPool::new();
+        let wallet = Wallet::new();
+        let address = wallet.get_address();
+        let funding = TXOutput::new(10, address.as_str());
+        let funding_txid = crate::sha256_digest(b"funding");
+        let find_output = |_: &[u8], _: usize| Some((funding.clone(), BlockTime::default()));
+
+        let thief = Wallet::new();
+        let stolen = spend(
+            &thief,
+            &[(&funding_txid, 0, &funding)],
+            vec![TXOutput::new(9, address.as_str())],
+        );
+        assert_eq!(
+            pool.accept_with(stolen, TIP, find_output),
+            Err(RejectReason::InvalidSignature)
+        );
+
+        let no_fee = spend(
+            &wallet,
+            &[(&funding_txid, 0, &funding)],
+            vec![TXOutput::new(10, address.as_str())],
+        );
+        assert!(matches!(
+            pool.accept_with(no_fee, TIP, find_output),
+            Err(RejectReason::FeeTooLow { fee: 0, .. })
+        ));
+
+        let overspend = spend(
+            &wallet,
+            &[(&funding_txid, 0, &funding)],
+            vec![TXOutput::new(11, address.as_str())],
+        );
+        assert_eq!(
+            pool.accept_with(overspend, TIP, find_output),
+            Err(RejectReason::NegativeFee)
+        );
+        assert_eq!(pool.len(), 0);
+    }
+
+    #[test]
+    fn test_accept_data_outputs() {
+        let pool = MemoryPool::new();
+        let wallet = Wallet::new();
+        let address = wallet.get_address();
+        let funding = TXOutput::new(10, address.as_str());
+        let funding_txid = crate::sha256_digest(b"funding");
+        let find_output = |_: &[u8], _: usize| Some((funding.clone(), BlockTime::default()));
+        let anchor = |outs: Vec<TXOutput>| {
+            let mut vout = vec![TXOutput::new(9, address.as_str())];
+            vout.extend(outs);
+            spend(&wallet, &[(&funding_txid, 0, &funding)], vout)
+        };
+
+        let too_large = anchor(vec![TXOutput::new_data(&[0; MAX_DATA_SIZE + 1])]);
+        let two = anchor(vec![TXOutput::new_data(b"one"), TXOutput::new_data(b"two")]);
+        for tx in [too_large, two] {
+            assert_eq!(
+                pool.accept_with(tx, TIP, find_output),
+                Err(RejectReason::NonStandardData)
+            );
+        }
+        let hash = crate::sha256_digest(b"document");
+        let anchored = anchor(vec![TXOutput::new_data(hash.as_slice())]);
+        assert_eq!(pool.accept_with(anchored, TIP, find_output), Ok(vec![]));
+    }
+
+    #[test]
+    fn test_accept_limits_script_sig_size() {
+        let pool = MemoryPool::new();
+        // Adds keys while the redeem script still fits in a push.
+        let mut signers: Vec<Wallet> = vec![];
+        let mut redeem_script = Script::default();
+        loop {
+            signers.push(Wallet::new());
+            let pub_keys: Vec<Vec<u8>> = signers
+                .iter()
+                .map(|wallet| wallet.get_public_key().to_vec())
+                .collect();
+            let larger = Script::new_multisig(signers.len(), pub_keys.as_slice());
+            if larger.serialize().len() > MAX_PUSH_SIZE {
+                signers.pop();
+                break;
+            }
+            redeem_script = larger;
+        }
+        let funding = TXOutput::with_script(10, Script::new_p2sh(&redeem_script.hash()));
+        let find_output = |_: &[u8], _: usize| Some((funding.clone(), BlockTime::default()));
+        let to = Wallet::new().get_address();
+        let tx = Transaction::new(
+            vec![TXInput::new(b"funding", 0)],
+            vec![TXOutput::new(8, to.as_str())],
+        );
+
+        // The largest multisig spend is standard.
+        let mut partial =
+            PartialTransaction::new(tx.clone(), vec![funding.clone()], vec![Some(redeem_script)]);
+        for signer in &signers {
+            partial.sign(signer).unwrap();
+        }
+        let spend = partial.finalize().unwrap();
+        assert_eq!(pool.accept_with(spend, TIP, find_output), Ok(vec![]));
+
+        let mut padded = tx;
+        padded.set_script_sig(
+            0,
+            Script::new(vec![Op::Push(vec![0; MAX_STANDARD_SCRIPT_SIG_SIZE])]),
+        );
+        assert_eq!(
+            pool.accept_with(padded, TIP, find_output),
+            Err(RejectReason::ScriptSigTooLarge(0))
+        );
+    }
+
+    #[test]
+    fn test_accept_enforces_lock_times() {
+        let pool = MemoryPool::new();
+        let wallet = Wallet::new();
+        let address = wallet.get_address();
+        let funding = TXOutput::new(100, address.as_str());
+        let find_output = |_: &[u8], _: usize| Some((funding.clone(), BlockTime::default()));
+        let locked = |lock_time: u32, sequence: u32| {
+            let mut input = TXInput::new(b"funding", 0);
+            input.set_sequence(sequence);
+            let mut tx = Transaction::new(vec![input], vec![TXOutput::new(90, address.as_str())]);
+            tx.set_lock_time(lock_time);
+            tx.sign_with_outputs(&wallet, &[funding.clone()]);
+            tx
+        };
+
+        let until_next_height = locked(TIP.height as u32, MAX_NON_FINAL_SEQUENCE);
+        let until_next_minute = locked(TIP.time as u32 + 60, MAX_NON_FINAL_SEQUENCE);
+        let two_blocks_after_funding = locked(0, 2);
+        for tx in [
+            until_next_height,
+            until_next_minute,
+            two_blocks_after_funding,
+        ] {
+            assert_eq!(
+                pool.accept_with(tx, TIP, find_output),
+                Err(RejectReason::NonFinal)
+            );
+        }
+        // Final inputs ignore the lock time.
+        let ignored = locked(TIP.height as u32, SEQUENCE_FINAL);
+        assert_eq!(pool.accept_with(ignored, TIP, find_output), Ok(vec![]));
+    }
+
+    #[test]
+    fn test_trim_evicts_lowest_fee_rate_package() {
+        let wallet = Wallet::new();
+        let address = wallet.get_address();
+        let funding = TXOutput::new(100, address.as_str());
+        let find_output = |_: &[u8], _: usize| Some((funding.clone(), BlockTime::default()));
+        let funding_tx = |seed: &[u8], fee: i32| {
+            let txid = crate::sha256_digest(seed);
+            spend(
+                &wallet,
+                &[(&txid, 0, &funding)],
+                vec![TXOutput::new(100 - fee, address.as_str())],
+            )
+        };
+
+        let cheap = funding_tx(b"cheap", 1);
+        let cheap_child = spend(
+            &wallet,
+            &[(cheap.get_id(), 0, &cheap.get_vout()[0])],
+            vec![TXOutput::new(96, address.as_str())],
+        );
+        let rich = funding_tx(b"rich", 50);
+        let size = cheap.serialize().len();
+        let pool = MemoryPool::with_limits(size * 3 + size / 2, 60);
+
+        assert_eq!(
+            pool.accept_with(cheap.clone(), TIP, find_output),
+            Ok(vec![])
+        );
+        assert_eq!(
+            pool.accept_with(cheap_child.clone(), TIP, find_output),
+            Ok(vec![])
+        );
+        assert_eq!(pool.accept_with(rich.clone(), TIP, find_output), Ok(vec![]));
+        assert_eq!(pool.len(), 3);
+
+        let medium = funding_tx(b"medium", 20);
+        assert_eq!(pool.accept_with(medium, TIP, find_output), Ok(vec![]));
+        assert_eq!(pool.len(), 2);
+        assert!(!pool.containes(HEXLOWER.encode(cheap.get_id()).as_str()));
+        assert!(!pool.containes(HEXLOWER.encode(cheap_child.get_id()).as_str()));
+        assert!(pool.get_min_fee_rate() > super::fee_rate(1, size));
+
+        let too_cheap = funding_tx(b"too cheap", 1);
+        assert!(matches!(
+            pool.accept_with(too_cheap, TIP, find_output),
+            Err(RejectReason::FeeTooLow { .. })
+        ));
+    }
+
+    #[test]
+    fn test_replacement_evicted_from_full_pool_restores_original() {
+        let wallet = Wallet::new();
+        let address = wallet.get_address();
+        let funding = TXOutput::new(100, address.as_str());
+        let funding_txid = crate::sha256_digest(b"funding");
+        let rich_txid = crate::sha256_digest(b"rich");
+        let find_output = |_: &[u8], _: usize| Some((funding.clone(), BlockTime::default()));
+        let prevs = [(funding_txid.as_slice(), 0, &funding)];
+
+        let original = spend_with_sequence(
+            &wallet,
+            &prevs,
+            vec![TXOutput::new(90, address.as_str())],
+            MAX_RBF_SEQUENCE,
+        );
+        let rich = spend(
+            &wallet,
+            &[(&rich_txid, 0, &funding)],
+            vec![TXOutput::new(50, address.as_str())],
+        );
+        // Pays more, at a higher rate than the original, but at a lower rate
+        // than `rich`, so it is the first to go once the pool is full.
+        let replacement = spend(
+            &wallet,
+            &prevs,
+            vec![
+                TXOutput::new(20, address.as_str()),
+                TXOutput::new(20, address.as_str()),
+                TXOutput::new(20, address.as_str()),
+                TXOutput::new(20, address.as_str()),
+            ],
+        );
+        let (original_size, replacement_size) =
+            (original.serialize().len(), replacement.serialize().len());
+        let pool = MemoryPool::with_limits(
+            original_size + rich.serialize().len() + (replacement_size - original_size) / 2,
+            60,
+        );
+
+        assert_eq!(
+            pool.accept_with(original.clone(), TIP, find_output),
+            Ok(vec![])
+        );
+        assert_eq!(pool.accept_with(rich.clone(), TIP, find_output), Ok(vec![]));
+        assert_eq!(
+            pool.accept_with(replacement.clone(), TIP, find_output),
+            Err(RejectReason::MempoolFull)
+        );
+        assert_eq!(pool.len(), 2);
+        assert!(pool.containes(HEXLOWER.encode(original.get_id()).as_str()));
+        assert!(pool.containes(HEXLOWER.encode(rich.get_id()).as_str()));
+        assert!(!pool.containes(HEXLOWER.encode(replacement.get_id()).as_str()));
+    }
+
+    #[test]
+    fn test_descendant_packages_follow_the_pool() {
+        let pool = MemoryPool::new();
+        let wallet = Wallet::new();
+        let address = wallet.get_address();
+        let funding = TXOutput::new(100, address.as_str());
+        let funding_txid = crate::sha256_digest(b"funding");
+        let find_output = |_: &[u8], _: usize| Some((funding.clone(), BlockTime::default()));
+
+        let parent = spend(
+            &wallet,
+            &[(&funding_txid, 0, &funding)],
+            vec![
+                TXOutput::new(45, address.as_str()),
+                TXOutput::new(45, address.as_str()),
+            ],
+        );
+        let child = spend(
+            &wallet,
+            &[(parent.get_id(), 0, &parent.get_vout()[0])],
+            vec![TXOutput::new(40, address.as_str())],
+        );
+        let grandchild = spend(
+            &wallet,
+            &[(child.get_id(), 0, &child.get_vout()[0])],
+            vec![TXOutput::new(35, address.as_str())],
+        );
+        let sibling = spend(
+            &wallet,
+            &[(parent.get_id(), 1, &parent.get_vout()[1])],
+            vec![TXOutput::new(44, address.as_str())],
+        );
+        for tx in [&parent, &child, &grandchild, &sibling] {
+            assert_eq!(pool.accept_with(tx.clone(), TIP, find_output), Ok(vec![]));
+        }
+        let parent_txid_hex = HEXLOWER.encode(parent.get_id());
+        let package = |pool: &MemoryPool| {
+            let inner = pool.inner.read().unwrap();
+            assert_eq!(inner.by_descendant_score.len(), inner.txs.len());
+            let entry = &inner.txs[parent_txid_hex.as_str()];
+            (entry.descendant_fee, entry.descendant_size)
+        };
+        let size = |txs: &[&Transaction]| txs.iter().map(|tx| tx.get_size()).sum::<usize>();
+
+        assert_eq!(
+            package(&pool),
+            (21, size(&[&parent, &child, &grandchild, &sibling]))
+        );
+        pool.remove(HEXLOWER.encode(child.get_id()).as_str());
+        assert_eq!(pool.len(), 2);
+        assert_eq!(package(&pool), (11, size(&[&parent, &sibling])));
+    }
+
+    #[test]
+    fn test_ancestor_packages_follow_the_pool() {
+        let pool = MemoryPool::new();
+        let wallet = Wallet::new();
+        let address = wallet.get_address();
+        let funding = TXOutput::new(100, address.as_str());
+        let funding_txid = crate::sha256_digest(b"funding");
+        let find_output = |_: &[u8], _: usize| Some((funding.clone(), BlockTime::default()));
+
+        let parent = spend(
+            &wallet,
+            &[(&funding_txid, 0, &funding)],
+            vec![TXOutput::new(90, address.as_str())],
+        );
+        let child = spend(
+            &wallet,
+            &[(parent.get_id(), 0, &parent.get_vout()[0])],
+            vec![TXOutput::new(80, address.as_str())],
+        );
+        let grandchild = spend(
+            &wallet,
+            &[(child.get_id(), 0, &child.get_vout()[0])],
+            vec![TXOutput::new(75, address.as_str())],
+        );
+        for tx in [&parent, &child, &grandchild] {
+            assert_eq!(pool.accept_with(tx.clone(), TIP, find_output), Ok(vec![]));
+        }
+        let grandchild_txid_hex = HEXLOWER.encode(grandchild.get_id());
+        let package = |pool: &MemoryPool| {
+            let inner = pool.inner.read().unwrap();
+            let entry = &inner.txs[grandchild_txid_hex.as_str()];
+            (
+                entry.ancestor_fee,
+                entry.ancestor_size,
+                entry.ancestor_count,
+            )
+        };
+        let size = |txs: &[&Transaction]| txs.iter().map(|tx| tx.get_size()).sum::<usize>();
+
+        assert_eq!(
+            package(&pool),
+            (25, size(&[&parent, &child, &grandchild]), 3)
+        );
+        // As when a block confirms the parent alone.
+        pool.inner
+            .write()
+            .unwrap()
+            .remove(HEXLOWER.encode(parent.get_id()).as_str());
+        assert_eq!(package(&pool), (15, size(&[&child, &grandchild]), 2));
+    }
+
+    #[test]
+    fn test_replace_by_fee() {
+        let pool = MemoryPool::new();
+        let wallet = Wallet::new();
+        let address = wallet.get_address();
+        let funding = TXOutput::new(10, address.as_str());
+        let funding_txid = crate::sha256_digest(b"funding");
+        let find_output = |_: &[u8], _: usize| Some((funding.clone(), BlockTime::default()));
+        let prevs = [(funding_txid.as_slice(), 0, &funding)];
+
+        let original = spend_with_sequence(
+            &wallet,
+            &prevs,
+            vec![TXOutput::new(9, address.as_str())],
+            MAX_RBF_SEQUENCE,
+        );
+        let child = spend(
+            &wallet,
+            &[(original.get_id(), 0, &original.get_vout()[0])],
+            vec![TXOutput::new(8, address.as_str())],
+        );
+        assert_eq!(
+            pool.accept_with(original.clone(), TIP, find_output),
+            Ok(vec![])
+        );
+        assert_eq!(
+            pool.accept_with(child.clone(), TIP, find_output),
+            Ok(vec![])
+        );
+
+        let cheap = spend_with_sequence(
+            &wallet,
+            &prevs,
+            vec![TXOutput::new(8, address.as_str())],
+            MAX_RBF_SEQUENCE,
+        );
+        assert!(matches!(
+            pool.accept_with(cheap, TIP, find_output),
+            Err(RejectReason::ReplacementFeeTooLow {
+                fee: 2,
+                required: 3
+            })
+        ));
+
+        let replacement = spend(&wallet, &prevs, vec![TXOutput::new(6, address.as_str())]);
+        let replaced = pool
+            .accept_with(replacement.clone(), TIP, find_output)
+            .unwrap();
+        assert_eq!(replaced.len(), 2);
+        assert_eq!(pool.len(), 1);
+        assert!(pool.containes(HEXLOWER.encode(replacement.get_id()).as_str()));
+
+        let final_conflict = spend(&wallet, &prevs, vec![TXOutput::new(1, address.as_str())]);
+        assert_eq!(
+            pool.accept_with(final_conflict, TIP, find_output),
+            Err(RejectReason::Conflict(
+                HEXLOWER.encode(replacement.get_id())
+            ))
+        );
+    }
+
+    #[test]
+    fn test_select_for_block_uses_ancestor_packages() {
+        let pool = MemoryPool::new();
+        let wallet = Wallet::new();
+        let address = wallet.get_address();
+        let funding = TXOutput::new(100, address.as_str());
+        let find_output = |_: &[u8], _: usize| Some((funding.clone(), BlockTime::default()));
+        let parent_txid = crate::sha256_digest(b"parent");
+        let other_txid = crate::sha256_digest(b"other");
+
+        let parent = spend(
+            &wallet,
+            &[(&parent_txid, 0, &funding)],
+            vec![TXOutput::new(99, address.as_str())],
+        );
+        let child = spend(
+            &wallet,
+            &[(parent.get_id(), 0, &parent.get_vout()[0])],
+            vec![TXOutput::new(79, address.as_str())],
+        );
+        let other = spend(
+            &wallet,
+            &[(&other_txid, 0, &funding)],
+            vec![TXOutput::new(95, address.as_str())],
+        );
+        assert_eq!(
+            pool.accept_with(other.clone(), TIP, find_output),
+            Ok(vec![])
+        );
+        assert_eq!(
+            pool.accept_with(parent.clone(), TIP, find_output),
+            Ok(vec![])
+        );
+        assert_eq!(
+            pool.accept_with(child.clone(), TIP, find_output),
+            Ok(vec![])
+        );
+
+        let ids: Vec<Vec<u8>> = pool
+            .select_for_block(usize::MAX)
+            .iter()
+            .map(|tx| tx.get_id_bytes())
+            .collect();
+        assert_eq!(
+            ids,
+            vec![
+                parent.get_id_bytes(),
+                child.get_id_bytes(),
+                other.get_id_bytes()
+            ]
+        );
+
+        let size = parent.serialize().len() + child.serialize().len();
+        let ids: Vec<Vec<u8>> = pool
+            .select_for_block(size)
+            .iter()
+            .map(|tx| tx.get_id_bytes())
+            .collect();
+        assert_eq!(ids, vec![parent.get_id_bytes(), child.get_id_bytes()]);
+    }
+
+    #[test]
+    fn test_expire() {
+        let pool = MemoryPool::new();
+        let tx = Transaction::new_coinbase_tx("1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa");
+        pool.add(tx);
+        assert!(pool.expire().is_empty());
+
+        let expired = pool.inner.write().unwrap().expire(i64::MAX);
+        assert_eq!(expired.len(), 1);
+        assert_eq!(pool.len(), 0);
+        assert_eq!(pool.get_size(), 0);
+    }
+
     #[test]
     fn test_blocks_in_transit() {
         let mut block_hashs = vec![];
@@ -0,0 +1,109 @@
+use crate::sha256_digest;
+
+use serde::{Deserialize, Serialize};
+
+/// Root of the Merkle tree over `leaves`: pairs of hashes are hashed
+/// together level by level, an odd hash out being paired with itself,
+/// until one is left. No leaves hash to the hash of nothing. Pairing with
+/// itself makes `[a, b, c]` and `[a, b, c, c]` share a root, so the leaves
+/// must be distinct for the root to commit to them.
+pub fn merkle_root(leaves: &[Vec<u8>]) -> Vec<u8> {
+    if leaves.is_empty() {
+        return sha256_digest(&[]);
+    }
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| hash_pair(&pair[0], pair.last().unwrap()))
+            .collect();
+    }
+    level.remove(0)
+}
+
+/// The hashes needed to recompute a Merkle root from one of its leaves.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MerkleProof {
+    index: usize,
+    /// The other hash of each pair on the way up, from the leaves.
+    siblings: Vec<Vec<u8>>,
+}
+
+impl MerkleProof {
+    /// Proves that the leaf at `index` is in the tree over `leaves`.
+    pub fn new(leaves: &[Vec<u8>], index: usize) -> MerkleProof {
+        if index >= leaves.len() {
+            panic!("Leaf {} is not in the tree", index)
+        }
+        let mut siblings = vec![];
+        let mut level = leaves.to_vec();
+        let mut position = index;
+        while level.len() > 1 {
+            let sibling = (position ^ 1).min(level.len() - 1);
+            siblings.push(level[sibling].clone());
+            level = level
+                .chunks(2)
+                .map(|pair| hash_pair(&pair[0], pair.last().unwrap()))
+                .collect();
+            position /= 2;
+        }
+        MerkleProof { index, siblings }
+    }
+
+    /// The root of a tree holding `leaf` at the proven position.
+    pub fn root_from(&self, leaf: &[u8]) -> Vec<u8> {
+        let mut hash = leaf.to_vec();
+        let mut position = self.index;
+        for sibling in &self.siblings {
+            hash = if position.is_multiple_of(2) {
+                hash_pair(&hash, sibling)
+            } else {
+                hash_pair(sibling, &hash)
+            };
+            position /= 2;
+        }
+        hash
+    }
+
+    pub fn verify(&self, leaf: &[u8], root: &[u8]) -> bool {
+        self.root_from(leaf) == root
+    }
+
+    pub fn get_index(&self) -> usize {
+        self.index
+    }
+
+    pub fn get_siblings(&self) -> &[Vec<u8>] {
+        self.siblings.as_slice()
+    }
+}
+
+fn hash_pair(left: &[u8], right: &[u8]) -> Vec<u8> {
+    let mut data = left.to_vec();
+    data.extend(right);
+    sha256_digest(data.as_slice())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MerkleProof, merkle_root};
+    use crate::sha256_digest;
+
+    #[test]
+    fn test_merkle_proof() {
+        for count in 1..=9 {
+            let leaves: Vec<Vec<u8>> = (0..count).map(|i: u8| sha256_digest(&[i])).collect();
+            let root = merkle_root(leaves.as_slice());
+            for (index, leaf) in leaves.iter().enumerate() {
+                let proof = MerkleProof::new(leaves.as_slice(), index);
+                assert!(proof.verify(leaf, root.as_slice()));
+                assert!(!proof.verify(sha256_digest(b"other").as_slice(), root.as_slice()));
+            }
+        }
+
+        let leaves = vec![sha256_digest(b"a"), sha256_digest(b"b")];
+        let proof = MerkleProof::new(leaves.as_slice(), 0);
+        assert_eq!(proof.get_siblings(), &leaves[1..]);
+        assert!(!proof.verify(&leaves[1], merkle_root(leaves.as_slice()).as_slice()));
+    }
+}
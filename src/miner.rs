@@ -0,0 +1,212 @@
+use crate::{
+    Block, BlockError, BlockTemplate, Blockchain, MemoryPool, MiningStats, Transaction, consensus,
+};
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{error, info};
+
+/// How long a template is worked on before it is rebuilt to pick up
+/// transactions that arrived since.
+const TEMPLATE_REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+const REFRESH_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+struct Job {
+    height: usize,
+    started: Instant,
+    cancel: Arc<AtomicBool>,
+}
+
+struct MinerState {
+    last_block: Instant,
+    job: Option<Job>,
+    new_transactions: bool,
+}
+
+/// Background block producer. It mines a block as soon as the memory pool
+/// holds enough transactions, or once the block interval has passed since the
+/// last block even if that block has to be empty.
+pub struct Miner {
+    blockchain: Blockchain,
+    mempool: &'static MemoryPool,
+    mining_address: String,
+    block_interval: Duration,
+    min_transactions: usize,
+    state: Mutex<MinerState>,
+    wakeup: Condvar,
+    stats: MiningStats,
+}
+
+impl Miner {
+    pub fn new(
+        blockchain: Blockchain,
+        mempool: &'static MemoryPool,
+        mining_address: String,
+        block_interval: Duration,
+        min_transactions: usize,
+    ) -> Miner {
+        Miner {
+            blockchain,
+            mempool,
+            mining_address,
+            block_interval,
+            min_transactions,
+            state: Mutex::new(MinerState {
+                last_block: Instant::now(),
+                job: None,
+                new_transactions: false,
+            }),
+            wakeup: Condvar::new(),
+            stats: MiningStats::new(),
+        }
+    }
+
+    /// Called when a transaction enters the pool. Work on the current
+    /// template goes on; the transaction is picked up when the template is
+    /// next refreshed.
+    pub fn notify_transaction(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.new_transactions = true;
+        self.wakeup.notify_all();
+    }
+
+    /// Called when a block at `height` is accepted from the network. Work on
+    /// a template at that height or below is stopped.
+    pub fn notify_block(&self, height: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.last_block = Instant::now();
+        if let Some(job) = &state.job
+            && job.height <= height
+        {
+            job.cancel.store(true, Ordering::Relaxed);
+        }
+        self.wakeup.notify_all();
+    }
+
+    pub fn get_stats(&self) -> &MiningStats {
+        &self.stats
+    }
+
+    pub fn run<F>(&self, on_block: F)
+    where
+        F: Fn(&Block),
+    {
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                loop {
+                    thread::sleep(REFRESH_CHECK_INTERVAL);
+                    self.refresh_template();
+                }
+            });
+            loop {
+                self.wait_for_work();
+                if let Some(block) = self.mine_template() {
+                    self.state.lock().unwrap().last_block = Instant::now();
+                    on_block(&block);
+                }
+            }
+        });
+    }
+
+    /// Stops work on a template that is older than the refresh interval
+    /// and misses transactions now in the pool, so the next one has them.
+    fn refresh_template(&self) {
+        let state = self.state.lock().unwrap();
+        if let Some(job) = &state.job
+            && state.new_transactions
+            && job.started.elapsed() >= TEMPLATE_REFRESH_INTERVAL
+        {
+            info!("Refreshing block template at height {}", job.height);
+            job.cancel.store(true, Ordering::Relaxed);
+        }
+    }
+
+    fn wait_for_work(&self) {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            // Under proof of authority another node may own the next block,
+            // or the wallet holding our key may still be locked.
+            if !consensus::current().can_seal(self.blockchain.get_best_height() + 1) {
+                let (guard, _) = self
+                    .wakeup
+                    .wait_timeout(state, REFRESH_CHECK_INTERVAL)
+                    .unwrap();
+                state = guard;
+                continue;
+            }
+            let elapsed = state.last_block.elapsed();
+            if elapsed >= self.block_interval {
+                return;
+            }
+            if self.min_transactions > 0 && self.mempool.len() >= self.min_transactions {
+                return;
+            }
+            let (guard, _) = self
+                .wakeup
+                .wait_timeout(state, self.block_interval - elapsed)
+                .unwrap();
+            state = guard;
+        }
+    }
+
+    /// Builds a template on the current tip and seals it. Returns `None` if
+    /// the search was cancelled or the tip moved.
+    fn mine_template(&self) -> Option<Block> {
+        self.state.lock().unwrap().new_transactions = false;
+        let template = BlockTemplate::new(&self.blockchain, self.mempool);
+        let tip_hash = template.get_prev_block_hash().to_string();
+        let height = template.get_height();
+
+        let Ok(coinbase_value) = i32::try_from(template.get_coinbase_value()) else {
+            error!(
+                "Coinbase value {} of block {} is out of range",
+                template.get_coinbase_value(),
+                height
+            );
+            return None;
+        };
+        let coinbase_tx =
+            Transaction::new_coinbase_tx_with_value(self.mining_address.as_str(), coinbase_value);
+        let tx_count = template.get_transactions().len();
+        let block = template.new_block(coinbase_tx);
+
+        let cancel = Arc::new(AtomicBool::new(false));
+        self.state.lock().unwrap().job = Some(Job {
+            height,
+            started: Instant::now(),
+            cancel: cancel.clone(),
+        });
+        if self.blockchain.get_tip_hash().ne(&tip_hash) {
+            self.state.lock().unwrap().job = None;
+            return None;
+        }
+        info!("Mining block {} with {} transactions", height, tx_count);
+        let result = consensus::current().seal(block, &cancel, &self.stats);
+        self.state.lock().unwrap().job = None;
+
+        let Some(block) = result else {
+            info!("Abandoned block template at height {}", height);
+            return None;
+        };
+        match self.blockchain.connect_block(&block) {
+            Ok(()) => {}
+            Err(BlockError::NotOnTip) => {
+                info!("Discarding stale block at height {}", height);
+                self.stats.record_stale_block();
+                return None;
+            }
+            Err(reason) => {
+                error!("Mined block {} refused: {}", block.get_hash(), reason);
+                return None;
+            }
+        }
+        self.mempool.remove_block_transactions(&block);
+        self.stats.record_block_found();
+
+        info!("New Block Mined: {}", block.get_hash());
+        Some(block)
+    }
+}
@@ -0,0 +1,231 @@
+use crate::transaction::SUBSIDY;
+use crate::{BlockTemplate, Blockchain, MemoryPool, ProofOfWork};
+
+use std::fmt::{self, Write};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use data_encoding::HEXLOWER;
+use serde::{Deserialize, Serialize};
+
+#[derive(Default)]
+struct MiningCounters {
+    hashes: u64,
+    hashing_time: Duration,
+    blocks_found: u64,
+    stale_blocks: u64,
+}
+
+/// Running totals kept by whatever mines on this node.
+#[derive(Default)]
+pub struct MiningStats {
+    inner: Mutex<MiningCounters>,
+}
+
+impl MiningStats {
+    pub fn new() -> MiningStats {
+        MiningStats::default()
+    }
+
+    /// Adds `hashes` computed over `elapsed` of mining.
+    pub fn record_hashes(&self, hashes: u64, elapsed: Duration) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.hashes += hashes;
+        inner.hashing_time += elapsed;
+    }
+
+    pub fn record_block_found(&self) {
+        self.inner.lock().unwrap().blocks_found += 1;
+    }
+
+    /// Counts a solved block that lost the race for the tip.
+    pub fn record_stale_block(&self) {
+        self.inner.lock().unwrap().stale_blocks += 1;
+    }
+
+    /// Average hashes per second over the time spent mining.
+    pub fn get_hashrate(&self) -> f64 {
+        let inner = self.inner.lock().unwrap();
+        let seconds = inner.hashing_time.as_secs_f64();
+        if seconds > 0.0 {
+            inner.hashes as f64 / seconds
+        } else {
+            0.0
+        }
+    }
+
+    pub fn get_blocks_found(&self) -> u64 {
+        self.inner.lock().unwrap().blocks_found
+    }
+
+    pub fn get_stale_blocks(&self) -> u64 {
+        self.inner.lock().unwrap().stale_blocks
+    }
+}
+
+/// Snapshot answered by `getmininginfo` and the metrics endpoint.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MiningInfo {
+    mining: bool,
+    height: usize,
+    difficulty: f64,
+    target: String,
+    hashrate: f64,
+    blocks_found: u64,
+    stale_blocks: u64,
+    template_transactions: usize,
+    template_fees: i64,
+    pooled_transactions: usize,
+}
+
+impl MiningInfo {
+    /// Collects the chain and template figures, plus the counters of the
+    /// local miner or pool if one is running.
+    pub fn new(
+        blockchain: &Blockchain,
+        mempool: &MemoryPool,
+        stats: Option<&MiningStats>,
+    ) -> MiningInfo {
+        let template = BlockTemplate::new(blockchain, mempool);
+        MiningInfo {
+            mining: stats.is_some(),
+            height: blockchain.get_best_height(),
+            difficulty: ProofOfWork::difficulty(),
+            target: HEXLOWER.encode(&ProofOfWork::target()),
+            hashrate: stats.map_or(0.0, |stats| stats.get_hashrate()),
+            blocks_found: stats.map_or(0, |stats| stats.get_blocks_found()),
+            stale_blocks: stats.map_or(0, |stats| stats.get_stale_blocks()),
+            template_transactions: template.get_transactions().len(),
+            template_fees: template.get_coinbase_value() - SUBSIDY as i64,
+            pooled_transactions: mempool.len(),
+        }
+    }
+
+    pub fn get_height(&self) -> usize {
+        self.height
+    }
+
+    /// Renders the snapshot in the Prometheus text format.
+    pub fn to_metrics(&self) -> String {
+        let mut metrics = String::new();
+        let series = [
+            (
+                "socratix_mining",
+                "gauge",
+                "Whether this node mines",
+                self.mining as u8 as f64,
+            ),
+            (
+                "socratix_block_height",
+                "gauge",
+                "Height of the chain tip",
+                self.height as f64,
+            ),
+            (
+                "socratix_difficulty",
+                "gauge",
+                "Expected hashes per block",
+                self.difficulty,
+            ),
+            (
+                "socratix_hashrate",
+                "gauge",
+                "Hashes per second while mining",
+                self.hashrate,
+            ),
+            (
+                "socratix_blocks_found_total",
+                "counter",
+                "Blocks mined by this node",
+                self.blocks_found as f64,
+            ),
+            (
+                "socratix_stale_blocks_total",
+                "counter",
+                "Mined blocks that lost the race for the tip",
+                self.stale_blocks as f64,
+            ),
+            (
+                "socratix_template_transactions",
+                "gauge",
+                "Transactions in the next block template",
+                self.template_transactions as f64,
+            ),
+            (
+                "socratix_template_fees",
+                "gauge",
+                "Fees collected by the next block template",
+                self.template_fees as f64,
+            ),
+            (
+                "socratix_mempool_transactions",
+                "gauge",
+                "Transactions waiting in the memory pool",
+                self.pooled_transactions as f64,
+            ),
+        ];
+        for (name, kind, help, value) in series {
+            let _ = writeln!(metrics, "# HELP {} {}", name, help);
+            let _ = writeln!(metrics, "# TYPE {} {}", name, kind);
+            let _ = writeln!(metrics, "{} {}", name, value);
+        }
+        metrics
+    }
+}
+
+impl fmt::Display for MiningInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Mining: {}", self.mining)?;
+        writeln!(f, "Height: {}", self.height)?;
+        writeln!(f, "Difficulty: {}", self.difficulty)?;
+        writeln!(f, "Target: {}", self.target)?;
+        writeln!(f, "Hashrate: {:.0} H/s", self.hashrate)?;
+        writeln!(f, "Blocks found: {}", self.blocks_found)?;
+        writeln!(f, "Stale blocks: {}", self.stale_blocks)?;
+        writeln!(f, "Template transactions: {}", self.template_transactions)?;
+        writeln!(f, "Template fees: {}", self.template_fees)?;
+        write!(f, "Pooled transactions: {}", self.pooled_transactions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MiningInfo, MiningStats};
+    use std::time::Duration;
+
+    #[test]
+    fn test_mining_stats() {
+        let stats = MiningStats::new();
+        assert_eq!(stats.get_hashrate(), 0.0);
+
+        stats.record_hashes(1000, Duration::from_secs(1));
+        stats.record_hashes(3000, Duration::from_secs(1));
+        stats.record_block_found();
+        stats.record_block_found();
+        stats.record_stale_block();
+        assert_eq!(stats.get_hashrate(), 2000.0);
+        assert_eq!(stats.get_blocks_found(), 2);
+        assert_eq!(stats.get_stale_blocks(), 1);
+    }
+
+    #[test]
+    fn test_to_metrics() {
+        let info = MiningInfo {
+            mining: true,
+            height: 7,
+            difficulty: 256.0,
+            target: String::new(),
+            hashrate: 1500.5,
+            blocks_found: 3,
+            stale_blocks: 1,
+            template_transactions: 2,
+            template_fees: 4,
+            pooled_transactions: 5,
+        };
+        let metrics = info.to_metrics();
+        assert!(metrics.contains("socratix_block_height 7\n"));
+        assert!(metrics.contains("socratix_hashrate 1500.5\n"));
+        assert!(metrics.contains("# TYPE socratix_stale_blocks_total counter\n"));
+        assert!(metrics.contains("socratix_template_fees 4\n"));
+    }
+}
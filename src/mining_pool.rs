@@ -0,0 +1,557 @@
+use crate::{
+    Block, BlockError, BlockTemplate, Blockchain, ChainParams, MAX_FUTURE_BLOCK_TIME, MemoryPool,
+    MiningStats, ProofOfWork, Transaction, current_timestamp, validate_address,
+};
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::error::Error;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use data_encoding::HEXLOWER;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+
+/// How many fewer leading zero bits a share needs than a block, so workers
+/// report progress several times per block.
+pub const SHARE_DIFFICULTY_SHIFT: i32 = 2;
+/// Number of coinbase extra nonces reserved for each worker.
+pub const EXTRANONCE_RANGE: u64 = 1 << 32;
+/// How old the current job must be before an accepted share replaces it with
+/// one paying out the new split, unless the current job pays no worker yet.
+const JOB_REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Messages of the pool protocol, one JSON object per line. A worker sends
+/// `Subscribe` once and then `Submit` for every share; the pool answers each
+/// with `Subscribed` or `SubmitResult` and pushes a `Notify` whenever the job
+/// changes.
+#[derive(Serialize, Deserialize, Debug)]
+pub enum PoolMessage {
+    Subscribe {
+        address: String,
+    },
+    Subscribed {
+        extranonce_start: u64,
+        extranonce_range: u64,
+        share_target: String,
+    },
+    Notify {
+        job_id: String,
+        template: Vec<u8>,
+        coinbase: Vec<u8>,
+        clean_jobs: bool,
+    },
+    Submit {
+        job_id: String,
+        extra_nonce: u64,
+        timestamp: i64,
+        nonce: i64,
+    },
+    SubmitResult {
+        accepted: bool,
+        block_found: bool,
+        reason: Option<String>,
+    },
+}
+
+struct Job {
+    template: BlockTemplate,
+    coinbase: Transaction,
+}
+
+struct PoolWorker {
+    address: String,
+    extranonce_start: u64,
+    /// Locked on its own, so that writing to a slow worker does not hold up
+    /// the pool state.
+    stream: Arc<Mutex<TcpStream>>,
+}
+
+struct MiningPoolState {
+    workers: HashMap<usize, PoolWorker>,
+    next_worker_id: usize,
+    jobs: HashMap<String, Job>,
+    next_job_id: u64,
+    /// When the newest job was built.
+    last_job: Instant,
+    /// Whether the newest job was built before any share of the round, so
+    /// that it pays the operator alone.
+    last_job_unshared: bool,
+    /// Shares per address since the last block found by the pool.
+    round_shares: BTreeMap<String, u64>,
+    /// Shares per address since the pool started.
+    total_shares: BTreeMap<String, u64>,
+    seen_shares: HashSet<(String, u64, i64, i64)>,
+    /// When the previous share arrived, to credit the hashes behind the
+    /// next one to the time in between.
+    last_share: Instant,
+}
+
+/// Pooled mining server. Workers grind jobs built from the node's block
+/// template and report shares at an easier target. Each job's coinbase
+/// splits the block value over the workers in proportion to their shares in
+/// the current round as of when the job was built; the operator address gets
+/// rounding leftovers and the whole value while no shares have come in yet.
+pub struct MiningPool {
+    blockchain: Blockchain,
+    mempool: &'static MemoryPool,
+    operator_address: String,
+    share_target_bits: i32,
+    share_target: [u8; 32],
+    state: Mutex<MiningPoolState>,
+    stats: MiningStats,
+}
+
+impl MiningPool {
+    pub fn new(
+        blockchain: Blockchain,
+        mempool: &'static MemoryPool,
+        operator_address: String,
+    ) -> MiningPool {
+        let share_target_bits =
+            (ChainParams::current().get_target_bits() - SHARE_DIFFICULTY_SHIFT).max(0);
+        MiningPool {
+            blockchain,
+            mempool,
+            operator_address,
+            share_target_bits,
+            share_target: ProofOfWork::target_for_bits(share_target_bits),
+            state: Mutex::new(MiningPoolState {
+                workers: HashMap::new(),
+                next_worker_id: 0,
+                jobs: HashMap::new(),
+                next_job_id: 0,
+                last_job: Instant::now(),
+                last_job_unshared: true,
+                round_shares: BTreeMap::new(),
+                total_shares: BTreeMap::new(),
+                seen_shares: HashSet::new(),
+                last_share: Instant::now(),
+            }),
+            stats: MiningStats::new(),
+        }
+    }
+
+    /// Accepts workers on `addr` forever, handing every block the pool finds
+    /// to `on_block` after connecting it.
+    pub fn run<F>(&'static self, addr: &str, on_block: F)
+    where
+        F: Fn(&Block) + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind(addr).unwrap();
+        info!("Mining pool listening on {}", addr);
+        let on_block = Arc::new(on_block);
+        for stream in listener.incoming() {
+            let on_block = on_block.clone();
+            thread::spawn(move || match stream {
+                Ok(stream) => {
+                    if let Err(e) = self.serve_worker(stream, on_block.as_ref()) {
+                        error!("Error on serving worker: {}", e);
+                    }
+                }
+                Err(e) => {
+                    error!("Worker connection failed: {}", e);
+                }
+            });
+        }
+    }
+
+    /// Called when a transaction enters the memory pool, so the next job
+    /// includes it.
+    pub fn notify_transaction(&self) {
+        self.broadcast_job(false);
+    }
+
+    /// Called when a block arrives from the network. Outstanding jobs are
+    /// stale and the round restarts.
+    pub fn notify_block(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.jobs.clear();
+        state.seen_shares.clear();
+        state.round_shares.clear();
+        drop(state);
+        self.broadcast_job(true);
+    }
+
+    /// Counters for the workers together, with the hashrate estimated from
+    /// the share rate.
+    pub fn get_stats(&self) -> &MiningStats {
+        &self.stats
+    }
+
+    /// Shares credited to each worker address since the pool started.
+    pub fn get_shares(&self) -> BTreeMap<String, u64> {
+        self.state.lock().unwrap().total_shares.clone()
+    }
+
+    fn serve_worker(
+        &self,
+        stream: TcpStream,
+        on_block: &dyn Fn(&Block),
+    ) -> Result<(), Box<dyn Error>> {
+        let peer_addr = stream.peer_addr()?;
+        let mut worker_id = None;
+        for line in BufReader::new(stream.try_clone()?).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let message: PoolMessage = serde_json::from_str(line.as_str())?;
+            match (message, &worker_id) {
+                (PoolMessage::Subscribe { address }, None) => {
+                    if !validate_address(address.as_str()) {
+                        return Err(format!("invalid worker address {}", address).into());
+                    }
+                    let writer = Arc::new(Mutex::new(stream.try_clone()?));
+                    // Held until the first job is sent, so that broadcasts
+                    // reach this worker only after it.
+                    let mut writer_stream = writer.lock().unwrap();
+                    let mut state = self.state.lock().unwrap();
+                    let id = state.next_worker_id;
+                    state.next_worker_id += 1;
+                    let extranonce_start = id as u64 * EXTRANONCE_RANGE;
+                    info!(
+                        "Worker {} subscribed from {} for {}",
+                        id, peer_addr, address
+                    );
+                    let (job_id, notify) = self.new_job(&mut state, true);
+                    state.workers.insert(
+                        id,
+                        PoolWorker {
+                            address,
+                            extranonce_start,
+                            stream: writer.clone(),
+                        },
+                    );
+                    drop(state);
+                    send_message(
+                        &mut writer_stream,
+                        &PoolMessage::Subscribed {
+                            extranonce_start,
+                            extranonce_range: EXTRANONCE_RANGE,
+                            share_target: HEXLOWER.encode(&self.share_target),
+                        },
+                    );
+                    send_message(&mut writer_stream, &notify);
+                    info!("Sent job {} to worker {}", job_id, id);
+                    drop(writer_stream);
+                    worker_id = Some((id, writer));
+                }
+                (
+                    PoolMessage::Submit {
+                        job_id,
+                        extra_nonce,
+                        timestamp,
+                        nonce,
+                    },
+                    Some((id, writer)),
+                ) => {
+                    let result =
+                        self.submit_share(*id, job_id, extra_nonce, timestamp, nonce, on_block);
+                    send_message(&mut writer.lock().unwrap(), &result);
+                }
+                (message, _) => {
+                    return Err(format!("unexpected message {:?}", message).into());
+                }
+            }
+        }
+        if let Some((id, _)) = worker_id {
+            self.state.lock().unwrap().workers.remove(&id);
+            info!("Worker {} disconnected", id);
+        }
+        Ok(())
+    }
+
+    fn submit_share(
+        &self,
+        worker_id: usize,
+        job_id: String,
+        extra_nonce: u64,
+        timestamp: i64,
+        nonce: i64,
+        on_block: &dyn Fn(&Block),
+    ) -> PoolMessage {
+        let rejected = |reason: &str| PoolMessage::SubmitResult {
+            accepted: false,
+            block_found: false,
+            reason: Some(reason.to_string()),
+        };
+
+        let now = current_timestamp();
+        if timestamp <= self.blockchain.get_median_time_past()
+            || timestamp > now + MAX_FUTURE_BLOCK_TIME
+        {
+            return rejected("timestamp out of range");
+        }
+
+        let mut state = self.state.lock().unwrap();
+        let worker = &state.workers[&worker_id];
+        let address = worker.address.clone();
+        if extra_nonce < worker.extranonce_start
+            || extra_nonce - worker.extranonce_start >= EXTRANONCE_RANGE
+        {
+            return rejected("extra nonce outside the assigned range");
+        }
+        let Some(job) = state
+            .jobs
+            .get(&job_id)
+            .filter(|job| job.template.get_prev_block_hash() == self.blockchain.get_tip_hash())
+        else {
+            return rejected("unknown or stale job");
+        };
+        let mut coinbase = job.coinbase.clone();
+        coinbase.set_extra_nonce(extra_nonce);
+        let mut block = job.template.new_block(coinbase);
+        block.set_timestamp(timestamp);
+
+        let hash = ProofOfWork::new_proof_of_work(block.clone()).hash_header(nonce);
+        if hash >= self.share_target {
+            return rejected("share does not meet the share target");
+        }
+        if !state
+            .seen_shares
+            .insert((job_id.clone(), extra_nonce, timestamp, nonce))
+        {
+            return rejected("duplicate share");
+        }
+        *state.round_shares.entry(address.clone()).or_default() += 1;
+        *state.total_shares.entry(address.clone()).or_default() += 1;
+        let now = Instant::now();
+        self.stats
+            .record_hashes(1 << self.share_target_bits, now - state.last_share);
+        state.last_share = now;
+        info!("Accepted share from {} for job {}", address, job_id);
+
+        let mut block_found = false;
+        if hash < ProofOfWork::target() {
+            block.seal(nonce, HEXLOWER.encode(hash.as_slice()));
+            match self.blockchain.connect_block(&block) {
+                Ok(()) => {
+                    self.mempool.remove_block_transactions(&block);
+                    info!(
+                        "Pool found block {} at height {}",
+                        block.get_hash(),
+                        block.get_height()
+                    );
+                    self.stats.record_block_found();
+                    block_found = true;
+                }
+                Err(reason) => {
+                    if reason == BlockError::NotOnTip {
+                        self.stats.record_stale_block();
+                    }
+                    info!("Pool block {} refused: {}", block.get_hash(), reason)
+                }
+            }
+        }
+
+        if block_found {
+            state.jobs.clear();
+            state.seen_shares.clear();
+            state.round_shares.clear();
+        }
+        // The payout split changed, but a new job for every share would
+        // flood the workers.
+        let refresh = block_found
+            || state.last_job_unshared
+            || state.last_job.elapsed() >= JOB_REFRESH_INTERVAL;
+        drop(state);
+        if block_found {
+            on_block(&block);
+        }
+        if refresh {
+            self.broadcast_job(block_found);
+        }
+
+        PoolMessage::SubmitResult {
+            accepted: true,
+            block_found,
+            reason: None,
+        }
+    }
+
+    fn broadcast_job(&self, clean_jobs: bool) {
+        let mut state = self.state.lock().unwrap();
+        if state.workers.is_empty() {
+            return;
+        }
+        let (job_id, notify) = self.new_job(&mut state, clean_jobs);
+        let streams: Vec<Arc<Mutex<TcpStream>>> = state
+            .workers
+            .values()
+            .map(|worker| worker.stream.clone())
+            .collect();
+        drop(state);
+        info!("Broadcasting job {} to {} workers", job_id, streams.len());
+        for stream in streams {
+            send_message(&mut stream.lock().unwrap(), &notify);
+        }
+    }
+
+    /// Builds a job on the current tip and drops the jobs, and their seen
+    /// shares, built on an older one.
+    fn new_job(&self, state: &mut MiningPoolState, clean_jobs: bool) -> (String, PoolMessage) {
+        let template = BlockTemplate::new(&self.blockchain, self.mempool);
+        let tip_hash = template.get_prev_block_hash().to_string();
+        state
+            .jobs
+            .retain(|_, job| job.template.get_prev_block_hash() == tip_hash);
+        let jobs = &state.jobs;
+        state
+            .seen_shares
+            .retain(|(job_id, _, _, _)| jobs.contains_key(job_id));
+        // Templates keep the coinbase value in range, and a coinbase may
+        // always pay less than it is allowed to.
+        let value = i32::try_from(template.get_coinbase_value()).unwrap_or(i32::MAX);
+        let outputs = payout_outputs(value, &state.round_shares, self.operator_address.as_str());
+        let coinbase = Transaction::new_coinbase_tx_with_outputs(outputs.as_slice());
+
+        let job_id = format!("{:x}", state.next_job_id);
+        state.next_job_id += 1;
+        state.last_job = Instant::now();
+        state.last_job_unshared = state.round_shares.is_empty();
+        let notify = PoolMessage::Notify {
+            job_id: job_id.clone(),
+            template: template.serialize(),
+            coinbase: coinbase.serialize(),
+            clean_jobs,
+        };
+        state
+            .jobs
+            .insert(job_id.clone(), Job { template, coinbase });
+        (job_id, notify)
+    }
+}
+
+/// Splits `value` over `shares` in proportion, rounding down, and gives the
+/// remainder to `operator_address`.
+fn payout_outputs(
+    value: i32,
+    shares: &BTreeMap<String, u64>,
+    operator_address: &str,
+) -> Vec<(String, i32)> {
+    let total: u64 = shares.values().sum();
+    let mut outputs = vec![];
+    let mut paid = 0;
+    if total > 0 {
+        for (address, count) in shares {
+            // Computed wide, as `value * count` overflows an i32, but never
+            // more than `value` itself.
+            let amount = i64::from(value) * *count as i64 / total as i64;
+            let amount = i32::try_from(amount).expect("Share above the coinbase value");
+            if amount > 0 {
+                outputs.push((address.clone(), amount));
+                paid += amount;
+            }
+        }
+    }
+    if value > paid {
+        match outputs
+            .iter_mut()
+            .find(|(address, _)| address == operator_address)
+        {
+            Some((_, amount)) => *amount += value - paid,
+            None => outputs.push((operator_address.to_string(), value - paid)),
+        }
+    }
+    outputs
+}
+
+fn send_message(stream: &mut TcpStream, message: &PoolMessage) {
+    let mut line = serde_json::to_vec(message).unwrap();
+    line.push(b'\n');
+    if let Err(e) = stream.write_all(line.as_slice()) {
+        error!("Failed to send pool message: {}", e);
+    }
+}
+
+/// Worker side of the pool protocol.
+pub struct PoolClient {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl PoolClient {
+    /// Connects to the pool at `addr` and subscribes with the payout
+    /// `address`. The first message read afterwards is `Subscribed`.
+    pub fn connect(addr: &str, address: &str) -> std::io::Result<PoolClient> {
+        let stream = TcpStream::connect(addr)?;
+        let mut client = PoolClient {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+        };
+        client.send(&PoolMessage::Subscribe {
+            address: address.to_string(),
+        });
+        Ok(client)
+    }
+
+    /// A second handle on the same connection, so one thread can read pool
+    /// messages while another submits shares. Messages this handle has
+    /// already buffered are not seen by the clone, so clone before reading.
+    pub fn try_clone(&self) -> std::io::Result<PoolClient> {
+        Ok(PoolClient {
+            reader: BufReader::new(self.writer.try_clone()?),
+            writer: self.writer.try_clone()?,
+        })
+    }
+
+    pub fn send(&mut self, message: &PoolMessage) {
+        send_message(&mut self.writer, message);
+    }
+
+    /// Blocks until the pool sends a message, or returns `None` once the
+    /// connection is closed.
+    pub fn next_message(&mut self) -> Option<PoolMessage> {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if self.reader.read_line(&mut line).ok()? == 0 {
+                return None;
+            }
+            if !line.trim().is_empty() {
+                return serde_json::from_str(line.as_str()).ok();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::payout_outputs;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_payout_outputs() {
+        let mut shares = BTreeMap::new();
+        assert_eq!(
+            payout_outputs(10, &shares, "operator"),
+            vec![("operator".to_string(), 10)]
+        );
+
+        shares.insert("a".to_string(), 3);
+        shares.insert("b".to_string(), 1);
+        assert_eq!(
+            payout_outputs(10, &shares, "operator"),
+            vec![
+                ("a".to_string(), 7),
+                ("b".to_string(), 2),
+                ("operator".to_string(), 1)
+            ]
+        );
+
+        shares.insert("operator".to_string(), 1);
+        let outputs = payout_outputs(10, &shares, "operator");
+        assert_eq!(outputs.iter().map(|(_, value)| value).sum::<i32>(), 10);
+        assert_eq!(outputs.len(), 3);
+
+        let outputs = payout_outputs(i32::MAX, &shares, "operator");
+        assert_eq!(
+            outputs.iter().map(|(_, value)| *value as i64).sum::<i64>(),
+            i32::MAX as i64
+        );
+    }
+}
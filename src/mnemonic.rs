@@ -0,0 +1,186 @@
+use std::fmt;
+use std::num::NonZeroU32;
+
+use once_cell::sync::Lazy;
+use ring::pbkdf2;
+use ring::rand::{SecureRandom, SystemRandom};
+
+/// The BIP39 English wordlist, sorted.
+static WORDS: Lazy<Vec<&'static str>> =
+    Lazy::new(|| include_str!("mnemonic_english.txt").lines().collect());
+
+const BITS_PER_WORD: usize = 11;
+const SEED_ITERATIONS: u32 = 2048;
+pub const SEED_LEN: usize = 64;
+
+#[derive(Debug, PartialEq)]
+pub enum MnemonicError {
+    /// Mnemonics have 12, 15, 18, 21 or 24 words.
+    WordCount(usize),
+    UnknownWord(String),
+    /// The last bits do not match the hash of the entropy, usually a typo.
+    Checksum,
+}
+
+impl fmt::Display for MnemonicError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MnemonicError::WordCount(count) => write!(
+                f,
+                "a mnemonic has 12, 15, 18, 21 or 24 words, not {}",
+                count
+            ),
+            MnemonicError::UnknownWord(word) => write!(f, "{} is not a mnemonic word", word),
+            MnemonicError::Checksum => write!(f, "mnemonic checksum does not match"),
+        }
+    }
+}
+
+/// A BIP39 mnemonic: random entropy followed by the first bits of its
+/// SHA-256, written as words of 11 bits each.
+pub struct Mnemonic {
+    entropy: Vec<u8>,
+}
+
+impl Mnemonic {
+    pub fn generate(word_count: usize) -> Result<Mnemonic, MnemonicError> {
+        let mut entropy = vec![0; entropy_len(word_count)?];
+        SystemRandom::new().fill(&mut entropy).unwrap();
+        Ok(Mnemonic { entropy })
+    }
+
+    pub fn from_entropy(entropy: &[u8]) -> Result<Mnemonic, MnemonicError> {
+        let word_count = entropy.len() * 8 * 33 / 32 / BITS_PER_WORD;
+        if entropy_len(word_count)? != entropy.len() {
+            return Err(MnemonicError::WordCount(word_count));
+        }
+        Ok(Mnemonic {
+            entropy: entropy.to_vec(),
+        })
+    }
+
+    pub fn from_phrase(phrase: &str) -> Result<Mnemonic, MnemonicError> {
+        let words: Vec<&str> = phrase.split_whitespace().collect();
+        let entropy_len = entropy_len(words.len())?;
+        let mut bits = vec![];
+        for word in words {
+            let index = WORDS
+                .binary_search(&word.to_lowercase().as_str())
+                .map_err(|_| MnemonicError::UnknownWord(word.to_string()))?;
+            bits.extend((0..BITS_PER_WORD).rev().map(|bit| (index >> bit) & 1 == 1));
+        }
+        let bytes = from_bits(&bits);
+        let mnemonic = Mnemonic {
+            entropy: bytes[..entropy_len].to_vec(),
+        };
+        if mnemonic.to_bits() != bits {
+            return Err(MnemonicError::Checksum);
+        }
+        Ok(mnemonic)
+    }
+
+    pub fn get_entropy(&self) -> &[u8] {
+        self.entropy.as_slice()
+    }
+
+    pub fn get_phrase(&self) -> String {
+        let words: Vec<&str> = self
+            .to_bits()
+            .chunks(BITS_PER_WORD)
+            .map(|chunk| {
+                let index = chunk
+                    .iter()
+                    .fold(0, |index, bit| index << 1 | *bit as usize);
+                WORDS[index]
+            })
+            .collect();
+        words.join(" ")
+    }
+
+    /// The 64 byte seed: PBKDF2-HMAC-SHA512 of the phrase, salted with
+    /// "mnemonic" and an optional passphrase.
+    pub fn to_seed(&self, passphrase: &str) -> Vec<u8> {
+        let salt = format!("mnemonic{}", passphrase);
+        let mut seed = vec![0; SEED_LEN];
+        pbkdf2::derive(
+            pbkdf2::PBKDF2_HMAC_SHA512,
+            NonZeroU32::new(SEED_ITERATIONS).unwrap(),
+            salt.as_bytes(),
+            self.get_phrase().as_bytes(),
+            &mut seed,
+        );
+        seed
+    }
+
+    fn to_bits(&self) -> Vec<bool> {
+        let checksum = crate::sha256_digest(self.entropy.as_slice());
+        let mut bits = to_bits(self.entropy.as_slice());
+        bits.extend(
+            to_bits(checksum.as_slice())
+                .iter()
+                .take(self.entropy.len() / 4),
+        );
+        bits
+    }
+}
+
+fn entropy_len(word_count: usize) -> Result<usize, MnemonicError> {
+    match word_count {
+        12 | 15 | 18 | 21 | 24 => Ok(word_count * BITS_PER_WORD * 32 / 33 / 8),
+        _ => Err(MnemonicError::WordCount(word_count)),
+    }
+}
+
+fn to_bits(bytes: &[u8]) -> Vec<bool> {
+    bytes
+        .iter()
+        .flat_map(|byte| (0..8).rev().map(move |bit| (byte >> bit) & 1 == 1))
+        .collect()
+}
+
+fn from_bits(bits: &[bool]) -> Vec<u8> {
+    bits.chunks(8)
+        .map(|chunk| chunk.iter().fold(0, |byte, bit| byte << 1 | *bit as u8))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Mnemonic, MnemonicError};
+    use data_encoding::HEXLOWER;
+
+    #[test]
+    fn test_mnemonic() {
+        let mnemonic = Mnemonic::from_entropy(&[0; 16]).unwrap();
+        let phrase = format!("{} about", "abandon ".repeat(11).trim());
+        assert_eq!(mnemonic.get_phrase(), phrase);
+        assert_eq!(
+            HEXLOWER.encode(mnemonic.to_seed("TREZOR").as_slice()),
+            "c55257c360c07c72029aebc1b53c05ed0362ada38ead3e3e9efa3708e5349553\
+             1f09a6987599d18264c1e1c92f2cf141630c7a3c4ab7c81b2f001698e7463b04"
+        );
+        let parsed = Mnemonic::from_phrase(&phrase.to_uppercase()).unwrap();
+        assert_eq!(parsed.get_entropy(), &[0; 16]);
+
+        let mnemonic = Mnemonic::generate(24).unwrap();
+        assert_eq!(mnemonic.get_entropy().len(), 32);
+        let restored = Mnemonic::from_phrase(mnemonic.get_phrase().as_str()).unwrap();
+        assert_eq!(restored.get_entropy(), mnemonic.get_entropy());
+
+        let wrong_checksum = "abandon ".repeat(12);
+        assert_eq!(
+            Mnemonic::from_phrase(&wrong_checksum).err(),
+            Some(MnemonicError::Checksum)
+        );
+        assert_eq!(
+            Mnemonic::from_phrase("abandon about").err(),
+            Some(MnemonicError::WordCount(2))
+        );
+        let unknown = phrase.replace("about", "socratix");
+        assert_eq!(
+            Mnemonic::from_phrase(&unknown).err(),
+            Some(MnemonicError::UnknownWord(String::from("socratix")))
+        );
+        assert!(Mnemonic::generate(13).is_err());
+    }
+}
@@ -0,0 +1,429 @@
+use crate::coin_selection::Selection;
+use crate::script::{Op, Script};
+use crate::transaction::{SigHashType, TXInput, TXOutput};
+use crate::{Transaction, UTXOSet, Wallet, hash_pub_key};
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use data_encoding::HEXLOWER;
+use serde::{Deserialize, Serialize};
+
+/// Leads every serialized partial transaction, followed by the format
+/// version, so a file can be recognised before it is decoded.
+const FILE_MAGIC: &[u8] = b"SCTXPART";
+const FILE_VERSION: u8 = 1;
+
+/// A transaction whose signatures are being collected, possibly away from
+/// the machine that built it. It carries the outputs its inputs spend, so
+/// signers need neither the blockchain nor the UTXO set: every signer adds
+/// theirs to a copy with `sign`, the copies are merged with `combine`, and
+/// once enough signatures are in, `finalize` builds the unlocking scripts.
+///
+/// Inputs spend either pay to public key hash outputs, signed by the key
+/// with that hash, or pay to script hash outputs of a multisig redeem
+/// script.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PartialTransaction {
+    tx: Transaction,
+    /// Outputs spent by the inputs, in input order.
+    prev_outputs: Vec<TXOutput>,
+    /// Redeem script behind the pay to script hash output of each input,
+    /// none for inputs spending from an address.
+    redeem_scripts: Vec<Option<Script>>,
+    /// Signatures collected for each input, by public key.
+    signatures: Vec<BTreeMap<Vec<u8>, Vec<u8>>>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PartialError {
+    /// The copies being combined are not of the same transaction.
+    DifferentTransaction,
+    /// The wallet holds none of the keys the inputs need signatures from.
+    NotASigner,
+    Incomplete {
+        missing: usize,
+    },
+    /// The finished transaction does not verify.
+    InvalidSignatures,
+    /// The bytes are not a partial transaction of a known version.
+    InvalidFile,
+}
+
+impl fmt::Display for PartialError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PartialError::DifferentTransaction => {
+                write!(f, "partial transactions spend different transactions")
+            }
+            PartialError::NotASigner => write!(f, "wallet is not a signer of any input"),
+            PartialError::Incomplete { missing } => {
+                write!(f, "{} more signatures are needed", missing)
+            }
+            PartialError::InvalidSignatures => write!(f, "signatures do not verify"),
+            PartialError::InvalidFile => write!(f, "not a partial transaction file"),
+        }
+    }
+}
+
+impl PartialTransaction {
+    /// Starts collecting signatures for `tx`, whose inputs spend
+    /// `prev_outputs`, those paying to script hash locked to the hashes of
+    /// `redeem_scripts`.
+    pub fn new(
+        tx: Transaction,
+        prev_outputs: Vec<TXOutput>,
+        redeem_scripts: Vec<Option<Script>>,
+    ) -> PartialTransaction {
+        let inputs = tx.get_vin().len();
+        if prev_outputs.len() != inputs || redeem_scripts.len() != inputs {
+            panic!("Every input needs its previous output and redeem script")
+        }
+        for (prev_output, redeem_script) in prev_outputs.iter().zip(&redeem_scripts) {
+            let script_pubkey = prev_output.get_script_pubkey();
+            match redeem_script {
+                None if script_pubkey.get_pub_key_hash().is_some() => {}
+                None => panic!("Only outputs paying to an address or script hash can be signed"),
+                Some(redeem_script) => {
+                    if script_pubkey.get_script_hash() != Some(&redeem_script.hash()) {
+                        panic!("Redeem script does not match the output it spends")
+                    }
+                    if redeem_script.get_multisig().is_none() {
+                        panic!("Only multisig redeem scripts can be signed")
+                    }
+                }
+            }
+        }
+        PartialTransaction {
+            tx,
+            prev_outputs,
+            redeem_scripts,
+            signatures: vec![BTreeMap::new(); inputs],
+        }
+    }
+
+    /// Builds an unsigned payment of `amount` to `to` from the coins of
+    /// `selection`, returning the change to `from`. Only the address is
+    /// needed, so a watch-only machine can build it for the one holding the
+    /// key to sign.
+    pub fn new_spend(
+        from: &str,
+        to: &str,
+        amount: i32,
+        selection: &Selection,
+    ) -> PartialTransaction {
+        let mut inputs = vec![];
+        let mut prev_outputs = vec![];
+        for coin in selection.get_coins() {
+            inputs.push(TXInput::new(coin.get_txid(), coin.get_vout()));
+            prev_outputs.push(coin.get_output().clone());
+        }
+        let mut outputs = vec![TXOutput::new(amount, to)];
+        if selection.get_change() > 0 {
+            outputs.push(TXOutput::new(selection.get_change(), from));
+        }
+        let redeem_scripts = vec![None; inputs.len()];
+        PartialTransaction::new(
+            Transaction::new(inputs, outputs),
+            prev_outputs,
+            redeem_scripts,
+        )
+    }
+
+    /// Builds a spend of `amount` to `to` from the pay to script hash
+    /// address of `redeem_script`, returning the change to that address.
+    pub fn new_multisig_spend(
+        redeem_script: Script,
+        to: &str,
+        amount: i32,
+        fee: i32,
+        utxo_set: &UTXOSet,
+    ) -> PartialTransaction {
+        let lock = Script::new_p2sh(redeem_script.hash().as_slice());
+        let mut accumulated = 0;
+        let mut inputs = vec![];
+        let mut prev_outputs = vec![];
+        for (txid, vout, output) in utxo_set.find_outputs_locked_by(&lock) {
+            if accumulated >= amount + fee {
+                break;
+            }
+            accumulated += output.get_value();
+            inputs.push(TXInput::new(txid.as_slice(), vout));
+            prev_outputs.push(output);
+        }
+        if accumulated < amount + fee {
+            panic!("Not enough funds")
+        }
+
+        let mut outputs = vec![TXOutput::new(amount, to)];
+        if accumulated > amount + fee {
+            outputs.push(TXOutput::with_script(accumulated - amount - fee, lock));
+        }
+        let redeem_scripts = vec![Some(redeem_script); inputs.len()];
+        PartialTransaction::new(
+            Transaction::new(inputs, outputs),
+            prev_outputs,
+            redeem_scripts,
+        )
+    }
+
+    /// Adds the signature of `wallet` to every input it is a signer of and
+    /// returns how many inputs it signed.
+    pub fn sign(&mut self, wallet: &Wallet) -> Result<usize, PartialError> {
+        let mut signed = 0;
+        for idx in 0..self.redeem_scripts.len() {
+            if !self.is_signer(idx, wallet.get_public_key()) {
+                continue;
+            }
+            let script_code = self.prev_outputs[idx].get_script_pubkey();
+            let signature = self
+                .tx
+                .create_signature(idx, script_code, wallet, SigHashType::ALL);
+            self.signatures[idx].insert(wallet.get_public_key().to_vec(), signature);
+            signed += 1;
+        }
+        if signed == 0 {
+            return Err(PartialError::NotASigner);
+        }
+        Ok(signed)
+    }
+
+    /// Merges the signatures collected in another copy of the transaction.
+    pub fn combine(&mut self, other: &PartialTransaction) -> Result<(), PartialError> {
+        if self.tx.get_id() != other.tx.get_id() {
+            return Err(PartialError::DifferentTransaction);
+        }
+        for (signatures, others) in self.signatures.iter_mut().zip(&other.signatures) {
+            for (pub_key, signature) in others {
+                signatures
+                    .entry(pub_key.clone())
+                    .or_insert_with(|| signature.clone());
+            }
+        }
+        Ok(())
+    }
+
+    /// Signatures still needed over all inputs.
+    pub fn get_missing_signatures(&self) -> usize {
+        (0..self.redeem_scripts.len())
+            .map(|idx| {
+                self.get_required(idx)
+                    .saturating_sub(self.signatures[idx].len())
+            })
+            .sum()
+    }
+
+    /// Builds the unlocking scripts from the collected signatures, in the
+    /// order of the keys in each redeem script, and checks the result.
+    pub fn finalize(&self) -> Result<Transaction, PartialError> {
+        let missing = self.get_missing_signatures();
+        if missing > 0 {
+            return Err(PartialError::Incomplete { missing });
+        }
+        let mut tx = self.tx.clone();
+        for (idx, redeem_script) in self.redeem_scripts.iter().enumerate() {
+            let script_sig = match redeem_script {
+                None => {
+                    let (pub_key, signature) = self.signatures[idx].iter().next().unwrap();
+                    Script::new_p2pkh_unlock(signature, pub_key)
+                }
+                Some(redeem_script) => {
+                    let (required, pub_keys) = redeem_script.get_multisig().unwrap();
+                    let mut ops: Vec<Op> = pub_keys
+                        .iter()
+                        .filter_map(|pub_key| self.signatures[idx].get(*pub_key))
+                        .take(required)
+                        .map(|signature| Op::Push(signature.clone()))
+                        .collect();
+                    ops.push(Op::Push(redeem_script.serialize()));
+                    Script::new(ops)
+                }
+            };
+            tx.set_script_sig(idx, script_sig);
+        }
+        if !tx.verify_with_outputs(self.prev_outputs.as_slice()) {
+            return Err(PartialError::InvalidSignatures);
+        }
+        Ok(tx)
+    }
+
+    /// Whether `pub_key` is one of the keys input `idx` needs a signature
+    /// from.
+    fn is_signer(&self, idx: usize, pub_key: &[u8]) -> bool {
+        match &self.redeem_scripts[idx] {
+            Some(redeem_script) => redeem_script.get_multisig().unwrap().1.contains(&pub_key),
+            None => self.prev_outputs[idx].is_locked_with_key(hash_pub_key(pub_key).as_slice()),
+        }
+    }
+
+    fn get_required(&self, idx: usize) -> usize {
+        match &self.redeem_scripts[idx] {
+            Some(redeem_script) => redeem_script.get_multisig().unwrap().0,
+            None => 1,
+        }
+    }
+
+    pub fn get_transaction(&self) -> &Transaction {
+        &self.tx
+    }
+
+    pub fn get_prev_outputs(&self) -> &[TXOutput] {
+        self.prev_outputs.as_slice()
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut bytes = FILE_MAGIC.to_vec();
+        bytes.push(FILE_VERSION);
+        bytes.extend(bincode::serialize(self).unwrap());
+        bytes
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<PartialTransaction, PartialError> {
+        let body = bytes
+            .strip_prefix(FILE_MAGIC)
+            .and_then(|rest| rest.strip_prefix(&[FILE_VERSION]))
+            .ok_or(PartialError::InvalidFile)?;
+        bincode::deserialize(body).map_err(|_| PartialError::InvalidFile)
+    }
+}
+
+/// Shows what signing commits to: the coins spent, where they go and the
+/// fee, along with the signatures still missing.
+impl fmt::Display for PartialTransaction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Txid: {}", HEXLOWER.encode(self.tx.get_id()))?;
+        writeln!(
+            f,
+            "Unsigned size: {} bytes, weight: {}",
+            self.tx.get_size(),
+            self.tx.get_weight()
+        )?;
+        for (idx, (vin, prev_output)) in
+            self.tx.get_vin().iter().zip(&self.prev_outputs).enumerate()
+        {
+            writeln!(
+                f,
+                "Input {}: {}:{} value = {}, from = {}, signatures = {} of {}",
+                idx,
+                HEXLOWER.encode(vin.get_txid()),
+                vin.get_vout(),
+                prev_output.get_value(),
+                describe_lock(prev_output),
+                self.signatures[idx].len(),
+                self.get_required(idx),
+            )?;
+        }
+        for (idx, output) in self.tx.get_vout().iter().enumerate() {
+            writeln!(
+                f,
+                "Output {}: value = {}, to = {}",
+                idx,
+                output.get_value(),
+                describe_lock(output),
+            )?;
+        }
+        write!(f, "Fee: {}", self.tx.get_fee(self.prev_outputs.as_slice()))
+    }
+}
+
+fn describe_lock(output: &TXOutput) -> String {
+    let script_pubkey = output.get_script_pubkey();
+    script_pubkey
+        .get_address()
+        .unwrap_or_else(|| script_pubkey.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PartialError, PartialTransaction};
+    use crate::coin_selection::{Coin, LargestFirst, Selection};
+    use crate::script::Script;
+    use crate::transaction::{TXInput, TXOutput};
+    use crate::{Transaction, Wallet};
+
+    #[test]
+    fn test_collect_multisig_signatures() {
+        let signers: Vec<Wallet> = (0..3).map(|_| Wallet::new()).collect();
+        let pub_keys: Vec<Vec<u8>> = signers
+            .iter()
+            .map(|wallet| wallet.get_public_key().to_vec())
+            .collect();
+        let redeem_script = Script::new_multisig(2, pub_keys.as_slice());
+        let funding = TXOutput::with_script(10, Script::new_p2sh(&redeem_script.hash()));
+        let to = Wallet::new().get_address();
+        let tx = Transaction::new(
+            vec![TXInput::new(b"funding", 0)],
+            vec![TXOutput::new(9, to.as_str())],
+        );
+        let unsigned = PartialTransaction::new(tx, vec![funding], vec![Some(redeem_script)]);
+        assert_eq!(unsigned.get_missing_signatures(), 2);
+
+        // Signers work on their own copies, in any order.
+        let mut first = unsigned.clone();
+        assert_eq!(first.sign(&signers[2]), Ok(1));
+        let mut second = unsigned.clone();
+        assert_eq!(second.sign(&signers[0]), Ok(1));
+        assert_eq!(
+            first.finalize().err(),
+            Some(PartialError::Incomplete { missing: 1 })
+        );
+        assert_eq!(
+            first.clone().sign(&Wallet::new()),
+            Err(PartialError::NotASigner)
+        );
+
+        first.combine(&second).unwrap();
+        assert_eq!(first.get_missing_signatures(), 0);
+        let tx = first.finalize().unwrap();
+        assert_eq!(tx.get_id(), unsigned.get_transaction().get_id());
+
+        let other = Transaction::new(
+            vec![TXInput::new(b"other", 0)],
+            vec![TXOutput::new(9, to.as_str())],
+        );
+        let other = PartialTransaction::new(
+            other,
+            vec![unsigned.prev_outputs[0].clone()],
+            unsigned.redeem_scripts.clone(),
+        );
+        assert_eq!(
+            first.combine(&other),
+            Err(PartialError::DifferentTransaction)
+        );
+    }
+
+    #[test]
+    fn test_sign_offline() {
+        let (owner, to) = (Wallet::new(), Wallet::new().get_address());
+        let from = owner.get_address();
+        let coins = vec![
+            Coin::new(b"funding", 0, TXOutput::new(4, from.as_str())),
+            Coin::new(b"funding", 1, TXOutput::new(10, from.as_str())),
+        ];
+        let selection = Selection::new(&LargestFirst, coins.as_slice(), 7, 1).unwrap();
+        let unsigned = PartialTransaction::new_spend(from.as_str(), to.as_str(), 7, &selection);
+        assert_eq!(unsigned.get_prev_outputs().len(), 1);
+        assert_eq!(unsigned.get_missing_signatures(), 1);
+
+        // The signer only has the file and the wallet.
+        let mut signed = PartialTransaction::deserialize(&unsigned.serialize()).unwrap();
+        assert_eq!(signed.sign(&Wallet::new()), Err(PartialError::NotASigner));
+        assert_eq!(signed.sign(&owner), Ok(1));
+        let tx = PartialTransaction::deserialize(&signed.serialize())
+            .unwrap()
+            .finalize()
+            .unwrap();
+        assert!(tx.verify_with_outputs(unsigned.get_prev_outputs()));
+        assert_eq!(tx.get_fee(unsigned.get_prev_outputs()), 1);
+
+        let bytes = signed.serialize();
+        assert_eq!(
+            PartialTransaction::deserialize(&bytes[1..]).err(),
+            Some(PartialError::InvalidFile)
+        );
+        assert_eq!(
+            PartialTransaction::deserialize(&bytes[..bytes.len() / 2]).err(),
+            Some(PartialError::InvalidFile)
+        );
+    }
+}
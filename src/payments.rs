@@ -0,0 +1,207 @@
+use crate::validate_address;
+
+use std::collections::HashSet;
+use std::fmt;
+
+use serde::Deserialize;
+use serde::de::{Deserializer, MapAccess, Visitor};
+
+/// One line of a payment file.
+#[derive(Deserialize)]
+struct Payment {
+    address: String,
+    amount: i32,
+}
+
+/// The object form of a payment file, keeping every entry so that an
+/// address listed twice is not silently merged.
+struct PaymentMap(Vec<(String, serde_json::Value)>);
+
+impl<'de> Deserialize<'de> for PaymentMap {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<PaymentMap, D::Error> {
+        struct EntriesVisitor;
+
+        impl<'de> Visitor<'de> for EntriesVisitor {
+            type Value = PaymentMap;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "an object from addresses to amounts")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<PaymentMap, A::Error> {
+                let mut entries = vec![];
+                while let Some(entry) = map.next_entry()? {
+                    entries.push(entry);
+                }
+                Ok(PaymentMap(entries))
+            }
+        }
+
+        deserializer.deserialize_map(EntriesVisitor)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PaymentsError {
+    /// The JSON does not list payments.
+    InvalidJson(String),
+    /// A CSV line, counted from 1, is not `address,amount`.
+    InvalidLine(usize),
+    InvalidAddress(String),
+    /// Amounts must be positive.
+    InvalidAmount(String),
+    /// An address is a key of the JSON object more than once.
+    DuplicateAddress(String),
+    /// The amounts add up to more than an amount can hold.
+    TotalTooLarge,
+    Empty,
+}
+
+impl fmt::Display for PaymentsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PaymentsError::InvalidJson(err) => write!(f, "invalid payment JSON: {}", err),
+            PaymentsError::InvalidLine(line) => {
+                write!(f, "line {} is not an address and an amount", line)
+            }
+            PaymentsError::InvalidAddress(address) => write!(f, "invalid address {}", address),
+            PaymentsError::InvalidAmount(address) => {
+                write!(f, "amount paid to {} must be positive", address)
+            }
+            PaymentsError::DuplicateAddress(address) => {
+                write!(f, "{} is listed more than once", address)
+            }
+            PaymentsError::TotalTooLarge => write!(f, "payments add up to too much"),
+            PaymentsError::Empty => write!(f, "no payments listed"),
+        }
+    }
+}
+
+/// Reads the `(address, amount)` pairs of a payment file. JSON files hold
+/// either a list of `{"address": ..., "amount": ...}` objects or an object
+/// from addresses to amounts. CSV files hold an `address,amount` line per
+/// payment, after an optional header; blank lines and lines starting with
+/// `#` are skipped.
+pub fn parse_payments(contents: &str) -> Result<Vec<(String, i32)>, PaymentsError> {
+    let payments = match contents.trim_start().chars().next() {
+        Some('[') => serde_json::from_str::<Vec<Payment>>(contents)
+            .map_err(|err| PaymentsError::InvalidJson(err.to_string()))?
+            .into_iter()
+            .map(|payment| (payment.address, payment.amount))
+            .collect(),
+        Some('{') => {
+            let PaymentMap(entries) = serde_json::from_str(contents)
+                .map_err(|err| PaymentsError::InvalidJson(err.to_string()))?;
+            let mut seen = HashSet::new();
+            entries
+                .into_iter()
+                .map(|(address, amount)| {
+                    if !seen.insert(address.clone()) {
+                        return Err(PaymentsError::DuplicateAddress(address));
+                    }
+                    let amount = amount
+                        .as_i64()
+                        .and_then(|amount| i32::try_from(amount).ok())
+                        .ok_or_else(|| PaymentsError::InvalidAmount(address.clone()))?;
+                    Ok((address, amount))
+                })
+                .collect::<Result<_, _>>()?
+        }
+        _ => parse_csv(contents)?,
+    };
+
+    if payments.is_empty() {
+        return Err(PaymentsError::Empty);
+    }
+    let mut total: i32 = 0;
+    for (address, amount) in &payments {
+        if !validate_address(address.as_str()) {
+            return Err(PaymentsError::InvalidAddress(address.clone()));
+        }
+        if *amount <= 0 {
+            return Err(PaymentsError::InvalidAmount(address.clone()));
+        }
+        total = total
+            .checked_add(*amount)
+            .ok_or(PaymentsError::TotalTooLarge)?;
+    }
+    Ok(payments)
+}
+
+fn parse_csv(contents: &str) -> Result<Vec<(String, i32)>, PaymentsError> {
+    let mut payments = vec![];
+    for (idx, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        let [address, amount] = fields.as_slice() else {
+            return Err(PaymentsError::InvalidLine(idx + 1));
+        };
+        match amount.parse() {
+            Ok(amount) => payments.push((address.to_string(), amount)),
+            // A header names the columns instead.
+            Err(_) if payments.is_empty() && amount.eq_ignore_ascii_case("amount") => {}
+            Err(_) => return Err(PaymentsError::InvalidAmount(address.to_string())),
+        }
+    }
+    Ok(payments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PaymentsError, parse_payments};
+    use crate::Wallet;
+
+    #[test]
+    fn test_parse_payments() {
+        let (a, b) = (Wallet::new().get_address(), Wallet::new().get_address());
+        let expected = vec![(a.clone(), 5), (b.clone(), 7)];
+
+        let list = format!(
+            r#"[{{"address": "{}", "amount": 5}}, {{"address": "{}", "amount": 7}}]"#,
+            a, b
+        );
+        assert_eq!(parse_payments(list.as_str()), Ok(expected.clone()));
+        let map = format!(r#"{{"{}": 5, "{}": 7}}"#, a, b);
+        let mut parsed = parse_payments(map.as_str()).unwrap();
+        parsed.sort();
+        let mut sorted = expected.clone();
+        sorted.sort();
+        assert_eq!(parsed, sorted);
+        let csv = format!("address,amount\n{}, 5\n\n# bonus\n{},7\n", a, b);
+        assert_eq!(parse_payments(csv.as_str()), Ok(expected));
+
+        assert_eq!(
+            parse_payments(format!("{},5,extra", a).as_str()),
+            Err(PaymentsError::InvalidLine(1))
+        );
+        assert_eq!(
+            parse_payments(format!("{},0", a).as_str()),
+            Err(PaymentsError::InvalidAmount(a.clone()))
+        );
+        for address in ["nobody", "n0body"] {
+            assert_eq!(
+                parse_payments(format!("{},5", address).as_str()),
+                Err(PaymentsError::InvalidAddress(address.to_string()))
+            );
+        }
+        assert_eq!(
+            parse_payments("address,amount\n"),
+            Err(PaymentsError::Empty)
+        );
+        assert!(matches!(
+            parse_payments("[{\"address\": 1}]"),
+            Err(PaymentsError::InvalidJson(_))
+        ));
+        assert_eq!(
+            parse_payments(format!(r#"{{"{}": 5, "{}": 7}}"#, a, a).as_str()),
+            Err(PaymentsError::DuplicateAddress(a.clone()))
+        );
+        assert_eq!(
+            parse_payments(format!("{},{}\n{},1", a, i32::MAX, b).as_str()),
+            Err(PaymentsError::TotalTooLarge)
+        );
+    }
+}
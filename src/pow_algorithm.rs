@@ -0,0 +1,142 @@
+use crypto::scrypt::{ScryptParams, scrypt};
+use ring::digest::{Context, SHA256, digest};
+
+/// A hash function for proof of work. Mining hashes the same header prefix
+/// with millions of nonces, so an algorithm first absorbs the prefix into a
+/// [`HeaderHasher`] that can cheaply finish each nonce.
+pub trait PowAlgorithm: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn hasher(&self, prefix: &[u8]) -> Box<dyn HeaderHasher>;
+}
+
+/// State left after hashing a header prefix.
+pub trait HeaderHasher: Send + Sync {
+    fn hash_nonce(&self, nonce: i64) -> [u8; 32];
+}
+
+/// Plain SHA-256 of the header, the algorithm of the main chain.
+pub struct Sha256Pow;
+
+/// SHA-256 applied twice, as Bitcoin does.
+pub struct DoubleSha256Pow;
+
+/// Memory-hard scrypt with the header as both password and salt, as
+/// Litecoin does. `log_n` is the log2 of the CPU/memory cost.
+pub struct ScryptPow {
+    pub log_n: u8,
+    pub r: u32,
+    pub p: u32,
+}
+
+struct Sha256Hasher {
+    prefix: Context,
+    rounds: usize,
+}
+
+struct ScryptHasher {
+    prefix: Vec<u8>,
+    params: ScryptParams,
+}
+
+impl PowAlgorithm for Sha256Pow {
+    fn name(&self) -> &'static str {
+        "sha256"
+    }
+
+    fn hasher(&self, prefix: &[u8]) -> Box<dyn HeaderHasher> {
+        Box::new(Sha256Hasher::new(prefix, 1))
+    }
+}
+
+impl PowAlgorithm for DoubleSha256Pow {
+    fn name(&self) -> &'static str {
+        "sha256d"
+    }
+
+    fn hasher(&self, prefix: &[u8]) -> Box<dyn HeaderHasher> {
+        Box::new(Sha256Hasher::new(prefix, 2))
+    }
+}
+
+impl PowAlgorithm for ScryptPow {
+    fn name(&self) -> &'static str {
+        "scrypt"
+    }
+
+    fn hasher(&self, prefix: &[u8]) -> Box<dyn HeaderHasher> {
+        Box::new(ScryptHasher {
+            prefix: prefix.to_vec(),
+            params: ScryptParams::new(self.log_n, self.r, self.p),
+        })
+    }
+}
+
+impl Sha256Hasher {
+    fn new(prefix: &[u8], rounds: usize) -> Sha256Hasher {
+        let mut context = Context::new(&SHA256);
+        context.update(prefix);
+        Sha256Hasher {
+            prefix: context,
+            rounds,
+        }
+    }
+}
+
+impl HeaderHasher for Sha256Hasher {
+    fn hash_nonce(&self, nonce: i64) -> [u8; 32] {
+        let mut context = self.prefix.clone();
+        context.update(nonce.to_be_bytes().as_slice());
+        let mut hash: [u8; 32] = context.finish().as_ref().try_into().unwrap();
+        for _ in 1..self.rounds {
+            hash = digest(&SHA256, hash.as_slice())
+                .as_ref()
+                .try_into()
+                .unwrap();
+        }
+        hash
+    }
+}
+
+impl HeaderHasher for ScryptHasher {
+    fn hash_nonce(&self, nonce: i64) -> [u8; 32] {
+        let mut header = self.prefix.clone();
+        header.extend(nonce.to_be_bytes());
+        let mut hash = [0; 32];
+        scrypt(
+            header.as_slice(),
+            header.as_slice(),
+            &self.params,
+            &mut hash,
+        );
+        hash
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DoubleSha256Pow, PowAlgorithm, ScryptPow, Sha256Pow};
+    use crate::sha256_digest;
+
+    #[test]
+    fn test_algorithms_hash_prefix_and_nonce() {
+        let mut header = b"header".to_vec();
+        header.extend(7i64.to_be_bytes());
+
+        let single = Sha256Pow.hasher(b"header").hash_nonce(7);
+        assert_eq!(single.to_vec(), sha256_digest(header.as_slice()));
+
+        let double = DoubleSha256Pow.hasher(b"header").hash_nonce(7);
+        assert_eq!(double.to_vec(), sha256_digest(single.as_slice()));
+
+        let scrypt = ScryptPow {
+            log_n: 4,
+            r: 1,
+            p: 1,
+        };
+        let hasher = scrypt.hasher(b"header");
+        assert_eq!(hasher.hash_nonce(7), hasher.hash_nonce(7));
+        assert_ne!(hasher.hash_nonce(7), hasher.hash_nonce(8));
+        assert_ne!(hasher.hash_nonce(7), single);
+    }
+}
@@ -1,28 +1,90 @@
-use crate::Block;
+use crate::pow_algorithm::HeaderHasher;
+use crate::{Block, ChainParams, GLOBAL_CONFIG};
 
-use std::borrow::Borrow;
 use std::ops::ShlAssign;
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use data_encoding::HEXLOWER;
-use num_bigint::{BigInt, Sign};
+use log::{debug, info};
+use num_bigint::BigInt;
 
 pub struct ProofOfWork {
     block: Block,
-    target: BigInt,
+    params: &'static ChainParams,
+    target: [u8; 32],
+    threads: usize,
+    max_nonce: i64,
+    hash_count: AtomicU64,
 }
 
-const TARGET_BITS: i32 = 8;
-const MAX_NONCE: i64 = i64::MAX;
+/// Leading zero bits a block hash needs on the main chain.
+pub(crate) const TARGET_BITS: i32 = 8;
+/// Nonces tried for one header before it is rolled.
+const MAX_NONCE: i64 = u32::MAX as i64;
+/// Marks that no worker has found a solution yet.
+const NOT_FOUND: i64 = i64::MAX;
+/// Time between checks of the cancel flag. Counted in time rather than in
+/// nonces, as one scrypt hash takes as long as thousands of SHA-256 ones.
+const CANCEL_CHECK_PERIOD: Duration = Duration::from_millis(10);
 
 impl ProofOfWork {
     pub fn new_proof_of_work(block: Block) -> ProofOfWork {
+        ProofOfWork {
+            block,
+            params: ChainParams::current(),
+            target: ProofOfWork::target(),
+            threads: GLOBAL_CONFIG.get_mining_threads(),
+            max_nonce: MAX_NONCE,
+            hash_count: AtomicU64::new(0),
+        }
+    }
+
+    pub fn with_max_nonce(mut self, max_nonce: i64) -> ProofOfWork {
+        self.max_nonce = max_nonce;
+        self
+    }
+
+    /// Mines or validates under `params` instead of the current chain's.
+    pub fn with_params(mut self, params: &'static ChainParams) -> ProofOfWork {
+        self.params = params;
+        self.target = ProofOfWork::target_for_bits(params.get_target_bits());
+        self
+    }
+
+    /// The big-endian hash a block header must stay below.
+    pub fn target() -> [u8; 32] {
+        ProofOfWork::target_for_bits(ChainParams::current().get_target_bits())
+    }
+
+    /// Expected number of hashes needed to find a block.
+    pub fn difficulty() -> f64 {
+        let target = ProofOfWork::target()
+            .iter()
+            .fold(0.0, |value, byte| value * 256.0 + *byte as f64);
+        2f64.powi(256) / target
+    }
+
+    /// A target requiring `bits` leading zero bits, used for easier targets
+    /// such as pool shares.
+    pub fn target_for_bits(bits: i32) -> [u8; 32] {
         let mut target = BigInt::from(1);
 
-        target.shl_assign(256 - TARGET_BITS);
-        ProofOfWork { block, target }
+        target.shl_assign(256 - bits);
+        let (_, target_bytes) = target.to_bytes_be();
+        let mut target = [0; 32];
+        target[32 - target_bytes.len()..].copy_from_slice(target_bytes.as_slice());
+        target
+    }
+
+    pub fn with_threads(mut self, threads: usize) -> ProofOfWork {
+        self.threads = threads.max(1);
+        self
     }
 
-    fn prepare_data(&self, nonce: i64) -> Vec<u8> {
+    /// Everything hashed for the block except the trailing nonce.
+    fn prepare_header_prefix(&self) -> Vec<u8> {
         let prev_block_hash = self.block.get_prev_block_hash();
         let transaction_hash = self.block.hash_transactions();
         let timestamp = self.block.get_timestamp();
@@ -32,41 +94,220 @@ impl ProofOfWork {
         data_bytes.extend(prev_block_hash.as_bytes());
         data_bytes.extend(transaction_hash);
         data_bytes.extend(timestamp.to_be_bytes());
-        data_bytes.extend(TARGET_BITS.to_be_bytes());
-        data_bytes.extend(nonce.to_be_bytes());
+        data_bytes.extend(self.params.get_target_bits().to_be_bytes());
 
         data_bytes
     }
 
-    pub fn run(&self) -> (i64, String) {
-        let mut nonce = 0;
-        let mut hash = Vec::new();
-        println!("Mining the block...");
+    pub fn run(&mut self) -> Block {
+        self.run_until(&AtomicBool::new(false))
+            .expect("Mining was cancelled")
+    }
+
+    /// Searches for a valid nonce and returns the sealed block, or `None` if
+    /// `cancel` was set first. When every nonce of a header has been tried
+    /// the header is rolled, moving the timestamp forward and changing the
+    /// coinbase extra nonce, and the search starts over.
+    ///
+    /// Worker thread `i` of `n` tries nonces `i`, `i + n`, `i + 2n`, ... and
+    /// stops once it passes the lowest solution found so far, so the result
+    /// is the lowest valid nonce whatever the number of threads.
+    pub fn run_until(&mut self, cancel: &AtomicBool) -> Option<Block> {
+        debug!("Mining block {}", self.block.get_height());
+        let mut extra_nonce = 0;
+
+        let result = loop {
+            let hasher = self.hasher();
+
+            let best_nonce = AtomicI64::new(NOT_FOUND);
+            thread::scope(|scope| {
+                for start in 0..self.threads {
+                    let (pow, hasher, best_nonce) = (&*self, hasher.as_ref(), &best_nonce);
+                    scope.spawn(move || {
+                        pow.search(hasher, start as i64, pow.threads as i64, best_nonce, cancel)
+                    });
+                }
+            });
+
+            let nonce = best_nonce.load(Ordering::SeqCst);
+            if nonce != NOT_FOUND {
+                let hash = hasher.hash_nonce(nonce);
+                break Some((nonce, hash));
+            }
+            if cancel.load(Ordering::Relaxed) {
+                break None;
+            }
+            extra_nonce += 1;
+            self.block.roll_header(extra_nonce);
+        };
+
+        let Some((nonce, hash)) = result else {
+            debug!("Mining of block {} cancelled", self.block.get_height());
+            return None;
+        };
+        info!(
+            "Found hash {} with nonce {}",
+            HEXLOWER.encode(hash.as_slice()),
+            nonce
+        );
+
+        let mut block = self.block.clone();
+        block.seal(nonce, HEXLOWER.encode(hash.as_slice()));
+        Some(block)
+    }
+
+    /// Hashes the block header with `nonce` in place of its own.
+    pub fn hash_header(&self, nonce: i64) -> [u8; 32] {
+        self.hasher().hash_nonce(nonce)
+    }
+
+    fn hasher(&self) -> Box<dyn HeaderHasher> {
+        self.params
+            .get_pow()
+            .hasher(self.prepare_header_prefix().as_slice())
+    }
+
+    /// Checks that the block's nonce meets the target and yields its hash.
+    pub fn validate(&self) -> bool {
+        let hash = self.hash_header(self.block.get_nonce());
 
-        while nonce < MAX_NONCE {
-            let data = self.prepare_data(nonce);
-            hash = crate::sha256_digest(data.as_slice());
-            let hash_int = BigInt::from_bytes_be(Sign::Plus, hash.as_slice());
+        hash < self.target && HEXLOWER.encode(hash.as_slice()).eq(self.block.get_hash())
+    }
 
-            if hash_int.lt(self.target.borrow()) {
-                println!("Hash: {}", HEXLOWER.encode(hash.as_slice()));
-                println!("Nonce: {}", nonce);
+    fn search(
+        &self,
+        hasher: &dyn HeaderHasher,
+        start: i64,
+        step: i64,
+        best_nonce: &AtomicI64,
+        cancel: &AtomicBool,
+    ) {
+        let mut nonce = start;
+        let mut hashes = 0;
+        let mut last_check: Option<Instant> = None;
+        while nonce <= self.max_nonce && nonce < best_nonce.load(Ordering::Relaxed) {
+            if last_check.is_none_or(|checked| checked.elapsed() >= CANCEL_CHECK_PERIOD) {
+                if cancel.load(Ordering::Relaxed) {
+                    break;
+                }
+                last_check = Some(Instant::now());
+            }
+            let hash = hasher.hash_nonce(nonce);
+            hashes += 1;
+            if hash < self.target {
+                best_nonce.fetch_min(nonce, Ordering::SeqCst);
                 break;
-            } else {
-                nonce += 1;
+            }
+            match nonce.checked_add(step) {
+                Some(next) => nonce = next,
+                None => break,
             }
         }
-        println!();
-        (nonce, HEXLOWER.encode(hash.as_slice()))
+        self.hash_count.fetch_add(hashes, Ordering::Relaxed);
+    }
+
+    /// Total number of hashes computed by this search so far.
+    pub fn get_hash_count(&self) -> u64 {
+        self.hash_count.load(Ordering::Relaxed)
     }
 }
 
 #[cfg(test)]
 mod test {
-    use super::TARGET_BITS;
+    use super::{ProofOfWork, TARGET_BITS};
+    use crate::{Block, Transaction, chain_params};
     use data_encoding::HEXLOWER;
     use num_bigint::BigInt;
     use std::ops::ShlAssign;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_run_is_reproducible_across_threads() {
+        let block = Block::new_template(String::from("none"), &[], 0);
+        let mut single = ProofOfWork::new_proof_of_work(block.clone()).with_threads(1);
+        let mut multi = ProofOfWork::new_proof_of_work(block).with_threads(4);
+
+        let mined = single.run();
+        assert_eq!(multi.run().get_hash(), mined.get_hash());
+        assert_eq!(single.get_hash_count(), mined.get_nonce() as u64 + 1);
+        assert!(ProofOfWork::new_proof_of_work(mined).validate());
+    }
+
+    #[test]
+    fn test_run_rolls_header_when_nonces_run_out() {
+        // Only nonce 0 is tried, so start from a header it does not solve.
+        let (coinbase_tx, block) = loop {
+            let coinbase_tx = Transaction::new_coinbase_tx("1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa");
+            let block = Block::new_template(String::from("none"), &[coinbase_tx.clone()], 0);
+            if ProofOfWork::new_proof_of_work(block.clone()).hash_header(0) >= ProofOfWork::target()
+            {
+                break (coinbase_tx, block);
+            }
+        };
+        let mut pow = ProofOfWork::new_proof_of_work(block.clone()).with_max_nonce(0);
+
+        let mined = pow.run();
+        assert_eq!(mined.get_nonce(), 0);
+        assert!(mined.get_timestamp() > block.get_timestamp());
+        assert_ne!(mined.get_transactions()[0].get_id(), coinbase_tx.get_id());
+        let check = ProofOfWork::new_proof_of_work(mined.clone());
+        assert!(check.hash_header(mined.get_nonce()) < ProofOfWork::target());
+        assert!(check.validate());
+    }
+
+    #[test]
+    fn test_run_until_cancelled() {
+        let block = Block::new_template(String::from("none"), &[], 0);
+        let mut pow = ProofOfWork::new_proof_of_work(block);
+        assert!(pow.run_until(&AtomicBool::new(true)).is_none());
+        assert!(pow.run_until(&AtomicBool::new(false)).is_some());
+    }
+
+    #[test]
+    fn test_scrypt_run_cancelled_promptly() {
+        let block = Block::new_template(String::from("none"), &[], 0);
+        let mut pow = ProofOfWork::new_proof_of_work(block)
+            .with_params(&chain_params::SCRYPT)
+            .with_threads(1);
+        // No hash meets it, so only the cancel flag ends the search.
+        pow.target = [0; 32];
+        let cancel = AtomicBool::new(false);
+        let started = Instant::now();
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                thread::sleep(Duration::from_millis(100));
+                cancel.store(true, Ordering::Relaxed);
+            });
+            assert!(pow.run_until(&cancel).is_none());
+        });
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_run_with_each_algorithm() {
+        for params in [
+            &chain_params::MAIN,
+            &chain_params::SHA256D,
+            &chain_params::SCRYPT,
+        ] {
+            let block = Block::new_template(String::from("none"), &[], 0);
+            let mined = ProofOfWork::new_proof_of_work(block)
+                .with_params(params)
+                .run();
+            assert!(
+                ProofOfWork::new_proof_of_work(mined.clone())
+                    .with_params(params)
+                    .validate()
+            );
+        }
+    }
+
+    #[test]
+    fn test_difficulty() {
+        assert_eq!(ProofOfWork::difficulty(), 2f64.powi(TARGET_BITS));
+    }
 
     #[test]
     fn test_target_bits() {
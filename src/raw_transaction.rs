@@ -0,0 +1,225 @@
+use crate::transaction::{
+    MAX_NON_FINAL_SEQUENCE, MAX_RBF_SEQUENCE, SigHashType, TXInput, TXOutput,
+};
+use crate::{Transaction, Wallet, hash_pub_key, validate_address};
+
+use std::fmt;
+
+use data_encoding::HEXLOWER;
+use serde_json::{Value, json};
+
+#[derive(Debug, PartialEq)]
+pub enum RawTransactionError {
+    /// The text is not lowercase hex.
+    InvalidHex,
+    /// The bytes do not decode to a transaction.
+    InvalidTransaction,
+    /// An input is not `txid:vout`.
+    InvalidInput(String),
+    /// An output is not `address:amount`.
+    InvalidOutput(String),
+}
+
+impl fmt::Display for RawTransactionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RawTransactionError::InvalidHex => write!(f, "raw transaction is not hex"),
+            RawTransactionError::InvalidTransaction => {
+                write!(f, "raw transaction does not decode")
+            }
+            RawTransactionError::InvalidInput(input) => {
+                write!(f, "input {} is not a txid and output index", input)
+            }
+            RawTransactionError::InvalidOutput(output) => {
+                write!(
+                    f,
+                    "output {} is not an address and a positive amount",
+                    output
+                )
+            }
+        }
+    }
+}
+
+/// Builds an unsigned transaction spending `inputs`, each given as
+/// `txid:vout`, to `outputs`, each given as `address:amount`. The sequence
+/// numbers follow `send`: replaceable with `rbf`, and non-final when a
+/// `lock_time` is set.
+pub fn create_raw_transaction(
+    inputs: &[String],
+    outputs: &[String],
+    lock_time: u32,
+    rbf: bool,
+) -> Result<Transaction, RawTransactionError> {
+    let mut vin = vec![];
+    for input in inputs {
+        let invalid = || RawTransactionError::InvalidInput(input.clone());
+        let (txid, vout) = input.split_once(':').ok_or_else(invalid)?;
+        let txid = HEXLOWER.decode(txid.as_bytes()).map_err(|_| invalid())?;
+        // An input without a txid would make a coinbase.
+        if txid.is_empty() {
+            return Err(invalid());
+        }
+        let mut txinput = TXInput::new(txid.as_slice(), vout.parse().map_err(|_| invalid())?);
+        if rbf {
+            txinput.set_sequence(MAX_RBF_SEQUENCE);
+        } else if lock_time > 0 {
+            txinput.set_sequence(MAX_NON_FINAL_SEQUENCE);
+        }
+        vin.push(txinput);
+    }
+    let mut vout = vec![];
+    for output in outputs {
+        let invalid = || RawTransactionError::InvalidOutput(output.clone());
+        let (address, amount) = output.split_once(':').ok_or_else(invalid)?;
+        let amount: i32 = amount.parse().map_err(|_| invalid())?;
+        if !validate_address(address) || amount <= 0 {
+            return Err(invalid());
+        }
+        vout.push(TXOutput::new(amount, address));
+    }
+    let mut tx = Transaction::new(vin, vout);
+    tx.set_lock_time(lock_time);
+    Ok(tx)
+}
+
+/// Hex of the bincode serialization, as passed between the raw
+/// transaction commands.
+pub fn encode_raw_transaction(tx: &Transaction) -> String {
+    HEXLOWER.encode(tx.serialize().as_slice())
+}
+
+pub fn decode_raw_transaction(hex: &str) -> Result<Transaction, RawTransactionError> {
+    let bytes = HEXLOWER
+        .decode(hex.trim().as_bytes())
+        .map_err(|_| RawTransactionError::InvalidHex)?;
+    bincode::deserialize(bytes.as_slice()).map_err(|_| RawTransactionError::InvalidTransaction)
+}
+
+/// The full structure of `tx`: its id and witness-inclusive hash, lock
+/// time, size and weight, then every input with the output it spends and
+/// its unlocking script, and every output with its value, locking script
+/// and the address it pays, if any.
+pub fn raw_transaction_to_json(tx: &Transaction) -> Value {
+    let vin: Vec<Value> = tx
+        .get_vin()
+        .iter()
+        .map(|vin| {
+            json!({
+                "txid": HEXLOWER.encode(vin.get_txid()),
+                "vout": vin.get_vout(),
+                "script_sig": vin.get_script_sig().to_string(),
+                "sequence": vin.get_sequence(),
+            })
+        })
+        .collect();
+    let vout: Vec<Value> = tx
+        .get_vout()
+        .iter()
+        .enumerate()
+        .map(|(n, vout)| {
+            json!({
+                "n": n,
+                "value": vout.get_value(),
+                "script_pubkey": vout.get_script_pubkey().to_string(),
+                "address": vout.get_script_pubkey().get_address(),
+            })
+        })
+        .collect();
+    json!({
+        "txid": HEXLOWER.encode(tx.get_id()),
+        "wtxid": HEXLOWER.encode(tx.get_wtxid().as_slice()),
+        "coinbase": tx.is_coinbase(),
+        "lock_time": tx.get_lock_time(),
+        "size": tx.get_size(),
+        "weight": tx.get_weight(),
+        "vin": vin,
+        "vout": vout,
+    })
+}
+
+/// Signs with `hash_type` each input of `tx` that spends an output paying to
+/// the address of one of `wallets`, given the outputs spent in input order.
+/// Returns whether every input is now signed.
+pub fn sign_raw_transaction(
+    tx: &mut Transaction,
+    prev_outputs: &[TXOutput],
+    wallets: &[&Wallet],
+    hash_type: SigHashType,
+) -> bool {
+    for (idx, prev_output) in prev_outputs.iter().enumerate() {
+        let wallet = wallets.iter().find(|wallet| {
+            prev_output.is_locked_with_key(hash_pub_key(wallet.get_public_key()).as_slice())
+        });
+        if let Some(wallet) = wallet {
+            tx.sign_input(idx, wallet, prev_output, hash_type);
+        }
+    }
+    tx.verify_with_outputs(prev_outputs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        RawTransactionError, create_raw_transaction, decode_raw_transaction,
+        encode_raw_transaction, raw_transaction_to_json, sign_raw_transaction,
+    };
+    use crate::Wallet;
+    use crate::transaction::{MAX_RBF_SEQUENCE, SigHashType, TXOutput};
+
+    #[test]
+    fn test_raw_transaction() {
+        let (owner, to) = (Wallet::new(), Wallet::new().get_address());
+        let funding = TXOutput::new(10, owner.get_address().as_str());
+        let txid = "ab".repeat(32);
+        let inputs = vec![format!("{}:0", txid)];
+        let outputs = vec![format!("{}:9", to)];
+        let mut tx = create_raw_transaction(&inputs, &outputs, 0, true).unwrap();
+        assert_eq!(tx.get_vin()[0].get_sequence(), MAX_RBF_SEQUENCE);
+
+        let decoded = decode_raw_transaction(&encode_raw_transaction(&tx)).unwrap();
+        assert_eq!(decoded.get_id(), tx.get_id());
+        let json = raw_transaction_to_json(&decoded);
+        assert_eq!(json["vin"][0]["txid"], txid);
+        assert_eq!(json["vout"][0]["address"], to);
+        assert_eq!(json["vout"][0]["value"], 9);
+
+        let prev_outputs = vec![funding];
+        assert!(!sign_raw_transaction(
+            &mut tx,
+            &prev_outputs,
+            &[&Wallet::new()],
+            SigHashType::ALL
+        ));
+        assert!(sign_raw_transaction(
+            &mut tx,
+            &prev_outputs,
+            &[&owner],
+            SigHashType::ALL
+        ));
+
+        assert_eq!(
+            decode_raw_transaction("zz").err(),
+            Some(RawTransactionError::InvalidHex)
+        );
+        assert_eq!(
+            decode_raw_transaction("00").err(),
+            Some(RawTransactionError::InvalidTransaction)
+        );
+        let unpaired = vec![txid.clone()];
+        assert_eq!(
+            create_raw_transaction(&unpaired, &outputs, 0, false).err(),
+            Some(RawTransactionError::InvalidInput(txid))
+        );
+        let coinbase = vec![":0".to_string()];
+        assert_eq!(
+            create_raw_transaction(&coinbase, &outputs, 0, false).err(),
+            Some(RawTransactionError::InvalidInput(coinbase[0].clone()))
+        );
+        let free = vec![format!("{}:0", to)];
+        assert_eq!(
+            create_raw_transaction(&inputs, &free, 0, false).err(),
+            Some(RawTransactionError::InvalidOutput(free[0].clone()))
+        );
+    }
+}
@@ -0,0 +1,715 @@
+use crate::transaction::SEQUENCE_LOCKTIME_DISABLE_FLAG;
+use crate::wallet::{ADDRESS_CHECK_SUM_LEN, P2SH_VERSION, convert_address, convert_script_address};
+use crate::{base58_decode, hash_pub_key};
+
+use std::fmt;
+
+use data_encoding::HEXLOWER;
+use serde::{Deserialize, Serialize};
+
+/// Most operations a single script may contain.
+pub const MAX_SCRIPT_OPS: usize = 201;
+/// Largest item a script may push.
+pub const MAX_PUSH_SIZE: usize = 520;
+/// Most items the stack may hold at any time.
+pub const MAX_STACK_SIZE: usize = 1000;
+/// Most public keys a multisig script may list.
+pub const MAX_MULTISIG_KEYS: usize = 20;
+/// Most bytes a data output may carry to be relayed.
+pub const MAX_DATA_SIZE: usize = 80;
+
+/// One instruction of the script language. Scripts run on a stack of byte
+/// strings where an empty string or all zero bytes mean false.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum Op {
+    /// Pushes the bytes onto the stack.
+    Push(Vec<u8>),
+    Dup,
+    Drop,
+    Swap,
+    /// Replaces the top item with RIPEMD-160 of its SHA-256.
+    Hash160,
+    Sha256,
+    /// Replaces the top two items with whether they are equal.
+    Equal,
+    EqualVerify,
+    /// Fails the script unless the top item is true, which it removes.
+    Verify,
+    /// Pops a public key, then a signature, and pushes whether the signature
+    /// is valid for the spending transaction.
+    CheckSig,
+    CheckSigVerify,
+    /// Pops a key count `n` and `n` public keys, then a signature count `m`
+    /// and `m` signatures, and pushes whether every signature is valid for a
+    /// different key. Signatures must be in the same order as their keys.
+    CheckMultiSig,
+    /// Fails unless the spending transaction is locked until at least the
+    /// height or time on top of the stack, which it leaves in place.
+    CheckLockTimeVerify,
+    /// Fails unless the spending input is locked for at least the relative
+    /// height or time on top of the stack, which it leaves in place.
+    CheckSequenceVerify,
+    /// Pops the top item and runs the following operations only if it is
+    /// true, up to the matching `Else` or `EndIf`.
+    If,
+    /// Runs the following operations only if those since the matching `If`
+    /// did not run.
+    Else,
+    EndIf,
+    /// Fails the script. Outputs locked with it carry data and can never
+    /// be spent.
+    Return,
+}
+
+/// A locking script in an output or an unlocking script in an input. To
+/// spend an output, the unlocking script runs first and the locking script
+/// then runs on the stack it left, which must end with true on top.
+///
+/// A pay to script hash output only locks to the hash of a redeem script.
+/// Its unlocking script pushes the serialized redeem script last; once the
+/// hash matches, the redeem script runs on the items pushed before it.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Script {
+    ops: Vec<Op>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ScriptError {
+    TooManyOps,
+    PushTooLarge,
+    StackOverflow,
+    StackUnderflow,
+    /// An unlocking script did something other than push data.
+    NotPushOnly,
+    VerifyFailed,
+    /// A count is not a small number or is out of range.
+    InvalidNumber,
+    /// The spending transaction is not locked as long as the script asks.
+    UnsatisfiedLockTime,
+    /// The redeem script of a pay to script hash spend does not decode.
+    BadRedeemScript,
+    /// The scripts ran but left false or nothing on the stack.
+    EvalFalse,
+    /// An `Else` or `EndIf` has no `If`, or an `If` is never ended.
+    UnbalancedConditional,
+    /// The script ran a `Return`.
+    Unspendable,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScriptError::TooManyOps => write!(f, "script has too many operations"),
+            ScriptError::PushTooLarge => write!(f, "script pushes an item that is too large"),
+            ScriptError::StackOverflow => write!(f, "stack holds too many items"),
+            ScriptError::StackUnderflow => write!(f, "operation needs more stack items"),
+            ScriptError::NotPushOnly => write!(f, "unlocking script must only push data"),
+            ScriptError::VerifyFailed => write!(f, "verify operation failed"),
+            ScriptError::InvalidNumber => write!(f, "count is not a valid number"),
+            ScriptError::UnsatisfiedLockTime => write!(f, "lock time is not satisfied"),
+            ScriptError::BadRedeemScript => write!(f, "redeem script does not decode"),
+            ScriptError::EvalFalse => write!(f, "script evaluated to false"),
+            ScriptError::UnbalancedConditional => write!(f, "script has an unbalanced conditional"),
+            ScriptError::Unspendable => write!(f, "output is unspendable"),
+        }
+    }
+}
+
+/// Checks signatures and locks against the transaction whose input is
+/// being run.
+pub trait SignatureChecker {
+    fn check_signature(&self, signature: &[u8], pub_key: &[u8]) -> bool;
+
+    fn check_lock_time(&self, _lock_time: u32) -> bool {
+        false
+    }
+
+    fn check_sequence(&self, _sequence: u32) -> bool {
+        false
+    }
+}
+
+impl Op {
+    /// Pushes `number` in the little-endian form counts and lock times
+    /// are read in.
+    pub fn push_number(number: u32) -> Op {
+        let mut bytes = number.to_le_bytes().to_vec();
+        while bytes.last() == Some(&0) {
+            bytes.pop();
+        }
+        Op::Push(bytes)
+    }
+
+    /// Reads the number pushed by `push_number`.
+    pub fn as_number(&self) -> Option<u32> {
+        match self {
+            Op::Push(bytes) => to_number(bytes.as_slice()).ok().map(|number| number as u32),
+            _ => None,
+        }
+    }
+}
+
+impl Script {
+    pub fn new(ops: Vec<Op>) -> Script {
+        Script { ops }
+    }
+
+    /// Pay to public key hash, the default lock of an address:
+    /// `DUP HASH160 <pub_key_hash> EQUALVERIFY CHECKSIG`.
+    pub fn new_p2pkh(pub_key_hash: &[u8]) -> Script {
+        Script::new(vec![
+            Op::Dup,
+            Op::Hash160,
+            Op::Push(pub_key_hash.to_vec()),
+            Op::EqualVerify,
+            Op::CheckSig,
+        ])
+    }
+
+    /// Unlocks a pay to public key hash output: `<signature> <pub_key>`.
+    pub fn new_p2pkh_unlock(signature: &[u8], pub_key: &[u8]) -> Script {
+        Script::new(vec![
+            Op::Push(signature.to_vec()),
+            Op::Push(pub_key.to_vec()),
+        ])
+    }
+
+    /// M-of-N multisig: `<m> <pub_key>... <n> CHECKMULTISIG`.
+    pub fn new_multisig(required: usize, pub_keys: &[Vec<u8>]) -> Script {
+        if required == 0 || required > pub_keys.len() || pub_keys.len() > MAX_MULTISIG_KEYS {
+            panic!(
+                "Multisig needs 1 to {} keys and at most as many signatures",
+                MAX_MULTISIG_KEYS
+            )
+        }
+        let mut ops = vec![Op::Push(vec![required as u8])];
+        ops.extend(pub_keys.iter().map(|pub_key| Op::Push(pub_key.clone())));
+        ops.push(Op::Push(vec![pub_keys.len() as u8]));
+        ops.push(Op::CheckMultiSig);
+        Script::new(ops)
+    }
+
+    /// Pay to public key hash that cannot be spent before `lock_time`, a
+    /// height or Unix time as for a transaction lock time:
+    /// `<lock_time> CHECKLOCKTIMEVERIFY DROP DUP HASH160 <pub_key_hash> ...`.
+    pub fn new_lock_time_p2pkh(lock_time: u32, pub_key_hash: &[u8]) -> Script {
+        let mut ops = vec![
+            Op::push_number(lock_time),
+            Op::CheckLockTimeVerify,
+            Op::Drop,
+        ];
+        ops.extend(Script::new_p2pkh(pub_key_hash).ops);
+        Script::new(ops)
+    }
+
+    /// Pay to public key hash that cannot be spent until the relative lock
+    /// in `sequence` has passed since the output was confirmed.
+    pub fn new_sequence_p2pkh(sequence: u32, pub_key_hash: &[u8]) -> Script {
+        let mut ops = vec![Op::push_number(sequence), Op::CheckSequenceVerify, Op::Drop];
+        ops.extend(Script::new_p2pkh(pub_key_hash).ops);
+        Script::new(ops)
+    }
+
+    /// Data carrier that can never be spent: `RETURN <data>`.
+    pub fn new_data(data: &[u8]) -> Script {
+        Script::new(vec![Op::Return, Op::Push(data.to_vec())])
+    }
+
+    /// Pay to script hash: `HASH160 <script_hash> EQUAL`.
+    pub fn new_p2sh(script_hash: &[u8]) -> Script {
+        Script::new(vec![Op::Hash160, Op::Push(script_hash.to_vec()), Op::Equal])
+    }
+
+    /// The locking script of payments to `address`.
+    pub fn for_address(address: &str) -> Script {
+        let payload = base58_decode(address);
+        let hash = &payload[1..payload.len() - ADDRESS_CHECK_SUM_LEN];
+        if payload[0] == P2SH_VERSION {
+            Script::new_p2sh(hash)
+        } else {
+            Script::new_p2pkh(hash)
+        }
+    }
+
+    /// The address this script locks to, if it follows a template that
+    /// has one.
+    pub fn get_address(&self) -> Option<String> {
+        if let Some(pub_key_hash) = self.get_pub_key_hash() {
+            return Some(convert_address(pub_key_hash));
+        }
+        self.get_script_hash().map(convert_script_address)
+    }
+
+    pub fn get_ops(&self) -> &[Op] {
+        self.ops.as_slice()
+    }
+
+    /// The hash locked to if this is a pay to public key hash script.
+    pub fn get_pub_key_hash(&self) -> Option<&[u8]> {
+        match self.ops.as_slice() {
+            [
+                Op::Dup,
+                Op::Hash160,
+                Op::Push(hash),
+                Op::EqualVerify,
+                Op::CheckSig,
+            ] => Some(hash.as_slice()),
+            _ => None,
+        }
+    }
+
+    /// The redeem script hash locked to if this is a pay to script hash
+    /// script.
+    pub fn get_script_hash(&self) -> Option<&[u8]> {
+        match self.ops.as_slice() {
+            [Op::Hash160, Op::Push(hash), Op::Equal] => Some(hash.as_slice()),
+            _ => None,
+        }
+    }
+
+    /// The data carried if this is a data carrier script.
+    pub fn get_data(&self) -> Option<&[u8]> {
+        match self.ops.as_slice() {
+            [Op::Return, Op::Push(data)] => Some(data.as_slice()),
+            _ => None,
+        }
+    }
+
+    /// Whether the script fails before it can run anything, so outputs
+    /// locked with it need not be tracked as unspent.
+    pub fn is_unspendable(&self) -> bool {
+        self.ops.first() == Some(&Op::Return)
+    }
+
+    /// Signatures required and public keys listed if this is a multisig
+    /// script.
+    pub fn get_multisig(&self) -> Option<(usize, Vec<&[u8]>)> {
+        let [
+            Op::Push(required),
+            keys @ ..,
+            Op::Push(count),
+            Op::CheckMultiSig,
+        ] = self.ops.as_slice()
+        else {
+            return None;
+        };
+        let pub_keys: Vec<&[u8]> = keys
+            .iter()
+            .map(|op| match op {
+                Op::Push(pub_key) => Some(pub_key.as_slice()),
+                _ => None,
+            })
+            .collect::<Option<_>>()?;
+        let required = to_number(required).ok()?;
+        if to_number(count).ok()? != pub_keys.len() || required > pub_keys.len() {
+            return None;
+        }
+        Some((required, pub_keys))
+    }
+
+    /// Hash a pay to script hash output locks to.
+    pub fn hash(&self) -> Vec<u8> {
+        hash_pub_key(self.serialize().as_slice())
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        bincode::serialize(self).unwrap()
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Option<Script> {
+        bincode::deserialize(bytes).ok()
+    }
+
+    pub fn is_push_only(&self) -> bool {
+        self.ops.iter().all(|op| matches!(op, Op::Push(_)))
+    }
+
+    /// Runs the script on `stack`.
+    pub fn eval(
+        &self,
+        stack: &mut Vec<Vec<u8>>,
+        checker: &dyn SignatureChecker,
+    ) -> Result<(), ScriptError> {
+        if self.ops.len() > MAX_SCRIPT_OPS {
+            return Err(ScriptError::TooManyOps);
+        }
+        // Whether each open conditional is running its current branch.
+        let mut branches: Vec<bool> = vec![];
+        for op in &self.ops {
+            let executing = branches.iter().all(|branch| *branch);
+            if !executing && !matches!(op, Op::If | Op::Else | Op::EndIf) {
+                continue;
+            }
+            match op {
+                Op::If => {
+                    let branch = executing && cast_to_bool(pop(stack)?.as_slice());
+                    branches.push(branch);
+                }
+                Op::Else => {
+                    let branch = branches
+                        .last_mut()
+                        .ok_or(ScriptError::UnbalancedConditional)?;
+                    *branch = !*branch;
+                }
+                Op::EndIf => {
+                    branches.pop().ok_or(ScriptError::UnbalancedConditional)?;
+                }
+                Op::Push(data) => {
+                    if data.len() > MAX_PUSH_SIZE {
+                        return Err(ScriptError::PushTooLarge);
+                    }
+                    stack.push(data.clone());
+                }
+                Op::Dup => {
+                    let top = stack.last().ok_or(ScriptError::StackUnderflow)?.clone();
+                    stack.push(top);
+                }
+                Op::Drop => {
+                    pop(stack)?;
+                }
+                Op::Swap => {
+                    let len = stack.len();
+                    if len < 2 {
+                        return Err(ScriptError::StackUnderflow);
+                    }
+                    stack.swap(len - 1, len - 2);
+                }
+                Op::Hash160 => {
+                    let item = pop(stack)?;
+                    stack.push(hash_pub_key(item.as_slice()));
+                }
+                Op::Sha256 => {
+                    let item = pop(stack)?;
+                    stack.push(crate::sha256_digest(item.as_slice()));
+                }
+                Op::Equal | Op::EqualVerify => {
+                    let (a, b) = (pop(stack)?, pop(stack)?);
+                    push_bool(stack, a == b);
+                    if *op == Op::EqualVerify {
+                        verify(stack)?;
+                    }
+                }
+                Op::Verify => verify(stack)?,
+                Op::CheckSig | Op::CheckSigVerify => {
+                    let pub_key = pop(stack)?;
+                    let signature = pop(stack)?;
+                    let valid = checker.check_signature(signature.as_slice(), pub_key.as_slice());
+                    push_bool(stack, valid);
+                    if *op == Op::CheckSigVerify {
+                        verify(stack)?;
+                    }
+                }
+                Op::CheckMultiSig => {
+                    let key_count = to_number(pop(stack)?.as_slice())?;
+                    if key_count > MAX_MULTISIG_KEYS {
+                        return Err(ScriptError::InvalidNumber);
+                    }
+                    let mut pub_keys = (0..key_count)
+                        .map(|_| pop(stack))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    pub_keys.reverse();
+                    let sig_count = to_number(pop(stack)?.as_slice())?;
+                    if sig_count > key_count {
+                        return Err(ScriptError::InvalidNumber);
+                    }
+                    let mut signatures = (0..sig_count)
+                        .map(|_| pop(stack))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    signatures.reverse();
+
+                    // Each signature must match a key after the one the
+                    // previous signature matched.
+                    let mut keys = pub_keys.iter();
+                    let valid = signatures.iter().all(|signature| {
+                        keys.any(|pub_key| {
+                            checker.check_signature(signature.as_slice(), pub_key.as_slice())
+                        })
+                    });
+                    push_bool(stack, valid);
+                }
+                Op::CheckLockTimeVerify => {
+                    let lock_time = stack.last().ok_or(ScriptError::StackUnderflow)?;
+                    if !checker.check_lock_time(to_number(lock_time)? as u32) {
+                        return Err(ScriptError::UnsatisfiedLockTime);
+                    }
+                }
+                Op::CheckSequenceVerify => {
+                    let sequence = stack.last().ok_or(ScriptError::StackUnderflow)?;
+                    let sequence = to_number(sequence)? as u32;
+                    // With the disable flag set the check does nothing,
+                    // leaving those values free for future kinds of locks.
+                    if sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG == 0
+                        && !checker.check_sequence(sequence)
+                    {
+                        return Err(ScriptError::UnsatisfiedLockTime);
+                    }
+                }
+                Op::Return => return Err(ScriptError::Unspendable),
+            }
+            if stack.len() > MAX_STACK_SIZE {
+                return Err(ScriptError::StackOverflow);
+            }
+        }
+        if !branches.is_empty() {
+            return Err(ScriptError::UnbalancedConditional);
+        }
+        Ok(())
+    }
+}
+
+/// Checks that `script_sig` unlocks `script_pubkey`.
+pub fn verify_script(
+    script_sig: &Script,
+    script_pubkey: &Script,
+    checker: &dyn SignatureChecker,
+) -> Result<(), ScriptError> {
+    if !script_sig.is_push_only() {
+        return Err(ScriptError::NotPushOnly);
+    }
+    let mut stack = vec![];
+    script_sig.eval(&mut stack, checker)?;
+    let mut redeem_stack = stack.clone();
+    script_pubkey.eval(&mut stack, checker)?;
+    check_true(&stack)?;
+
+    if script_pubkey.get_script_hash().is_some() {
+        let redeem_script = pop(&mut redeem_stack)?;
+        let redeem_script =
+            Script::deserialize(redeem_script.as_slice()).ok_or(ScriptError::BadRedeemScript)?;
+        redeem_script.eval(&mut redeem_stack, checker)?;
+        check_true(&redeem_stack)?;
+    }
+    Ok(())
+}
+
+fn check_true(stack: &[Vec<u8>]) -> Result<(), ScriptError> {
+    match stack.last() {
+        Some(top) if cast_to_bool(top) => Ok(()),
+        _ => Err(ScriptError::EvalFalse),
+    }
+}
+
+/// Reads a count pushed as up to four little-endian bytes.
+fn to_number(item: &[u8]) -> Result<usize, ScriptError> {
+    if item.len() > 4 {
+        return Err(ScriptError::InvalidNumber);
+    }
+    Ok(item
+        .iter()
+        .rev()
+        .fold(0, |number, byte| (number << 8) | *byte as usize))
+}
+
+fn pop(stack: &mut Vec<Vec<u8>>) -> Result<Vec<u8>, ScriptError> {
+    stack.pop().ok_or(ScriptError::StackUnderflow)
+}
+
+fn push_bool(stack: &mut Vec<Vec<u8>>, value: bool) {
+    stack.push(if value { vec![1] } else { vec![] });
+}
+
+fn verify(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptError> {
+    if cast_to_bool(pop(stack)?.as_slice()) {
+        Ok(())
+    } else {
+        Err(ScriptError::VerifyFailed)
+    }
+}
+
+fn cast_to_bool(item: &[u8]) -> bool {
+    item.iter().any(|byte| *byte != 0)
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Op::Push(data) => write!(f, "{}", HEXLOWER.encode(data.as_slice())),
+            Op::Dup => write!(f, "OP_DUP"),
+            Op::Drop => write!(f, "OP_DROP"),
+            Op::Swap => write!(f, "OP_SWAP"),
+            Op::Hash160 => write!(f, "OP_HASH160"),
+            Op::Sha256 => write!(f, "OP_SHA256"),
+            Op::Equal => write!(f, "OP_EQUAL"),
+            Op::EqualVerify => write!(f, "OP_EQUALVERIFY"),
+            Op::Verify => write!(f, "OP_VERIFY"),
+            Op::CheckSig => write!(f, "OP_CHECKSIG"),
+            Op::CheckSigVerify => write!(f, "OP_CHECKSIGVERIFY"),
+            Op::CheckMultiSig => write!(f, "OP_CHECKMULTISIG"),
+            Op::CheckLockTimeVerify => write!(f, "OP_CHECKLOCKTIMEVERIFY"),
+            Op::CheckSequenceVerify => write!(f, "OP_CHECKSEQUENCEVERIFY"),
+            Op::If => write!(f, "OP_IF"),
+            Op::Else => write!(f, "OP_ELSE"),
+            Op::EndIf => write!(f, "OP_ENDIF"),
+            Op::Return => write!(f, "OP_RETURN"),
+        }
+    }
+}
+
+impl fmt::Display for Script {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ops: Vec<String> = self.ops.iter().map(|op| op.to_string()).collect();
+        write!(f, "{}", ops.join(" "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Op, Script, ScriptError, SignatureChecker, verify_script};
+    use crate::{Wallet, hash_pub_key};
+
+    const MESSAGE: &[u8] = b"spending transaction";
+
+    struct MessageChecker;
+
+    impl SignatureChecker for MessageChecker {
+        fn check_signature(&self, signature: &[u8], pub_key: &[u8]) -> bool {
+            crate::ecdsa_p256_sha256_sign_verify(pub_key, signature, MESSAGE)
+        }
+    }
+
+    #[test]
+    fn test_p2pkh() {
+        let (wallet, other) = (Wallet::new(), Wallet::new());
+        let lock = Script::new_p2pkh(hash_pub_key(wallet.get_public_key()).as_slice());
+        assert_eq!(lock.to_string().split(' ').count(), 5);
+        assert!(lock.get_pub_key_hash().is_some());
+
+        let signature = crate::ecdsa_p256_sha256_sign_digest(wallet.get_pkcs8(), MESSAGE);
+        let unlock = Script::new_p2pkh_unlock(signature.as_slice(), wallet.get_public_key());
+        assert_eq!(verify_script(&unlock, &lock, &MessageChecker), Ok(()));
+
+        let wrong_key = Script::new_p2pkh_unlock(signature.as_slice(), other.get_public_key());
+        assert_eq!(
+            verify_script(&wrong_key, &lock, &MessageChecker),
+            Err(ScriptError::VerifyFailed)
+        );
+        let other_signature = crate::ecdsa_p256_sha256_sign_digest(other.get_pkcs8(), MESSAGE);
+        let wrong_signature =
+            Script::new_p2pkh_unlock(other_signature.as_slice(), wallet.get_public_key());
+        assert_eq!(
+            verify_script(&wrong_signature, &lock, &MessageChecker),
+            Err(ScriptError::EvalFalse)
+        );
+    }
+
+    #[test]
+    fn test_p2sh_multisig() {
+        let wallets: Vec<Wallet> = (0..3).map(|_| Wallet::new()).collect();
+        let pub_keys: Vec<Vec<u8>> = wallets
+            .iter()
+            .map(|wallet| wallet.get_public_key().to_vec())
+            .collect();
+        let redeem_script = Script::new_multisig(2, pub_keys.as_slice());
+        let (required, keys) = redeem_script.get_multisig().unwrap();
+        assert_eq!((required, keys.len()), (2, 3));
+
+        let lock = Script::new_p2sh(redeem_script.hash().as_slice());
+        let address = lock.get_address().unwrap();
+        assert!(address.starts_with('3'));
+        assert_eq!(Script::for_address(address.as_str()), lock);
+
+        let sign =
+            |idx: usize| crate::ecdsa_p256_sha256_sign_digest(wallets[idx].get_pkcs8(), MESSAGE);
+        let unlock = |signatures: Vec<Vec<u8>>| {
+            let mut ops: Vec<Op> = signatures.into_iter().map(Op::Push).collect();
+            ops.push(Op::Push(redeem_script.serialize()));
+            Script::new(ops)
+        };
+        assert_eq!(
+            verify_script(&unlock(vec![sign(0), sign(2)]), &lock, &MessageChecker),
+            Ok(())
+        );
+        // Too few signatures, out of key order, or the same key twice.
+        for signatures in [
+            vec![sign(1)],
+            vec![sign(2), sign(0)],
+            vec![sign(1), sign(1)],
+        ] {
+            assert!(verify_script(&unlock(signatures), &lock, &MessageChecker).is_err());
+        }
+
+        let other = Script::new_multisig(1, &pub_keys[..1]);
+        let mut ops = vec![Op::Push(sign(0))];
+        ops.push(Op::Push(other.serialize()));
+        assert_eq!(
+            verify_script(&Script::new(ops), &lock, &MessageChecker),
+            Err(ScriptError::EvalFalse)
+        );
+    }
+
+    #[test]
+    fn test_hash_lock() {
+        let secret = b"secret".to_vec();
+        let lock = Script::new(vec![
+            Op::Sha256,
+            Op::Push(crate::sha256_digest(secret.as_slice())),
+            Op::Equal,
+        ]);
+        let unlock = Script::new(vec![Op::Push(secret)]);
+        assert_eq!(verify_script(&unlock, &lock, &MessageChecker), Ok(()));
+
+        let wrong = Script::new(vec![Op::Push(b"guess".to_vec())]);
+        assert_eq!(
+            verify_script(&wrong, &lock, &MessageChecker),
+            Err(ScriptError::EvalFalse)
+        );
+        assert_eq!(
+            verify_script(&Script::default(), &lock, &MessageChecker),
+            Err(ScriptError::StackUnderflow)
+        );
+
+        // Unlocking scripts cannot run code of their own.
+        let sneaky = Script::new(vec![Op::Push(vec![1]), Op::Dup, Op::Drop]);
+        assert_eq!(
+            verify_script(&sneaky, &lock, &MessageChecker),
+            Err(ScriptError::NotPushOnly)
+        );
+    }
+
+    #[test]
+    fn test_conditionals() {
+        // Pushes 2 if the unlocking script pushed true, otherwise 3.
+        let lock = Script::new(vec![
+            Op::If,
+            Op::Push(vec![2]),
+            Op::Else,
+            Op::Push(vec![3]),
+            Op::EndIf,
+            Op::Push(vec![3]),
+            Op::Equal,
+        ]);
+        let unlock = |value: Vec<u8>| Script::new(vec![Op::Push(value)]);
+        assert_eq!(
+            verify_script(&unlock(vec![]), &lock, &MessageChecker),
+            Ok(())
+        );
+        assert_eq!(
+            verify_script(&unlock(vec![1]), &lock, &MessageChecker),
+            Err(ScriptError::EvalFalse)
+        );
+
+        // Operations in a branch that does not run cannot fail.
+        let skipped = Script::new(vec![
+            Op::If,
+            Op::Drop,
+            Op::Verify,
+            Op::EndIf,
+            Op::Push(vec![1]),
+        ]);
+        assert_eq!(
+            verify_script(&unlock(vec![]), &skipped, &MessageChecker),
+            Ok(())
+        );
+
+        for ops in [
+            vec![Op::If],
+            vec![Op::Else],
+            vec![Op::If, Op::EndIf, Op::EndIf],
+        ] {
+            assert_eq!(
+                verify_script(&unlock(vec![1]), &Script::new(ops), &MessageChecker),
+                Err(ScriptError::UnbalancedConditional)
+            );
+        }
+    }
+}
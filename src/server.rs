@@ -1,24 +1,27 @@
+use crate::chain_params::ConsensusKind;
 use crate::{
-    Block, BlockInTransit, Blockchain, GLOBAL_CONFIG, MemoryPool, Nodes, Transaction, UTXOSet,
+    Block, BlockError, BlockInTransit, BlockTemplate, Blockchain, ChainParams, GLOBAL_CONFIG,
+    MemoryPool, Miner, MiningInfo, MiningPool, MiningStats, Nodes, Transaction, UTXOSet, Wallets,
 };
 
+use std::collections::HashMap;
 use std::error::Error;
-use std::io::{BufReader, Write};
+use std::fmt;
+use std::io::{BufRead, BufReader, Write};
 use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::Mutex;
 use std::thread;
 use std::time::Duration;
 
 use data_encoding::HEXLOWER;
 use log::{error, info};
-use once_cell::sync::Lazy;
+use once_cell::sync::{Lazy, OnceCell};
 use serde::{Deserialize, Serialize};
 use serde_json::Deserializer;
 
 const NODE_VERSION: usize = 1;
 pub const CENTRAL_NODE: &str = "127.0.0.1:42069";
 
-pub const TRANSACTION_THRESHOLD: usize = 2;
-
 static GLOBAL_NODES: Lazy<Nodes> = Lazy::new(|| {
     let nodes = Nodes::new();
     nodes.add_node(CENTRAL_NODE.to_string());
@@ -29,6 +32,15 @@ static GLOBAL_MEMORY_POOL: Lazy<MemoryPool> = Lazy::new(|| MemoryPool::new());
 
 static GLOBAL_BLOCKS_IN_TRANSIT: Lazy<BlockInTransit> = Lazy::new(|| BlockInTransit::new());
 
+static GLOBAL_MINER: OnceCell<Miner> = OnceCell::new();
+
+static GLOBAL_MINING_POOL: OnceCell<MiningPool> = OnceCell::new();
+
+/// Templates handed out on the current tip, by template id. A submitted
+/// block must come from one of them.
+static GLOBAL_TEMPLATES: Lazy<Mutex<HashMap<String, BlockTemplate>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
 const TCP_WRITE_TIMEOUT: u64 = 1000;
 
 pub struct Server {
@@ -48,6 +60,43 @@ impl Server {
             info!("Send version best height: {}", best_height);
             send_version(CENTRAL_NODE, best_height);
         }
+        if let Some(pool_addr) = GLOBAL_CONFIG.get_pool_addr() {
+            if ChainParams::current().get_consensus() != ConsensusKind::ProofOfWork {
+                panic!("Pool mode needs a proof of work chain")
+            }
+            // In pool mode the workers mine and the mining address only
+            // collects what is not paid out to them.
+            let operator_address = GLOBAL_CONFIG
+                .get_mining_addr()
+                .expect("Pool mode needs a mining address");
+            let pool = MiningPool::new(
+                self.blockchain.clone(),
+                &GLOBAL_MEMORY_POOL,
+                operator_address,
+            );
+            let _ = GLOBAL_MINING_POOL.set(pool);
+            thread::spawn(move || {
+                let pool = GLOBAL_MINING_POOL.get().unwrap();
+                pool.run(pool_addr.as_str(), broadcast_block);
+            });
+        } else if let Some(mining_address) = GLOBAL_CONFIG.get_mining_addr() {
+            let miner = Miner::new(
+                self.blockchain.clone(),
+                &GLOBAL_MEMORY_POOL,
+                mining_address,
+                Duration::from_secs(GLOBAL_CONFIG.get_mining_block_interval()),
+                GLOBAL_CONFIG.get_mining_min_txs(),
+            );
+            let _ = GLOBAL_MINER.set(miner);
+            thread::spawn(|| {
+                let miner = GLOBAL_MINER.get().unwrap();
+                miner.run(broadcast_block);
+            });
+        }
+        if let Some(metrics_addr) = GLOBAL_CONFIG.get_metrics_addr() {
+            let blockchain = self.blockchain.clone();
+            thread::spawn(move || serve_metrics(blockchain, metrics_addr.as_str()));
+        }
         info!("Listening on {}", addr);
         for stream in listener.incoming() {
             let blockchain = self.blockchain.clone();
@@ -71,6 +120,16 @@ pub enum OpType {
     Block,
 }
 
+/// A wallet passphrase on the wire, left out of the logged packages.
+#[derive(Serialize, Deserialize)]
+pub struct Passphrase(String);
+
+impl fmt::Debug for Passphrase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Passphrase(..)")
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub enum Package {
     Block {
@@ -80,25 +139,68 @@ pub enum Package {
     GetBlocks {
         addr_from: String,
     },
+    GetBlockTemplate {
+        addr_from: String,
+    },
+    BlockTemplate {
+        addr_from: String,
+        template: Vec<u8>,
+    },
     GetData {
         addr_from: String,
         op_type: OpType,
         id: Vec<u8>,
     },
+    GetMiningInfo {
+        addr_from: String,
+    },
+    MiningInfo {
+        addr_from: String,
+        info: MiningInfo,
+    },
     Inv {
         addr_from: String,
         op_type: OpType,
         items: Vec<Vec<u8>>,
     },
+    SubmitBlock {
+        addr_from: String,
+        template_id: String,
+        block: Vec<u8>,
+    },
+    SubmitBlockResult {
+        addr_from: String,
+        accepted: bool,
+        reason: Option<String>,
+    },
     Tx {
         addr_from: String,
         transaction: Vec<u8>,
     },
+    TxResult {
+        addr_from: String,
+        txid: Vec<u8>,
+        accepted: bool,
+        reason: Option<String>,
+    },
     Version {
         addr_from: String,
         version: usize,
         best_height: usize,
     },
+    WalletPassphrase {
+        addr_from: String,
+        passphrase: Passphrase,
+        timeout: u64,
+    },
+    WalletLock {
+        addr_from: String,
+    },
+    WalletResult {
+        addr_from: String,
+        accepted: bool,
+        reason: Option<String>,
+    },
 }
 
 fn send_get_data(addr: &str, op_type: OpType, id: &[u8]) {
@@ -139,7 +241,7 @@ fn send_block(addr: &str, block: &Block) {
     );
 }
 
-pub fn send_tx(addr: &str, tx: &Transaction) {
+fn relay_tx(addr: &str, tx: &Transaction) {
     let socket_addr = addr.parse().unwrap();
     let node_addr = GLOBAL_CONFIG.get_node_addr().parse().unwrap();
     send_data(
@@ -151,6 +253,24 @@ pub fn send_tx(addr: &str, tx: &Transaction) {
     );
 }
 
+/// Hands `tx` to `addr` for its memory pool. Returns `Err` with the node's
+/// reason if the transaction was refused.
+pub fn send_tx(addr: &str, tx: &Transaction) -> Result<(), String> {
+    let socket_addr = addr.parse().unwrap();
+    let node_addr = GLOBAL_CONFIG.get_node_addr();
+    let pkg = Package::Tx {
+        addr_from: node_addr,
+        transaction: tx.serialize(),
+    };
+    match send_request(socket_addr, pkg) {
+        Some(Package::TxResult { accepted: true, .. }) => Ok(()),
+        Some(Package::TxResult { reason, .. }) => {
+            Err(reason.unwrap_or_else(|| "rejected".to_string()))
+        }
+        _ => Err(format!("no answer from {}", addr)),
+    }
+}
+
 fn send_version(addr: &str, height: usize) {
     let socket_addr = addr.parse().unwrap();
     let node_addr = GLOBAL_CONFIG.get_node_addr().parse().unwrap();
@@ -175,6 +295,91 @@ fn send_get_blocks(addr: &str) {
     );
 }
 
+/// Asks `addr` for a block template to mine on.
+pub fn get_block_template(addr: &str) -> Option<BlockTemplate> {
+    let socket_addr = addr.parse().unwrap();
+    let node_addr = GLOBAL_CONFIG.get_node_addr();
+    match send_request(
+        socket_addr,
+        Package::GetBlockTemplate {
+            addr_from: node_addr,
+        },
+    ) {
+        Some(Package::BlockTemplate { template, .. }) => {
+            Some(BlockTemplate::deserialize(template.as_slice()))
+        }
+        _ => None,
+    }
+}
+
+/// Hands a solved block built from template `template_id` to `addr`.
+/// Returns `Err` with the node's reason if the block was refused.
+pub fn submit_block(addr: &str, template_id: &str, block: &Block) -> Result<(), String> {
+    let socket_addr = addr.parse().unwrap();
+    let node_addr = GLOBAL_CONFIG.get_node_addr();
+    let pkg = Package::SubmitBlock {
+        addr_from: node_addr,
+        template_id: template_id.to_string(),
+        block: block.serialize(),
+    };
+    match send_request(socket_addr, pkg) {
+        Some(Package::SubmitBlockResult { accepted: true, .. }) => Ok(()),
+        Some(Package::SubmitBlockResult { reason, .. }) => {
+            Err(reason.unwrap_or_else(|| "rejected".to_string()))
+        }
+        _ => Err(format!("no answer from {}", addr)),
+    }
+}
+
+/// Asks `addr` for its mining statistics.
+pub fn get_mining_info(addr: &str) -> Option<MiningInfo> {
+    let socket_addr = addr.parse().unwrap();
+    let node_addr = GLOBAL_CONFIG.get_node_addr();
+    match send_request(
+        socket_addr,
+        Package::GetMiningInfo {
+            addr_from: node_addr,
+        },
+    ) {
+        Some(Package::MiningInfo { info, .. }) => Some(info),
+        _ => None,
+    }
+}
+
+pub fn wallet_passphrase(addr: &str, passphrase: &str, timeout: u64) -> Result<(), String> {
+    let pkg = Package::WalletPassphrase {
+        addr_from: GLOBAL_CONFIG.get_node_addr(),
+        passphrase: Passphrase(passphrase.to_string()),
+        timeout,
+    };
+    send_wallet_request(addr, pkg)
+}
+
+pub fn wallet_lock(addr: &str) -> Result<(), String> {
+    let pkg = Package::WalletLock {
+        addr_from: GLOBAL_CONFIG.get_node_addr(),
+    };
+    send_wallet_request(addr, pkg)
+}
+
+fn send_wallet_request(addr: &str, pkg: Package) -> Result<(), String> {
+    let socket_addr = addr.parse().unwrap();
+    match send_request(socket_addr, pkg) {
+        Some(Package::WalletResult { accepted: true, .. }) => Ok(()),
+        Some(Package::WalletResult { reason, .. }) => {
+            Err(reason.unwrap_or_else(|| "rejected".to_string()))
+        }
+        _ => Err(format!("no answer from {}", addr)),
+    }
+}
+
+/// Writes a reply on the connection a request arrived on.
+fn reply(mut stream: &TcpStream, pkg: Package) {
+    info!("Reply Package: {:?}", &pkg);
+    let _ = serde_json::to_writer(stream, &pkg);
+    let _ = stream.flush();
+}
+
 fn serve(blockchain: Blockchain, stream: TcpStream) -> std::result::Result<(), Box<dyn Error>> {
     let peer_addr = stream.peer_addr()?;
     let reader = BufReader::new(&stream);
@@ -223,24 +428,119 @@ fn serve(blockchain: Blockchain, stream: TcpStream) -> std::result::Result<(), B
                     let txid_hex = HEXLOWER.encode(id.as_slice());
 
                     if let Some(tx) = GLOBAL_MEMORY_POOL.get(txid_hex.as_str()) {
-                        send_tx(addr_from.as_str(), &tx);
+                        relay_tx(addr_from.as_str(), &tx);
                     }
                 }
             },
+            Package::GetBlockTemplate { .. } => {
+                let template = BlockTemplate::new(&blockchain, &GLOBAL_MEMORY_POOL);
+                let mut templates = GLOBAL_TEMPLATES.lock().unwrap();
+                templates.retain(|_, issued| {
+                    issued.get_prev_block_hash() == template.get_prev_block_hash()
+                });
+                templates.insert(template.get_template_id().to_string(), template.clone());
+                drop(templates);
+                info!(
+                    "Issued template {} at height {}",
+                    template.get_template_id(),
+                    template.get_height()
+                );
+                reply(
+                    &stream,
+                    Package::BlockTemplate {
+                        addr_from: GLOBAL_CONFIG.get_node_addr(),
+                        template: template.serialize(),
+                    },
+                );
+            }
+            Package::SubmitBlock {
+                template_id, block, ..
+            } => {
+                let block = Block::deserialize(block.as_slice());
+                let result =
+                    check_template(&blockchain, template_id.as_str(), &block).and_then(|()| {
+                        connect_block(&blockchain, &block).map_err(|reason| reason.to_string())
+                    });
+                match &result {
+                    Ok(()) => {
+                        info!(
+                            "Accepted block {} from template {}",
+                            block.get_hash(),
+                            template_id
+                        );
+                        broadcast_block(&block);
+                    }
+                    Err(reason) => {
+                        info!(
+                            "Rejected block {} from template {}: {}",
+                            block.get_hash(),
+                            template_id,
+                            reason
+                        );
+                    }
+                }
+                reply(
+                    &stream,
+                    Package::SubmitBlockResult {
+                        addr_from: GLOBAL_CONFIG.get_node_addr(),
+                        accepted: result.is_ok(),
+                        reason: result.err(),
+                    },
+                );
+            }
+            Package::GetMiningInfo { .. } => {
+                reply(
+                    &stream,
+                    Package::MiningInfo {
+                        addr_from: GLOBAL_CONFIG.get_node_addr(),
+                        info: local_mining_info(&blockchain),
+                    },
+                );
+            }
+            Package::WalletPassphrase {
+                passphrase,
+                timeout,
+                ..
+            } => {
+                let result = local_wallets(&peer_addr).and_then(|mut wallets| {
+                    wallets
+                        .unlock(passphrase.0.as_str(), timeout)
+                        .map_err(|err| err.to_string())
+                });
+                reply_wallet_result(&stream, result);
+            }
+            Package::WalletLock { .. } => {
+                let result = local_wallets(&peer_addr)
+                    .and_then(|mut wallets| wallets.lock().map_err(|err| err.to_string()));
+                reply_wallet_result(&stream, result);
+            }
+            Package::BlockTemplate { .. }
+            | Package::MiningInfo { .. }
+            | Package::SubmitBlockResult { .. }
+            | Package::TxResult { .. }
+            | Package::WalletResult { .. } => {}
             Package::Block { addr_from, block } => {
                 let block = Block::deserialize(block.as_slice());
-                blockchain.add_block(&block);
-
-                info!("Added block: {}", block.get_hash());
-
-                if GLOBAL_BLOCKS_IN_TRANSIT.len() > 0 {
-                    let block_hash = GLOBAL_BLOCKS_IN_TRANSIT.first().unwrap();
+                match connect_block(&blockchain, &block) {
+                    Ok(()) => {
+                        info!("Added block: {}", block.get_hash());
+                    }
+                    Err(BlockError::AlreadyKnown) => {}
+                    Err(reason) => {
+                        error!(
+                            "Dropped block {} from {}: {}",
+                            block.get_hash(),
+                            addr_from,
+                            reason
+                        );
+                        // The blocks still to come build on this one.
+                        GLOBAL_BLOCKS_IN_TRANSIT.clear();
+                    }
+                }
 
+                if let Some(block_hash) = GLOBAL_BLOCKS_IN_TRANSIT.first() {
                     send_get_data(addr_from.as_str(), OpType::Block, &block_hash);
                     GLOBAL_BLOCKS_IN_TRANSIT.remove(block_hash.as_slice());
-                } else {
-                    let utxo_set = UTXOSet::new(blockchain.clone());
-                    utxo_set.reindex();
                 }
             }
             Package::Inv {
@@ -249,11 +549,19 @@ fn serve(blockchain: Blockchain, stream: TcpStream) -> std::result::Result<(), B
                 items,
             } => match op_type {
                 OpType::Block => {
-                    GLOBAL_BLOCKS_IN_TRANSIT.add_blocks(items.as_slice());
-                    let block_hash = items.get(0).unwrap();
-
-                    send_get_data(addr_from.as_str(), OpType::Block, block_hash);
-                    GLOBAL_BLOCKS_IN_TRANSIT.remove(block_hash);
+                    // Blocks are listed from the tip down. Fetch the missing
+                    // ones oldest first, so that each extends the last.
+                    let missing: Vec<Vec<u8>> = items
+                        .iter()
+                        .rev()
+                        .filter(|block_hash| blockchain.get_block(block_hash).is_none())
+                        .cloned()
+                        .collect();
+                    GLOBAL_BLOCKS_IN_TRANSIT.add_blocks(missing.as_slice());
+                    if let Some(block_hash) = missing.first() {
+                        send_get_data(addr_from.as_str(), OpType::Block, block_hash);
+                        GLOBAL_BLOCKS_IN_TRANSIT.remove(block_hash);
+                    }
                 }
                 OpType::Tx => {
                     let txid = items.get(0).unwrap();
@@ -271,7 +579,39 @@ fn serve(blockchain: Blockchain, stream: TcpStream) -> std::result::Result<(), B
                 let tx = Transaction::deserialize(transaction.as_slice());
                 let txid = tx.get_id_bytes();
 
-                GLOBAL_MEMORY_POOL.add(tx);
+                let utxo_set = UTXOSet::new(blockchain.clone());
+                let result = GLOBAL_MEMORY_POOL.accept(tx, &utxo_set);
+                match &result {
+                    Ok(replaced) => {
+                        for replaced_txid_hex in replaced {
+                            info!(
+                                "Transaction {} replaced {}",
+                                HEXLOWER.encode(txid.as_slice()),
+                                replaced_txid_hex
+                            );
+                        }
+                    }
+                    Err(reason) => {
+                        info!(
+                            "Rejected transaction {}: {}",
+                            HEXLOWER.encode(txid.as_slice()),
+                            reason
+                        );
+                    }
+                }
+                // Peers relaying the transaction do not wait for the answer.
+                reply(
+                    &stream,
+                    Package::TxResult {
+                        addr_from: GLOBAL_CONFIG.get_node_addr(),
+                        txid: txid.clone(),
+                        accepted: result.is_ok(),
+                        reason: result.as_ref().err().map(|reason| reason.to_string()),
+                    },
+                );
+                if result.is_err() {
+                    continue;
+                }
 
                 let node_addr = GLOBAL_CONFIG.get_node_addr();
 
@@ -291,43 +631,124 @@ fn serve(blockchain: Blockchain, stream: TcpStream) -> std::result::Result<(), B
                     }
                 }
 
-                if GLOBAL_MEMORY_POOL.len() >= TRANSACTION_THRESHOLD && GLOBAL_CONFIG.is_miner() {
-                    let mining_address = GLOBAL_CONFIG.get_mining_addr().unwrap();
-                    let coinbase_tx = Transaction::new_coinbase_tx(mining_address.as_str());
-                    let mut txs = GLOBAL_MEMORY_POOL.get_all();
+                if let Some(miner) = GLOBAL_MINER.get() {
+                    miner.notify_transaction();
+                }
+                if let Some(pool) = GLOBAL_MINING_POOL.get() {
+                    pool.notify_transaction();
+                }
+            }
+        }
+    }
+    let _ = stream.shutdown(Shutdown::Both);
+    Ok(())
+}
 
-                    txs.push(coinbase_tx);
+fn local_mining_info(blockchain: &Blockchain) -> MiningInfo {
+    let stats: Option<&MiningStats> = match (GLOBAL_MINER.get(), GLOBAL_MINING_POOL.get()) {
+        (Some(miner), _) => Some(miner.get_stats()),
+        (None, Some(pool)) => Some(pool.get_stats()),
+        (None, None) => None,
+    };
+    MiningInfo::new(blockchain, &GLOBAL_MEMORY_POOL, stats)
+}
 
-                    let new_block = blockchain.mine_block(&txs);
-                    let utxo_set = UTXOSet::new(blockchain.clone());
-                    utxo_set.reindex();
+/// Answers every connection on `addr` with the mining statistics in the
+/// Prometheus text format, whatever was asked for.
+fn serve_metrics(blockchain: Blockchain, addr: &str) {
+    let listener = TcpListener::bind(addr).unwrap();
+    info!("Serving metrics on {}", addr);
+    for stream in listener.incoming() {
+        let Ok(mut stream) = stream else {
+            continue;
+        };
+        // Skip the request head before answering.
+        let mut reader = BufReader::new(&stream);
+        let mut line = String::new();
+        while reader.read_line(&mut line).unwrap_or(0) > 0 && !line.trim().is_empty() {
+            line.clear();
+        }
+        let body = local_mining_info(&blockchain).to_metrics();
+        let _ = write!(
+            stream,
+            "HTTP/1.0 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        let _ = stream.flush();
+    }
+}
 
-                    info!("New Block Mined: {}", new_block.get_hash());
+/// Checks that `block` was built from template `template_id`, issued by
+/// this node on the current tip: same parent, height and transactions after
+/// the coinbase.
+fn check_template(blockchain: &Blockchain, template_id: &str, block: &Block) -> Result<(), String> {
+    let templates = GLOBAL_TEMPLATES.lock().unwrap();
+    let template = templates
+        .get(template_id)
+        .ok_or_else(|| format!("unknown template {}", template_id))?;
+    if template.get_prev_block_hash() != blockchain.get_tip_hash() {
+        return Err(format!("template {} is stale", template_id));
+    }
+    let matches = block.get_prev_block_hash() == template.get_prev_block_hash()
+        && block.get_height() == template.get_height()
+        && block.get_transactions().len() == template.get_transactions().len() + 1
+        && block.get_transactions()[1..]
+            .iter()
+            .zip(template.get_transactions())
+            .all(|(tx, issued)| tx.get_id() == issued.get_id());
+    if !matches {
+        return Err(format!("block does not match template {}", template_id));
+    }
+    Ok(())
+}
 
-                    for tx in &txs {
-                        let txid_hex = HEXLOWER.encode(tx.get_id());
-                        GLOBAL_MEMORY_POOL.remove(txid_hex.as_str());
-                    }
+/// The wallet of this node, for a request from `peer_addr`. Only clients on
+/// this host may unlock or lock it.
+fn local_wallets(peer_addr: &SocketAddr) -> Result<Wallets, String> {
+    if !peer_addr.ip().is_loopback() {
+        return Err("wallet requests are only accepted from this host".to_string());
+    }
+    Ok(Wallets::new())
+}
 
-                    let nodes = GLOBAL_NODES.get_nodes();
+fn reply_wallet_result(stream: &TcpStream, result: Result<(), String>) {
+    reply(
+        stream,
+        Package::WalletResult {
+            addr_from: GLOBAL_CONFIG.get_node_addr(),
+            accepted: result.is_ok(),
+            reason: result.err(),
+        },
+    );
+}
 
-                    for node in &nodes {
-                        if node_addr.eq(node.get_addr().as_str()) {
-                            continue;
-                        }
+/// Validates a block and makes it the new tip, then stops any local work
+/// on the old one.
+fn connect_block(blockchain: &Blockchain, block: &Block) -> Result<(), BlockError> {
+    blockchain.connect_block(block)?;
+    GLOBAL_MEMORY_POOL.remove_block_transactions(block);
+    if let Some(miner) = GLOBAL_MINER.get() {
+        miner.notify_block(block.get_height());
+    }
+    if let Some(pool) = GLOBAL_MINING_POOL.get() {
+        pool.notify_block();
+    }
+    Ok(())
+}
 
-                        send_inv(
-                            node.get_addr().as_str(),
-                            OpType::Block,
-                            &vec![new_block.get_hash_bytes()],
-                        );
-                    }
-                }
-            }
+fn broadcast_block(block: &Block) {
+    let node_addr = GLOBAL_CONFIG.get_node_addr();
+    for node in GLOBAL_NODES.get_nodes() {
+        if node_addr.eq(node.get_addr().as_str()) {
+            continue;
         }
+        send_inv(
+            node.get_addr().as_str(),
+            OpType::Block,
+            &[block.get_hash_bytes()],
+        );
     }
-    let _ = stream.shutdown(Shutdown::Both);
-    Ok(())
 }
 
 fn send_data(addr: SocketAddr, pkg: Package) {
@@ -347,6 +768,18 @@ fn send_data(addr: SocketAddr, pkg: Package) {
     let _ = stream.flush();
 }
 
+/// Sends `pkg` and waits for the single reply on the same connection.
+fn send_request(addr: SocketAddr, pkg: Package) -> Option<Package> {
+    info!("Send Request: {:?}", &pkg);
+    let stream = TcpStream::connect(addr).ok()?;
+    let _ = serde_json::to_writer(&stream, &pkg);
+    let _ = (&stream).flush();
+    Deserializer::from_reader(BufReader::new(&stream))
+        .into_iter::<Package>()
+        .next()?
+        .ok()
+}
+
 #[cfg(test)]
 mod tests {
     use crate::server::{OpType, send_get_data};
@@ -1,18 +1,200 @@
-use crate::wallet::hash_pub_key;
-use crate::{Blockchain, UTXOSet, Wallets, base58_decode, wallet};
+use crate::coin_selection::{self, Selection};
+use crate::script::{Op, Script, SignatureChecker, verify_script};
+use crate::{Blockchain, UTXOSet, Wallet, WalletError, Wallets};
 
 use data_encoding::HEXLOWER;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-const SUBSIDY: i32 = 10;
+/// Coins created by every block on top of the fees it collects.
+pub const SUBSIDY: i32 = 10;
+/// Largest serialized transaction a block may hold.
+pub const MAX_TX_SIZE: usize = 500_000;
+/// Bytes outside the unlocking scripts count this many times towards the
+/// weight.
+pub const WITNESS_SCALE_FACTOR: usize = 4;
+/// Length of the random tag at the start of a coinbase input.
+const COINBASE_TAG_LEN: usize = 16;
+
+/// Sequence number of an input that does not opt in to replacement.
+pub const SEQUENCE_FINAL: u32 = 0xffffffff;
+/// Sequence number that enables the lock time without opting in to
+/// replacement.
+pub const MAX_NON_FINAL_SEQUENCE: u32 = 0xfffffffe;
+/// Highest sequence number that still signals replace-by-fee (BIP 125).
+pub const MAX_RBF_SEQUENCE: u32 = 0xfffffffd;
+
+/// Lock times below this are block heights, others are Unix times in
+/// seconds.
+pub const LOCKTIME_THRESHOLD: u32 = 500_000_000;
+/// Set in a sequence number to give its input no relative lock (BIP 68).
+pub const SEQUENCE_LOCKTIME_DISABLE_FLAG: u32 = 1 << 31;
+/// Set in a sequence number whose relative lock counts time rather than
+/// blocks.
+pub const SEQUENCE_LOCKTIME_TYPE_FLAG: u32 = 1 << 22;
+/// Bits of a sequence number holding the relative lock.
+pub const SEQUENCE_LOCKTIME_MASK: u32 = 0x0000ffff;
+/// Relative time locks count units of 2^9 = 512 seconds.
+const SEQUENCE_LOCKTIME_GRANULARITY: u32 = 9;
+
+/// Which parts of a transaction a signature commits to. It is appended to
+/// the signature as its last byte, so every signature carries its own.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SigHashType(u8);
+
+impl SigHashType {
+    /// Commits to every input and every output.
+    pub const ALL: SigHashType = SigHashType(0x01);
+    /// Commits to the inputs but to no output, so the coins can be sent
+    /// anywhere.
+    pub const NONE: SigHashType = SigHashType(0x02);
+    /// Commits to the inputs and to the output at the index of the signed
+    /// input only.
+    pub const SINGLE: SigHashType = SigHashType(0x03);
+    /// Set with any of the others to commit to the signed input alone, so
+    /// anyone can add inputs.
+    pub const ANYONECANPAY: u8 = 0x80;
+
+    pub fn from_byte(byte: u8) -> Option<SigHashType> {
+        match byte & !SigHashType::ANYONECANPAY {
+            0x01..=0x03 => Some(SigHashType(byte)),
+            _ => None,
+        }
+    }
+
+    /// The hash type called `name`, such as `ALL` or `SINGLE|ANYONECANPAY`.
+    pub fn from_name(name: &str) -> Option<SigHashType> {
+        let (base, anyone_can_pay) = match name.to_uppercase().split_once('|') {
+            Some((base, "ANYONECANPAY")) => (base.to_string(), true),
+            Some(_) => return None,
+            None => (name.to_uppercase(), false),
+        };
+        let hash_type = match base.as_str() {
+            "ALL" => SigHashType::ALL,
+            "NONE" => SigHashType::NONE,
+            "SINGLE" => SigHashType::SINGLE,
+            _ => return None,
+        };
+        Some(if anyone_can_pay {
+            hash_type.with_anyone_can_pay()
+        } else {
+            hash_type
+        })
+    }
+
+    pub fn with_anyone_can_pay(self) -> SigHashType {
+        SigHashType(self.0 | SigHashType::ANYONECANPAY)
+    }
+
+    pub fn is_anyone_can_pay(self) -> bool {
+        self.0 & SigHashType::ANYONECANPAY != 0
+    }
+
+    pub fn get_byte(self) -> u8 {
+        self.0
+    }
+
+    fn base(self) -> SigHashType {
+        SigHashType(self.0 & !SigHashType::ANYONECANPAY)
+    }
+}
+
+impl std::fmt::Display for SigHashType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let base = match self.base() {
+            SigHashType::ALL => "ALL",
+            SigHashType::NONE => "NONE",
+            _ => "SINGLE",
+        };
+        if self.is_anyone_can_pay() {
+            write!(f, "{}|ANYONECANPAY", base)
+        } else {
+            write!(f, "{}", base)
+        }
+    }
+}
+
+/// Why a wallet could not build a transaction.
+#[derive(Debug, PartialEq)]
+pub enum TransactionError {
+    NoPayments,
+    /// The outputs and the fee add up to more than an amount can hold.
+    AmountOverflow,
+    InsufficientFunds,
+    SelectionMismatch,
+    Wallet(WalletError),
+}
+
+impl std::fmt::Display for TransactionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TransactionError::NoPayments => write!(f, "a transaction needs at least one payment"),
+            TransactionError::AmountOverflow => {
+                write!(f, "the payments and the fee add up to too much")
+            }
+            TransactionError::InsufficientFunds => write!(f, "not enough funds"),
+            TransactionError::SelectionMismatch => {
+                write!(f, "coin selection does not pay for the outputs")
+            }
+            TransactionError::Wallet(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+/// Why a fee bump could not be built.
+#[derive(Debug, PartialEq)]
+pub enum FeeBumpError {
+    Coinbase,
+    /// An input spends an output that is not confirmed, such as one of an
+    /// unconfirmed parent.
+    UnresolvedInput(String),
+    NotFromAddress,
+    Wallet(WalletError),
+    /// The wallet did not record which output is the change.
+    NoChange,
+    FeeNotHigher(i64),
+    ChangeTooSmall(i32),
+}
+
+impl std::fmt::Display for FeeBumpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FeeBumpError::Coinbase => write!(f, "cannot bump the fee of a coinbase transaction"),
+            FeeBumpError::UnresolvedInput(outpoint) => write!(
+                f,
+                "input {} does not spend a confirmed output, wait for its parent to confirm",
+                outpoint
+            ),
+            FeeBumpError::NotFromAddress => {
+                write!(
+                    f,
+                    "only transactions spending from an address can be bumped"
+                )
+            }
+            FeeBumpError::Wallet(err) => write!(f, "{}", err),
+            FeeBumpError::NoChange => {
+                write!(f, "transaction has no change output to take the fee from")
+            }
+            FeeBumpError::FeeNotHigher(old_fee) => {
+                write!(
+                    f,
+                    "new fee must be higher than the current fee of {}",
+                    old_fee
+                )
+            }
+            FeeBumpError::ChangeTooSmall(fee) => {
+                write!(f, "change output is too small to pay a fee of {}", fee)
+            }
+        }
+    }
+}
 
 #[derive(Serialize, Deserialize, Default, Clone)]
 pub struct TXInput {
     txid: Vec<u8>,
     vout: usize,
-    signature: Vec<u8>,
-    pub_key: Vec<u8>,
+    script_sig: Script,
+    sequence: u32,
 }
 
 impl TXInput {
@@ -20,11 +202,19 @@ impl TXInput {
         TXInput {
             txid: txid.to_vec(),
             vout,
-            signature: vec![],
-            pub_key: vec![],
+            script_sig: Script::default(),
+            sequence: SEQUENCE_FINAL,
         }
     }
 
+    pub fn get_sequence(&self) -> u32 {
+        self.sequence
+    }
+
+    pub fn set_sequence(&mut self, sequence: u32) {
+        self.sequence = sequence;
+    }
+
     pub fn get_txid(&self) -> &[u8] {
         self.txid.as_slice()
     }
@@ -33,50 +223,116 @@ impl TXInput {
         self.vout
     }
 
-    pub fn get_pub_key(&self) -> &[u8] {
-        self.pub_key.as_slice()
+    /// Unlocking script run before the locking script of the spent output.
+    pub fn get_script_sig(&self) -> &Script {
+        &self.script_sig
     }
 
-    pub fn uses_key(&self, pub_key_hash: &[u8]) -> bool {
-        let locking_hash = wallet::hash_pub_key(self.pub_key.as_slice());
-
-        locking_hash.eq(pub_key_hash)
+    pub fn set_script_sig(&mut self, script_sig: Script) {
+        self.script_sig = script_sig;
     }
 }
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct TXOutput {
     value: i32,
-    pub_key_hash: Vec<u8>,
+    script_pubkey: Script,
 }
 
 impl TXOutput {
+    /// Creates an output paying to `address`.
     pub fn new(value: i32, address: &str) -> TXOutput {
-        let mut output = TXOutput {
+        TXOutput::with_script(value, Script::for_address(address))
+    }
+
+    pub fn with_script(value: i32, script_pubkey: Script) -> TXOutput {
+        TXOutput {
             value,
-            pub_key_hash: vec![],
-        };
-        output.lock(address);
-        output
+            script_pubkey,
+        }
+    }
+
+    /// Creates an output carrying `data` that can never be spent, so it
+    /// holds no value and is left out of the UTXO set.
+    pub fn new_data(data: &[u8]) -> TXOutput {
+        TXOutput::with_script(0, Script::new_data(data))
     }
 
     pub fn get_value(&self) -> i32 {
         self.value
     }
 
-    pub fn get_pub_key_hash(&self) -> &[u8] {
-        self.pub_key_hash.as_slice()
+    /// Locking script an input must satisfy to spend this output.
+    pub fn get_script_pubkey(&self) -> &Script {
+        &self.script_pubkey
     }
 
-    fn lock(&mut self, address: &str) {
-        let payload = base58_decode(address);
-        let pub_key_hash = payload[1..payload.len() - wallet::ADDRESS_CHECK_SUM_LEN].to_vec();
-        self.pub_key_hash = pub_key_hash;
+    /// The key hash paid to, unless the output uses a script other than
+    /// pay to public key hash.
+    pub fn get_pub_key_hash(&self) -> Option<&[u8]> {
+        self.script_pubkey.get_pub_key_hash()
     }
 
     pub fn is_locked_with_key(&self, pub_key_hash: &[u8]) -> bool {
-        self.pub_key_hash.eq(pub_key_hash)
+        self.get_pub_key_hash() == Some(pub_key_hash)
+    }
+
+    pub fn is_spendable(&self) -> bool {
+        !self.script_pubkey.is_unspendable()
+    }
+}
+
+/// Checks signatures in input `input` of `tx`, which spends an output
+/// locked with `script_code`.
+struct TransactionChecker<'a> {
+    tx: &'a Transaction,
+    input: usize,
+    script_code: &'a Script,
+}
+
+impl SignatureChecker for TransactionChecker<'_> {
+    /// The last byte of the signature is its hash type.
+    fn check_signature(&self, signature: &[u8], pub_key: &[u8]) -> bool {
+        let Some((&hash_type, signature)) = signature.split_last() else {
+            return false;
+        };
+        let hash = SigHashType::from_byte(hash_type).and_then(|hash_type| {
+            self.tx
+                .signature_hash(self.input, self.script_code, hash_type)
+        });
+        match hash {
+            Some(hash) => crate::ecdsa_p256_sha256_sign_verify(pub_key, signature, hash.as_slice()),
+            None => false,
+        }
+    }
+
+    /// The transaction lock time must be of the same kind and at least
+    /// `lock_time`, and must be enforced by a non-final input.
+    fn check_lock_time(&self, lock_time: u32) -> bool {
+        let tx_lock_time = self.tx.lock_time;
+        (lock_time < LOCKTIME_THRESHOLD) == (tx_lock_time < LOCKTIME_THRESHOLD)
+            && lock_time <= tx_lock_time
+            && self.tx.vin[self.input].sequence != SEQUENCE_FINAL
     }
+
+    /// The relative lock of the input must be of the same kind and at
+    /// least that of `sequence`.
+    fn check_sequence(&self, sequence: u32) -> bool {
+        let tx_sequence = self.tx.vin[self.input].sequence;
+        let kind = SEQUENCE_LOCKTIME_TYPE_FLAG;
+        tx_sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG == 0
+            && sequence & kind == tx_sequence & kind
+            && sequence & SEQUENCE_LOCKTIME_MASK <= tx_sequence & SEQUENCE_LOCKTIME_MASK
+    }
+}
+
+/// Height and time, in seconds, of a block: the one a transaction is
+/// checked for, or the one that confirmed an output it spends. The time is
+/// the median time past before the block, see [`Blockchain::get_block_time`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct BlockTime {
+    pub height: usize,
+    pub time: i64,
 }
 
 #[derive(Clone, Default, Serialize, Deserialize)]
@@ -84,79 +340,360 @@ pub struct Transaction {
     id: Vec<u8>,
     vin: Vec<TXInput>,
     vout: Vec<TXOutput>,
+    /// Height or time before which the transaction cannot be mined, unless
+    /// every input has a final sequence number. Zero means no lock.
+    lock_time: u32,
 }
 
 impl Transaction {
+    pub fn new(vin: Vec<TXInput>, vout: Vec<TXOutput>) -> Transaction {
+        let mut tx = Transaction {
+            id: vec![],
+            vin,
+            vout,
+            lock_time: 0,
+        };
+        tx.id = tx.hash();
+        tx
+    }
+
     pub fn new_coinbase_tx(to: &str) -> Transaction {
-        let txout = TXOutput::new(SUBSIDY, to);
+        Transaction::new_coinbase_tx_with_value(to, SUBSIDY)
+    }
+
+    /// Creates a coinbase paying `value`, the subsidy plus collected fees.
+    pub fn new_coinbase_tx_with_value(to: &str, value: i32) -> Transaction {
+        Transaction::new_coinbase_tx_with_outputs(&[(to.to_string(), value)])
+    }
+
+    /// Creates a coinbase splitting its value over several addresses, as a
+    /// mining pool does to pay its workers.
+    pub fn new_coinbase_tx_with_outputs(outputs: &[(String, i32)]) -> Transaction {
+        let vout = outputs
+            .iter()
+            .map(|(address, value)| TXOutput::new(*value, address.as_str()))
+            .collect();
         let mut tx_input = TXInput::default();
 
-        tx_input.signature = Uuid::new_v4().as_bytes().to_vec();
+        tx_input.script_sig = Script::new(vec![Op::Push(Uuid::new_v4().as_bytes().to_vec())]);
+        tx_input.sequence = SEQUENCE_FINAL;
 
         let mut tx = Transaction {
             id: vec![],
             vin: vec![tx_input],
-            vout: vec![txout],
+            vout,
+            lock_time: 0,
         };
 
         tx.id = tx.hash();
         tx
     }
 
+    /// Replaces the extra nonce carried in the coinbase input after its
+    /// random tag, which changes the transaction id.
+    pub fn set_extra_nonce(&mut self, extra_nonce: u64) {
+        if !self.is_coinbase() {
+            panic!("Only a coinbase transaction carries an extra nonce")
+        }
+        let mut data = match self.vin[0].script_sig.get_ops() {
+            [Op::Push(data)] => data.clone(),
+            _ => vec![],
+        };
+        data.truncate(data.len().min(COINBASE_TAG_LEN));
+        data.extend(extra_nonce.to_be_bytes().as_slice());
+        self.vin[0].script_sig = Script::new(vec![Op::Push(data)]);
+        self.id = self.hash();
+    }
+
     pub fn new_utxo_transaction(
         from: &str,
         to: &str,
         amount: i32,
+        fee: i32,
+        rbf: bool,
+        lock_time: u32,
         utxo_set: &UTXOSet,
-    ) -> Transaction {
-        let wallets = Wallets::new();
-        let wallet = wallets.get_wallet(from).expect("Wallet not found");
+    ) -> Result<Transaction, TransactionError> {
+        Transaction::new_wallet_transaction(
+            from,
+            vec![TXOutput::new(amount, to)],
+            fee,
+            rbf,
+            lock_time,
+            utxo_set,
+        )
+    }
 
-        let public_key_hash = hash_pub_key(wallet.get_public_key());
+    /// Pays every `(address, amount)` of `payments` in one transaction with
+    /// a single change output.
+    pub fn new_batch_transaction(
+        from: &str,
+        payments: &[(String, i32)],
+        fee: i32,
+        rbf: bool,
+        utxo_set: &UTXOSet,
+    ) -> Result<Transaction, TransactionError> {
+        if payments.is_empty() {
+            return Err(TransactionError::NoPayments);
+        }
+        let outputs = payments
+            .iter()
+            .map(|(address, amount)| TXOutput::new(*amount, address.as_str()))
+            .collect();
+        Transaction::new_wallet_transaction(from, outputs, fee, rbf, 0, utxo_set)
+    }
 
-        let (accumlated, valid_outputs) =
-            utxo_set.find_spendable_outputs(public_key_hash.as_slice(), amount);
-        if accumlated < amount {
-            panic!("Not enough funds")
+    /// Like `new_batch_transaction`, paying `fee_rate` per 1000 bytes of the
+    /// signed transaction. The fee is raised until it covers the size,
+    /// which grows as more inputs are needed to pay it.
+    pub fn new_batch_transaction_at_rate(
+        from: &str,
+        payments: &[(String, i32)],
+        fee_rate: i64,
+        rbf: bool,
+        utxo_set: &UTXOSet,
+    ) -> Result<Transaction, TransactionError> {
+        let mut fee = 0;
+        loop {
+            let tx = Transaction::new_batch_transaction(from, payments, fee, rbf, utxo_set)?;
+            let required = (tx.get_size() as i64)
+                .checked_mul(fee_rate)
+                .and_then(|fee| fee.checked_add(999))
+                .ok_or(TransactionError::AmountOverflow)?
+                / 1000;
+            if fee as i64 >= required {
+                return Ok(tx);
+            }
+            fee = i32::try_from(required).map_err(|_| TransactionError::AmountOverflow)?;
         }
+    }
+
+    /// Builds a transaction anchoring `data` in a data output, paying `fee`
+    /// from the wallet of `from`.
+    pub fn new_data_transaction(
+        from: &str,
+        data: &[u8],
+        fee: i32,
+        utxo_set: &UTXOSet,
+    ) -> Result<Transaction, TransactionError> {
+        Transaction::new_wallet_transaction(
+            from,
+            vec![TXOutput::new_data(data)],
+            fee,
+            false,
+            0,
+            utxo_set,
+        )
+    }
+
+    /// Pays `amount` to `to` with the coins of `selection`, which must have
+    /// been chosen from the wallet of `from` for that amount.
+    pub fn new_selected_transaction(
+        from: &str,
+        to: &str,
+        amount: i32,
+        selection: &Selection,
+        rbf: bool,
+        lock_time: u32,
+    ) -> Result<Transaction, TransactionError> {
+        Transaction::from_selection(
+            from,
+            vec![TXOutput::new(amount, to)],
+            selection,
+            rbf,
+            lock_time,
+        )
+    }
+
+    /// Funds `outputs` and `fee` from the wallet of `from` with the default
+    /// coin selection, returning the change to it, and signs the result.
+    fn new_wallet_transaction(
+        from: &str,
+        outputs: Vec<TXOutput>,
+        fee: i32,
+        rbf: bool,
+        lock_time: u32,
+        utxo_set: &UTXOSet,
+    ) -> Result<Transaction, TransactionError> {
+        let amount = outputs
+            .iter()
+            .try_fold(0i32, |total, output| total.checked_add(output.get_value()))
+            .filter(|amount| amount.checked_add(fee).is_some())
+            .ok_or(TransactionError::AmountOverflow)?;
+        let coins = utxo_set.find_coins(from);
+        let selection = Selection::new(coin_selection::DEFAULT, coins.as_slice(), amount, fee)
+            .ok_or(TransactionError::InsufficientFunds)?;
+        Transaction::from_selection(from, outputs, &selection, rbf, lock_time)
+    }
 
+    fn from_selection(
+        from: &str,
+        mut outputs: Vec<TXOutput>,
+        selection: &Selection,
+        rbf: bool,
+        lock_time: u32,
+    ) -> Result<Transaction, TransactionError> {
+        let sequence = if rbf {
+            MAX_RBF_SEQUENCE
+        } else if lock_time > 0 {
+            MAX_NON_FINAL_SEQUENCE
+        } else {
+            SEQUENCE_FINAL
+        };
         let mut inputs = vec![];
+        let mut prev_outputs = vec![];
+        for coin in selection.get_coins() {
+            let mut input = TXInput::new(coin.get_txid(), coin.get_vout());
+            input.sequence = sequence;
+            inputs.push(input);
+            prev_outputs.push(coin.get_output().clone());
+        }
 
-        for (txid_hex, outs) in valid_outputs {
-            let txid = HEXLOWER.decode(txid_hex.as_bytes()).unwrap();
-            for out in outs {
-                let input = TXInput {
-                    txid: txid.clone(),
-                    vout: out,
-                    signature: vec![],
-                    pub_key: wallet.get_public_key().to_vec(),
-                };
-                inputs.push(input);
-            }
+        if selection.get_change() > 0 {
+            outputs.push(TXOutput::new(selection.get_change(), from));
         }
 
-        let mut outputs = vec![TXOutput::new(amount, to)];
+        let mut tx = Transaction::new(inputs, outputs);
+        tx.set_lock_time(lock_time);
+        if tx.get_fee(prev_outputs.as_slice()) != selection.get_fee() as i64 {
+            return Err(TransactionError::SelectionMismatch);
+        }
+        let wallets = Wallets::new();
+        let wallet = wallets
+            .get_signing_wallet(from)
+            .map_err(TransactionError::Wallet)?;
+        tx.sign_with_outputs(wallet, prev_outputs.as_slice());
+        Ok(tx)
+    }
 
-        if accumlated > amount {
-            outputs.push(TXOutput::new(accumlated - amount, from));
+    /// Builds a replacement for `original` that pays `fee` in total, taking
+    /// the extra fee out of its output at index `change`, which goes back
+    /// to the sender. Without a `fee` the current fee is raised by one coin
+    /// per 1000 bytes.
+    pub fn new_fee_bump_transaction(
+        original: &Transaction,
+        change: Option<usize>,
+        fee: Option<i32>,
+        utxo_set: &UTXOSet,
+    ) -> Result<Transaction, FeeBumpError> {
+        if original.is_coinbase() {
+            return Err(FeeBumpError::Coinbase);
+        }
+        let mut prev_outputs = vec![];
+        for vin in &original.vin {
+            let prev_output = utxo_set
+                .find_output(vin.get_txid(), vin.get_vout())
+                .ok_or_else(|| {
+                    FeeBumpError::UnresolvedInput(format!(
+                        "{}:{}",
+                        HEXLOWER.encode(vin.get_txid()),
+                        vin.get_vout()
+                    ))
+                })?;
+            prev_outputs.push(prev_output);
+        }
+        let public_key_hash = prev_outputs[0]
+            .get_pub_key_hash()
+            .ok_or(FeeBumpError::NotFromAddress)?
+            .to_vec();
+        let wallets = Wallets::new();
+        let from = crate::convert_address(public_key_hash.as_slice());
+        let wallet = wallets
+            .get_signing_wallet(from.as_str())
+            .map_err(FeeBumpError::Wallet)?;
+        let old_fee = original.get_fee(prev_outputs.as_slice());
+        let increment = (original.get_size() as i64 + 999) / 1000;
+        let fee = fee.unwrap_or((old_fee + increment) as i32);
+        if fee as i64 <= old_fee {
+            return Err(FeeBumpError::FeeNotHigher(old_fee));
         }
 
-        let mut tx = Transaction {
-            id: vec![],
-            vin: inputs,
-            vout: outputs,
+        let mut outputs = original.vout.clone();
+        let change = change
+            .and_then(|change| outputs.get_mut(change))
+            .filter(|out| out.is_locked_with_key(public_key_hash.as_slice()))
+            .ok_or(FeeBumpError::NoChange)?;
+        let change_value = change.value as i64 - (fee as i64 - old_fee);
+        if change_value <= 0 {
+            return Err(FeeBumpError::ChangeTooSmall(fee));
+        }
+        change.value = change_value as i32;
+
+        let mut inputs = vec![];
+        for vin in &original.vin {
+            let mut input = TXInput::new(vin.get_txid(), vin.get_vout());
+            input.sequence = MAX_RBF_SEQUENCE;
+            inputs.push(input);
+        }
+
+        let mut tx = Transaction::new(inputs, outputs);
+        tx.set_lock_time(original.lock_time);
+        tx.sign_with_outputs(wallet, prev_outputs.as_slice());
+        Ok(tx)
+    }
+
+    /// Index of the change output a wallet transaction paying `payments`
+    /// outputs has, if any: the wallet appends it after the payments.
+    pub fn get_change_index(&self, payments: usize) -> Option<usize> {
+        (self.vout.len() > payments).then_some(payments)
+    }
+
+    pub fn get_lock_time(&self) -> u32 {
+        self.lock_time
+    }
+
+    /// Sets the lock time, which changes the transaction id. Any signature
+    /// has to be made afterwards.
+    pub fn set_lock_time(&mut self, lock_time: u32) {
+        self.lock_time = lock_time;
+        self.id = self.hash();
+    }
+
+    /// Whether the transaction may go in a block at `at`: its lock time has
+    /// passed, or every input is final, and the relative lock of each input
+    /// has passed since the output it spends was confirmed, as given in
+    /// `confirmed` in input order. Without a confirmation for every input
+    /// it is not final.
+    pub fn is_final(&self, at: BlockTime, confirmed: &[BlockTime]) -> bool {
+        if self.is_coinbase() {
+            return true;
+        }
+        if confirmed.len() != self.vin.len() {
+            return false;
+        }
+        let lock_passed = match self.lock_time {
+            0 => true,
+            lock_time if lock_time < LOCKTIME_THRESHOLD => (lock_time as usize) < at.height,
+            lock_time => (lock_time as i64) < at.time,
         };
+        if !lock_passed && self.vin.iter().any(|vin| vin.sequence != SEQUENCE_FINAL) {
+            return false;
+        }
 
-        tx.id = tx.hash();
-        tx.sign(utxo_set.get_blockchain(), wallet.get_pkcs8());
-        tx
+        self.vin.iter().zip(confirmed).all(|(vin, confirmed)| {
+            if vin.sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG != 0 {
+                return true;
+            }
+            let lock = vin.sequence & SEQUENCE_LOCKTIME_MASK;
+            if vin.sequence & SEQUENCE_LOCKTIME_TYPE_FLAG != 0 {
+                at.time >= confirmed.time + ((lock as i64) << SEQUENCE_LOCKTIME_GRANULARITY)
+            } else {
+                at.height >= confirmed.height + lock as usize
+            }
+        })
+    }
+
+    /// Whether any input opts in to replace-by-fee.
+    pub fn signals_rbf(&self) -> bool {
+        self.vin.iter().any(|vin| vin.sequence <= MAX_RBF_SEQUENCE)
     }
 
     fn trimmed_copy(&self) -> Transaction {
         let mut inputs = vec![];
         let mut outputs = vec![];
         for input in &self.vin {
-            let txinput = TXInput::new(input.get_txid(), input.get_vout());
+            let mut txinput = TXInput::new(input.get_txid(), input.get_vout());
+            txinput.sequence = input.sequence;
             inputs.push(txinput);
         }
         for output in &self.vout {
@@ -166,76 +703,211 @@ impl Transaction {
             id: self.id.clone(),
             vin: inputs,
             vout: outputs,
+            lock_time: self.lock_time,
         }
     }
 
-    fn sign(&mut self, blockchain: &Blockchain, pkcs8: &[u8]) {
+    /// Hash signed for input `idx` with `hash_type`:
+    ///
+    /// 1. Every unlocking script is cleared, except that of input `idx`,
+    ///    which is replaced by `script_code`, the locking script of the
+    ///    output it spends.
+    /// 2. With `NONE` the outputs are dropped. With `SINGLE` those after
+    ///    index `idx` are dropped and those before it blanked to a value of
+    ///    -1 and an empty script. Both zero the sequence numbers of the
+    ///    other inputs, so they can be updated.
+    /// 3. With `ANYONECANPAY` only input `idx` is kept.
+    /// 4. The serialized result, without the id, is hashed followed by the
+    ///    hash type byte.
+    ///
+    /// `SINGLE` has nothing to sign when there is no output `idx`.
+    pub fn signature_hash(
+        &self,
+        idx: usize,
+        script_code: &Script,
+        hash_type: SigHashType,
+    ) -> Option<Vec<u8>> {
         let mut tx_copy = self.trimmed_copy();
-
-        for (idx, vin) in self.vin.iter_mut().enumerate() {
-            let prev_tx_option = blockchain.find_transaction(vin.get_txid());
-            if prev_tx_option.is_none() {
-                panic!("Error: Previous transaction is not correct")
+        tx_copy.vin[idx].script_sig = script_code.clone();
+        if hash_type.base() == SigHashType::SINGLE {
+            if idx >= tx_copy.vout.len() {
+                return None;
             }
-            let prev_tx = prev_tx_option.unwrap();
-            tx_copy.vin[idx].signature = vec![];
-            tx_copy.vin[idx].pub_key = prev_tx.vout[vin.vout].pub_key_hash.clone();
-            tx_copy.id = tx_copy.hash();
-            tx_copy.vin[idx].pub_key = vec![];
+            tx_copy.vout.truncate(idx + 1);
+            for output in &mut tx_copy.vout[..idx] {
+                *output = TXOutput::with_script(-1, Script::default());
+            }
+        } else if hash_type.base() == SigHashType::NONE {
+            tx_copy.vout.clear();
+        }
+        if hash_type.base() != SigHashType::ALL {
+            for (other, input) in tx_copy.vin.iter_mut().enumerate() {
+                if other != idx {
+                    input.sequence = 0;
+                }
+            }
+        }
+        if hash_type.is_anyone_can_pay() {
+            tx_copy.vin = vec![tx_copy.vin.swap_remove(idx)];
+        }
+        let mut data = tx_copy.serialize_without_id();
+        data.push(hash_type.get_byte());
+        Some(crate::sha256_digest(data.as_slice()))
+    }
 
-            let signature = crate::ecdsa_p256_sha256_sign_digest(pkcs8, tx_copy.get_id());
-            vin.signature = signature
+    /// Signature of `wallet` over input `idx`, spending an output locked with
+    /// `script_code`, followed by `hash_type`.
+    pub fn create_signature(
+        &self,
+        idx: usize,
+        script_code: &Script,
+        wallet: &Wallet,
+        hash_type: SigHashType,
+    ) -> Vec<u8> {
+        let hash = self
+            .signature_hash(idx, script_code, hash_type)
+            .unwrap_or_else(|| panic!("No output {} for a SINGLE signature to commit to", idx));
+        let mut signature = crate::ecdsa_p256_sha256_sign_digest(wallet.get_pkcs8(), &hash);
+        signature.push(hash_type.get_byte());
+        signature
+    }
+
+    /// Replaces the unlocking script of input `idx`.
+    pub fn set_script_sig(&mut self, idx: usize, script_sig: Script) {
+        self.vin[idx].script_sig = script_sig;
+    }
+
+    /// Signs every input as spending a pay to public key hash output of
+    /// `wallet`. `prev_outputs` are the outputs spent, in input order.
+    pub fn sign_with_outputs(&mut self, wallet: &Wallet, prev_outputs: &[TXOutput]) {
+        for (idx, prev_output) in prev_outputs.iter().enumerate() {
+            self.sign_input(idx, wallet, prev_output, SigHashType::ALL);
         }
     }
 
+    /// Signs input `idx` with `hash_type` as spending `prev_output`, a pay
+    /// to public key hash output of `wallet`.
+    pub fn sign_input(
+        &mut self,
+        idx: usize,
+        wallet: &Wallet,
+        prev_output: &TXOutput,
+        hash_type: SigHashType,
+    ) {
+        let signature =
+            self.create_signature(idx, prev_output.get_script_pubkey(), wallet, hash_type);
+        self.vin[idx].script_sig =
+            Script::new_p2pkh_unlock(signature.as_slice(), wallet.get_public_key());
+    }
+
     pub fn verify(&self, blockchain: &Blockchain) -> bool {
         if self.is_coinbase() {
             return true;
         }
+        let prev_outputs = self.find_prev_outputs(blockchain);
+        self.verify_with_outputs(prev_outputs.as_slice())
+    }
 
-        let mut tx_copy = self.trimmed_copy();
+    /// Runs the unlocking script of every input against the locking script
+    /// of the output it spends.
+    pub fn verify_with_outputs(&self, prev_outputs: &[TXOutput]) -> bool {
+        if self.is_coinbase() {
+            return true;
+        }
+        if prev_outputs.len() != self.vin.len() {
+            return false;
+        }
 
-        for (idx, vin) in self.vin.iter().enumerate() {
+        self.vin
+            .iter()
+            .zip(prev_outputs)
+            .enumerate()
+            .all(|(idx, (vin, prev_output))| {
+                let checker = TransactionChecker {
+                    tx: self,
+                    input: idx,
+                    script_code: prev_output.get_script_pubkey(),
+                };
+                verify_script(&vin.script_sig, prev_output.get_script_pubkey(), &checker).is_ok()
+            })
+    }
+
+    fn find_prev_outputs(&self, blockchain: &Blockchain) -> Vec<TXOutput> {
+        let mut prev_outputs = vec![];
+        for vin in &self.vin {
             let prev_tx_option = blockchain.find_transaction(vin.get_txid());
             if prev_tx_option.is_none() {
                 panic!("Error: Previous transaction is not correct")
             }
             let prev_tx = prev_tx_option.unwrap();
-            tx_copy.vin[idx].signature = vec![];
-            tx_copy.vin[idx].pub_key = prev_tx.vout[vin.vout].pub_key_hash.clone();
-            tx_copy.id = tx_copy.hash();
-            tx_copy.vin[idx].pub_key = vec![];
-
-            let verify = crate::ecdsa_p256_sha256_sign_verify(
-                vin.pub_key.as_slice(),
-                vin.signature.as_slice(),
-                tx_copy.get_id(),
-            );
-
-            if !verify {
-                return false;
-            }
+            prev_outputs.push(prev_tx.vout[vin.vout].clone());
         }
-        true
+        prev_outputs
+    }
+
+    /// Returns the fee paid by this transaction, given the outputs its inputs spend.
+    pub fn get_fee(&self, prev_outputs: &[TXOutput]) -> i64 {
+        let input_value: i64 = prev_outputs.iter().map(|out| out.get_value() as i64).sum();
+        input_value - self.get_output_value()
+    }
+
+    pub fn get_output_value(&self) -> i64 {
+        self.vout.iter().map(|out| out.get_value() as i64).sum()
+    }
+
+    /// Sum of the output values, or `None` if one of them is negative or
+    /// the sum overflows.
+    pub fn checked_output_value(&self) -> Option<i64> {
+        self.vout.iter().try_fold(0i64, |total, out| {
+            if out.get_value() < 0 {
+                return None;
+            }
+            total.checked_add(i64::from(out.get_value()))
+        })
     }
 
     pub fn is_coinbase(&self) -> bool {
-        self.vin.len() == 1 && self.vin[0].pub_key.len() == 0
+        self.vin.len() == 1 && self.vin[0].txid.is_empty()
     }
 
-    fn hash(&mut self) -> Vec<u8> {
+    /// The txid, hashed without the unlocking scripts, so that signing a
+    /// transaction or changing its signatures leaves its id as it is. A
+    /// coinbase keeps its input script, which makes it unique.
+    fn hash(&self) -> Vec<u8> {
+        if self.is_coinbase() {
+            self.get_wtxid()
+        } else {
+            self.trimmed_copy().get_wtxid()
+        }
+    }
+
+    fn serialize_without_id(&self) -> Vec<u8> {
         let tx_copy = Transaction {
             id: vec![],
             vin: self.vin.clone(),
             vout: self.vout.clone(),
+            lock_time: self.lock_time,
         };
-        crate::sha256_digest(tx_copy.serialize().as_slice())
+        tx_copy.serialize()
     }
 
+    /// Id of the transaction, which does not cover the unlocking scripts.
     pub fn get_id(&self) -> &[u8] {
         self.id.as_slice()
     }
 
+    /// Hash of the whole transaction, unlocking scripts included, which
+    /// blocks commit to.
+    pub fn get_wtxid(&self) -> Vec<u8> {
+        crate::sha256_digest(self.serialize_without_id().as_slice())
+    }
+
+    /// Whether the id carried along is that of the contents, as received
+    /// transactions are only trusted once checked.
+    pub fn has_valid_id(&self) -> bool {
+        self.id == self.hash()
+    }
+
     pub fn get_id_bytes(&self) -> Vec<u8> {
         self.id.clone()
     }
@@ -248,6 +920,27 @@ impl Transaction {
         self.vout.as_slice()
     }
 
+    /// Serialized size in bytes.
+    pub fn get_size(&self) -> usize {
+        self.serialize().len()
+    }
+
+    /// Serialized size without the unlocking scripts, that of the
+    /// transaction as its txid covers it.
+    pub fn get_base_size(&self) -> usize {
+        if self.is_coinbase() {
+            self.get_size()
+        } else {
+            self.trimmed_copy().get_size()
+        }
+    }
+
+    /// Weight of the transaction: its base size scaled by
+    /// `WITNESS_SCALE_FACTOR`, plus the bytes of its unlocking scripts.
+    pub fn get_weight(&self) -> usize {
+        self.get_base_size() * (WITNESS_SCALE_FACTOR - 1) + self.get_size()
+    }
+
     pub fn serialize(&self) -> Vec<u8> {
         bincode::serialize(self).unwrap().to_vec()
     }
@@ -259,7 +952,12 @@ impl Transaction {
 
 #[cfg(test)]
 mod tests {
-    use crate::{Blockchain, Transaction, UTXOSet};
+    use super::{
+        BlockTime, LOCKTIME_THRESHOLD, MAX_NON_FINAL_SEQUENCE, SEQUENCE_FINAL,
+        SEQUENCE_LOCKTIME_TYPE_FLAG, SigHashType, TXInput, TXOutput, TransactionError,
+    };
+    use crate::coin_selection::{Coin, LargestFirst, Selection};
+    use crate::{Blockchain, Script, Transaction, UTXOSet, Wallet};
     use data_encoding::HEXLOWER;
 
     #[test]
@@ -277,6 +975,174 @@ mod tests {
         assert_eq!(tx.get_id(), new_tx.get_id())
     }
 
+    #[test]
+    fn test_sign_and_verify_with_outputs() {
+        let (wallet, other) = (Wallet::new(), Wallet::new());
+        let prev_outputs = vec![
+            TXOutput::new(10, wallet.get_address().as_str()),
+            TXOutput::new(5, wallet.get_address().as_str()),
+        ];
+        let vin = vec![TXInput::new(b"funding", 0), TXInput::new(b"funding", 1)];
+        let vout = vec![TXOutput::new(14, other.get_address().as_str())];
+        let mut tx = Transaction::new(vin, vout);
+        tx.sign_with_outputs(&wallet, prev_outputs.as_slice());
+        assert!(tx.verify_with_outputs(prev_outputs.as_slice()));
+
+        // Signatures commit to the locking script of the spent output.
+        let mut stolen = prev_outputs.clone();
+        stolen[1] = TXOutput::new(5, other.get_address().as_str());
+        assert!(!tx.verify_with_outputs(stolen.as_slice()));
+
+        let mut forged = tx.clone();
+        forged.sign_with_outputs(&other, prev_outputs.as_slice());
+        assert!(!forged.verify_with_outputs(prev_outputs.as_slice()));
+    }
+
+    #[test]
+    fn test_signature_hash_types() {
+        let (wallet, other) = (Wallet::new(), Wallet::new());
+        let funding = TXOutput::new(10, wallet.get_address().as_str());
+        let other_funding = TXOutput::new(5, other.get_address().as_str());
+        let pay = |value| TXOutput::new(value, other.get_address().as_str());
+        let new_tx = |vout| Transaction::new(vec![TXInput::new(b"funding", 0)], vout);
+
+        // Signing leaves the txid as it is, but not the wtxid.
+        let mut tx = new_tx(vec![pay(9)]);
+        let (txid, unsigned_wtxid) = (tx.get_id_bytes(), tx.get_wtxid());
+        tx.sign_input(0, &wallet, &funding, SigHashType::ALL);
+        assert_eq!(tx.get_id(), txid.as_slice());
+        assert!(tx.has_valid_id());
+        assert_ne!(tx.get_wtxid(), unsigned_wtxid);
+        let mut resigned = tx.clone();
+        resigned.sign_input(0, &wallet, &funding, SigHashType::ALL);
+        assert_eq!(resigned.get_id(), txid.as_slice());
+        let mut forged = tx.clone();
+        forged.vout[0] = pay(10);
+        assert!(!forged.has_valid_id());
+
+        // Changes the outputs after signing with `hash_type`.
+        let redirect = |hash_type, vout: Vec<TXOutput>, idx| {
+            let mut tx = new_tx(vout);
+            tx.vin.push(TXInput::new(b"other", 0));
+            tx.sign_input(0, &wallet, &funding, hash_type);
+            tx.vout[idx] = pay(1);
+            tx.sign_input(1, &other, &other_funding, SigHashType::ALL);
+            tx.verify_with_outputs(&[funding.clone(), other_funding.clone()])
+        };
+        assert!(!redirect(SigHashType::ALL, vec![pay(9)], 0));
+        assert!(redirect(SigHashType::NONE, vec![pay(9)], 0));
+        // SINGLE only commits to the output of the signed input.
+        assert!(!redirect(SigHashType::SINGLE, vec![pay(9), pay(5)], 0));
+        assert!(redirect(SigHashType::SINGLE, vec![pay(9), pay(5)], 1));
+        let tx = Transaction::new(
+            vec![TXInput::new(b"funding", 0), TXInput::new(b"other", 0)],
+            vec![pay(9)],
+        );
+        assert!(
+            tx.signature_hash(1, other_funding.get_script_pubkey(), SigHashType::SINGLE)
+                .is_none()
+        );
+
+        // ANYONECANPAY lets others add inputs.
+        let add_input = |hash_type| {
+            let mut tx = new_tx(vec![pay(14)]);
+            tx.sign_input(0, &wallet, &funding, hash_type);
+            tx.vin.push(TXInput::new(b"other", 0));
+            tx.sign_input(1, &other, &other_funding, SigHashType::ALL);
+            tx.verify_with_outputs(&[funding.clone(), other_funding.clone()])
+        };
+        assert!(!add_input(SigHashType::ALL));
+        assert!(add_input(SigHashType::ALL.with_anyone_can_pay()));
+
+        // The hash type byte is part of what is signed.
+        let mut tx = new_tx(vec![pay(9)]);
+        let mut signature =
+            tx.create_signature(0, funding.get_script_pubkey(), &wallet, SigHashType::ALL);
+        *signature.last_mut().unwrap() = SigHashType::NONE.get_byte();
+        tx.set_script_sig(
+            0,
+            Script::new_p2pkh_unlock(&signature, wallet.get_public_key()),
+        );
+        assert!(!tx.verify_with_outputs(&[funding.clone()]));
+        *signature.last_mut().unwrap() = 0;
+        tx.set_script_sig(
+            0,
+            Script::new_p2pkh_unlock(&signature, wallet.get_public_key()),
+        );
+        assert!(!tx.verify_with_outputs(&[funding.clone()]));
+
+        let single = SigHashType::from_name("single|anyonecanpay").unwrap();
+        assert_eq!(single, SigHashType::SINGLE.with_anyone_can_pay());
+        assert_eq!(single.to_string(), "SINGLE|ANYONECANPAY");
+        assert_eq!(SigHashType::from_byte(single.get_byte()), Some(single));
+        assert!(SigHashType::from_name("ALL|NONE").is_none());
+        assert!(SigHashType::from_byte(0x04).is_none());
+    }
+
+    #[test]
+    fn test_is_final() {
+        let at = BlockTime {
+            height: 10,
+            time: 1_700_000_000,
+        };
+        let confirmed = [BlockTime {
+            height: 8,
+            time: at.time - 1024,
+        }];
+        let tx = |lock_time: u32, sequence: u32| {
+            let mut input = TXInput::new(b"funding", 0);
+            input.set_sequence(sequence);
+            let mut tx = Transaction::new(vec![input], vec![]);
+            tx.set_lock_time(lock_time);
+            tx
+        };
+
+        assert!(tx(9, MAX_NON_FINAL_SEQUENCE).is_final(at, &confirmed));
+        assert!(!tx(10, MAX_NON_FINAL_SEQUENCE).is_final(at, &confirmed));
+        assert!(tx(10, SEQUENCE_FINAL).is_final(at, &confirmed));
+        assert!(!tx(at.time as u32, MAX_NON_FINAL_SEQUENCE).is_final(at, &confirmed));
+
+        // Relative locks of 2 blocks, then of 2 and 3 units of 512 seconds.
+        assert!(tx(0, 2).is_final(at, &confirmed));
+        assert!(!tx(0, 3).is_final(at, &confirmed));
+        assert!(tx(0, SEQUENCE_LOCKTIME_TYPE_FLAG | 2).is_final(at, &confirmed));
+        assert!(!tx(0, SEQUENCE_LOCKTIME_TYPE_FLAG | 3).is_final(at, &confirmed));
+        assert!(!tx(0, 2).is_final(at, &[]));
+    }
+
+    #[test]
+    fn test_lock_time_script() {
+        let wallet = Wallet::new();
+        let pub_key_hash = crate::hash_pub_key(wallet.get_public_key());
+        let vesting = TXOutput::with_script(10, Script::new_lock_time_p2pkh(100, &pub_key_hash));
+        let spend = |lock_time: u32, sequence: u32| {
+            let mut input = TXInput::new(b"vesting", 0);
+            input.set_sequence(sequence);
+            let mut tx =
+                Transaction::new(vec![input], vec![TXOutput::new(9, &wallet.get_address())]);
+            tx.set_lock_time(lock_time);
+            tx.sign_with_outputs(&wallet, &[vesting.clone()]);
+            tx.verify_with_outputs(&[vesting.clone()])
+        };
+        assert!(spend(100, MAX_NON_FINAL_SEQUENCE));
+        assert!(!spend(99, MAX_NON_FINAL_SEQUENCE));
+        assert!(!spend(100, SEQUENCE_FINAL));
+        assert!(!spend(LOCKTIME_THRESHOLD + 100, MAX_NON_FINAL_SEQUENCE));
+
+        let relative = TXOutput::with_script(10, Script::new_sequence_p2pkh(5, &pub_key_hash));
+        let spend = |sequence: u32| {
+            let mut input = TXInput::new(b"relative", 0);
+            input.set_sequence(sequence);
+            let mut tx = Transaction::new(vec![input], vec![]);
+            tx.sign_with_outputs(&wallet, &[relative.clone()]);
+            tx.verify_with_outputs(&[relative.clone()])
+        };
+        assert!(spend(5));
+        assert!(!spend(4));
+        assert!(!spend(SEQUENCE_LOCKTIME_TYPE_FLAG | 5));
+        assert!(!spend(SEQUENCE_FINAL));
+    }
+
     #[test]
     fn new_utxo_transaction() {
         let blockchain = Blockchain::new_blockchain();
@@ -285,10 +1151,53 @@ mod tests {
             "13SDifQUyLGCwFjh64vihoWQcGsTozHuQb",
             "1LecNaLYsDoxRtxBBWKMNbLvccftmFZWcv",
             5,
+            1,
+            false,
+            0,
             &utxo_set,
         );
-        let txid_hex = HEXLOWER.encode(tx.get_id());
+        match tx {
+            Ok(tx) => println!("txid_hex = {}", HEXLOWER.encode(tx.get_id())),
+            Err(err) => println!("{}", err),
+        }
+    }
 
-        println!("txid_hex = {}", txid_hex);
+    #[test]
+    fn new_batch_transaction_overflow() {
+        let utxo_set = UTXOSet::new(Blockchain::new_blockchain());
+        let payments = vec![
+            ("1LecNaLYsDoxRtxBBWKMNbLvccftmFZWcv".to_string(), i32::MAX),
+            ("13SDifQUyLGCwFjh64vihoWQcGsTozHuQb".to_string(), 1),
+        ];
+        let tx = Transaction::new_batch_transaction(
+            "13SDifQUyLGCwFjh64vihoWQcGsTozHuQb",
+            payments.as_slice(),
+            1,
+            false,
+            &utxo_set,
+        );
+        assert_eq!(tx.err(), Some(TransactionError::AmountOverflow));
+        let tx = Transaction::new_batch_transaction(
+            "13SDifQUyLGCwFjh64vihoWQcGsTozHuQb",
+            &[],
+            1,
+            false,
+            &utxo_set,
+        );
+        assert_eq!(tx.err(), Some(TransactionError::NoPayments));
+    }
+
+    #[test]
+    fn from_selection_mismatch() {
+        let address = Wallet::new().get_address();
+        let coins = vec![Coin::new(
+            b"funding",
+            0,
+            TXOutput::new(20, address.as_str()),
+        )];
+        let selection = Selection::new(&LargestFirst, coins.as_slice(), 10, 1).unwrap();
+        let outputs = vec![TXOutput::new(5, address.as_str())];
+        let tx = Transaction::from_selection(address.as_str(), outputs, &selection, false, 0);
+        assert_eq!(tx.err(), Some(TransactionError::SelectionMismatch));
     }
 }
@@ -1,75 +1,133 @@
-use crate::transaction::TXOutput;
-use crate::{Block, Blockchain};
+use crate::coin_selection::Coin;
+use crate::transaction::{BlockTime, TXOutput};
+use crate::{Block, Blockchain, Script};
 
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
 use data_encoding::HEXLOWER;
+use serde::{Deserialize, Serialize};
 
 const UTXO_TREE: &str = "chainstate";
+const UTXO_META_TREE: &str = "chainstate_meta";
+const FORMAT_VERSION_KEY: &str = "format_version";
+/// Version of the entries of `UTXO_TREE`. Chainstates without a version
+/// hold a list of the unspent outputs, which loses their indexes once an
+/// earlier one is spent. Version 1 keyed the outputs by index; version 2
+/// added the confirming block; version 3 dates it by the median time past
+/// instead of its own timestamp. Any other version is rebuilt from the
+/// blocks.
+const FORMAT_VERSION: u32 = 3;
+
+/// Unspent outputs of one transaction, stored under its id along with the
+/// block that confirmed it, which relative lock times count from.
+#[derive(Serialize, Deserialize)]
+struct UnspentOutputs {
+    confirmed: BlockTime,
+    outputs: BTreeMap<usize, TXOutput>,
+}
+
+impl UnspentOutputs {
+    fn serialize(&self) -> Vec<u8> {
+        bincode::serialize(self).expect("Unable to serialize TXOutput")
+    }
+
+    fn deserialize(bytes: &[u8]) -> UnspentOutputs {
+        bincode::deserialize(bytes).expect("Unable to deserialize TXOutput")
+    }
+}
 
 pub struct UTXOSet {
     blockchain: Blockchain,
 }
 
 impl UTXOSet {
+    /// Opens the chainstate of `blockchain`, rebuilding it from the blocks
+    /// if it was written in another format.
     pub fn new(blockchain: Blockchain) -> UTXOSet {
-        UTXOSet { blockchain }
+        let utxo_set = UTXOSet { blockchain };
+        if utxo_set.get_format_version() != Some(FORMAT_VERSION) {
+            utxo_set.reindex();
+        }
+        utxo_set
+    }
+
+    fn get_format_version(&self) -> Option<u32> {
+        let db = self.blockchain.get_db();
+        let meta_tree = db.open_tree(UTXO_META_TREE).unwrap();
+        let bytes = meta_tree.get(FORMAT_VERSION_KEY).unwrap()?;
+        Some(u32::from_be_bytes(bytes.as_ref().try_into().ok()?))
     }
 
     pub fn get_blockchain(&self) -> &Blockchain {
         &self.blockchain
     }
 
-    pub fn find_spendable_outputs(
-        &self,
-        pub_key_hash: &[u8],
-        amount: i32,
-    ) -> (i32, HashMap<String, Vec<usize>>) {
-        let mut unspent_outputs: HashMap<String, Vec<usize>> = HashMap::new();
-        let mut accumlated = 0;
+    pub fn find_coins(&self, address: &str) -> Vec<Coin> {
+        self.find_outputs_locked_by(&Script::for_address(address))
+            .into_iter()
+            .map(|(txid, vout, output)| Coin::new(txid.as_slice(), vout, output))
+            .collect()
+    }
+
+    pub fn find_utxo(&self, pub_key_hash: &[u8]) -> Vec<TXOutput> {
         let db = self.blockchain.get_db();
         let utxo_tree = db.open_tree(UTXO_TREE).unwrap();
+        let mut utxos = Vec::new();
 
         for item in utxo_tree.iter() {
-            let (k, v) = item.unwrap();
-            let txid_hex = HEXLOWER.encode(k.to_vec().as_slice());
-            let outs: Vec<TXOutput> = bincode::deserialize(v.to_vec().as_slice())
-                .expect("Unable to deserialize TXOutput");
-            for (idx, out) in outs.iter().enumerate() {
-                if out.is_locked_with_key(pub_key_hash) && accumlated < amount {
-                    accumlated += out.get_value();
-                    if unspent_outputs.contains_key(txid_hex.as_str()) {
-                        unspent_outputs
-                            .get_mut(txid_hex.as_str())
-                            .unwrap()
-                            .push(idx);
-                    } else {
-                        unspent_outputs.insert(txid_hex.clone(), vec![idx]);
-                    }
+            let (_, v) = item.unwrap();
+            let outs = UnspentOutputs::deserialize(v.as_ref()).outputs;
+            for out in outs.values() {
+                if out.is_locked_with_key(pub_key_hash) {
+                    utxos.push(out.clone());
                 }
             }
         }
-        (accumlated, unspent_outputs)
+        utxos
     }
 
-    pub fn find_utxo(&self, pub_key_hash: &[u8]) -> Vec<TXOutput> {
+    /// Unspent outputs locked with exactly `script_pubkey`, with the id of
+    /// their transaction and their index in it.
+    pub fn find_outputs_locked_by(
+        &self,
+        script_pubkey: &Script,
+    ) -> Vec<(Vec<u8>, usize, TXOutput)> {
         let db = self.blockchain.get_db();
         let utxo_tree = db.open_tree(UTXO_TREE).unwrap();
         let mut utxos = Vec::new();
 
         for item in utxo_tree.iter() {
-            let (_, v) = item.unwrap();
-            let outs: Vec<TXOutput> = bincode::deserialize(v.to_vec().as_slice())
-                .expect("Unable to deserialize TXOutput");
-            for out in outs.iter() {
-                if out.is_locked_with_key(pub_key_hash) {
-                    utxos.push(out.clone());
+            let (k, v) = item.unwrap();
+            let outs = UnspentOutputs::deserialize(v.as_ref()).outputs;
+            for (idx, out) in outs {
+                if out.get_script_pubkey() == script_pubkey {
+                    utxos.push((k.to_vec(), idx, out));
                 }
             }
         }
         utxos
     }
 
+    pub fn find_output(&self, txid: &[u8], vout: usize) -> Option<TXOutput> {
+        let db = self.blockchain.get_db();
+        let utxo_tree = db.open_tree(UTXO_TREE).unwrap();
+
+        let outs_bytes = utxo_tree.get(txid).unwrap()?;
+        UnspentOutputs::deserialize(outs_bytes.as_ref())
+            .outputs
+            .remove(&vout)
+    }
+
+    /// Height and time of the block that confirmed the unspent outputs of
+    /// transaction `txid`.
+    pub fn find_confirmation(&self, txid: &[u8]) -> Option<BlockTime> {
+        let db = self.blockchain.get_db();
+        let utxo_tree = db.open_tree(UTXO_TREE).unwrap();
+
+        let outs_bytes = utxo_tree.get(txid).unwrap()?;
+        Some(UnspentOutputs::deserialize(outs_bytes.as_ref()).confirmed)
+    }
+
     pub fn count_transactions(&self) -> i32 {
         let db = self.blockchain.get_db();
         let utxo_tree = db.open_tree(UTXO_TREE).unwrap();
@@ -86,11 +144,16 @@ impl UTXOSet {
         let _ = utxo_tree.clear().unwrap();
 
         let utxo_map = self.blockchain.find_utxo();
-        for (txid_hex, outs) in &utxo_map {
+        for (txid_hex, (confirmed, outputs)) in utxo_map {
             let txid = HEXLOWER.decode(txid_hex.as_bytes()).unwrap();
-            let value = bincode::serialize(outs).unwrap();
+            let value = UnspentOutputs { confirmed, outputs }.serialize();
             let _ = utxo_tree.insert(txid.as_slice(), value).unwrap();
         }
+
+        let meta_tree = db.open_tree(UTXO_META_TREE).unwrap();
+        let _ = meta_tree
+            .insert(FORMAT_VERSION_KEY, FORMAT_VERSION.to_be_bytes().as_slice())
+            .unwrap();
     }
 
     pub fn update(&self, block: &Block) {
@@ -100,31 +163,30 @@ impl UTXOSet {
         for tx in block.get_transactions() {
             if tx.is_coinbase() == false {
                 for vin in tx.get_vin() {
-                    let mut updated_outs = vec![];
                     let outs_bytes = utxo_tree.get(vin.get_txid()).unwrap().unwrap();
-                    let outs: Vec<TXOutput> = bincode::deserialize(outs_bytes.as_ref())
-                        .expect("Unable to deserialize TXOutput");
-                    for (idx, out) in outs.iter().enumerate() {
-                        if idx != vin.get_vout() {
-                            updated_outs.push(out.clone());
-                        }
-                    }
-                    if updated_outs.len() == 0 {
+                    let mut outs = UnspentOutputs::deserialize(outs_bytes.as_ref());
+                    outs.outputs.remove(&vin.get_vout());
+                    if outs.outputs.is_empty() {
                         let _ = utxo_tree.remove(vin.get_txid()).unwrap();
                     } else {
-                        let outs_bytes = bincode::serialize(&updated_outs)
-                            .expect("Unable to serialize TXOutput");
-                        utxo_tree.insert(vin.get_txid(), outs_bytes).unwrap();
+                        utxo_tree.insert(vin.get_txid(), outs.serialize()).unwrap();
                     }
                 }
             }
-            let mut new_outputs = vec![];
-            for out in tx.get_vout() {
-                new_outputs.push(out.clone());
+            let mut new_outputs = BTreeMap::new();
+            for (idx, out) in tx.get_vout().iter().enumerate() {
+                if out.is_spendable() {
+                    new_outputs.insert(idx, out.clone());
+                }
+            }
+            if new_outputs.is_empty() {
+                continue;
             }
-            let outs_bytes =
-                bincode::serialize(&new_outputs).expect("Unable to serialize TXOutput");
-            let _ = utxo_tree.insert(tx.get_id(), outs_bytes).unwrap();
+            let outs = UnspentOutputs {
+                confirmed: self.blockchain.get_block_time(block),
+                outputs: new_outputs,
+            };
+            let _ = utxo_tree.insert(tx.get_id(), outs.serialize()).unwrap();
         }
     }
 }
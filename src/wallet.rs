@@ -2,6 +2,16 @@ use ring::signature::{ECDSA_P256_SHA256_FIXED_SIGNING, EcdsaKeyPair, KeyPair};
 use serde::{Deserialize, Serialize};
 
 const VERSION: u8 = 0x00;
+/// PKCS#8 v1 encoding of a P-256 key pair as ring writes it, around the
+/// 32 byte private key, before the 65 byte public key.
+const PKCS8_PREFIX: &[u8] = &[
+    0x30, 0x81, 0x87, 0x02, 0x01, 0x00, 0x30, 0x13, 0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02,
+    0x01, 0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07, 0x04, 0x6d, 0x30, 0x6b, 0x02,
+    0x01, 0x01, 0x04, 0x20,
+];
+const PKCS8_PUBLIC_KEY_PREFIX: &[u8] = &[0xa1, 0x44, 0x03, 0x42, 0x00];
+/// Version byte of pay to script hash addresses, which start with a 3.
+pub const P2SH_VERSION: u8 = 0x05;
 pub const ADDRESS_CHECK_SUM_LEN: usize = 4;
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -20,6 +30,27 @@ impl Wallet {
         Wallet { pkcs8, public_key }
     }
 
+    pub fn from_pkcs8(pkcs8: Vec<u8>) -> Option<Wallet> {
+        let key_pair =
+            EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref()).ok()?;
+        let public_key = key_pair.public_key().as_ref().to_vec();
+        Some(Wallet { pkcs8, public_key })
+    }
+
+    pub fn from_private_key(private_key: &[u8], public_key: &[u8]) -> Option<Wallet> {
+        EcdsaKeyPair::from_private_key_and_public_key(
+            &ECDSA_P256_SHA256_FIXED_SIGNING,
+            private_key,
+            public_key,
+        )
+        .ok()?;
+        let mut pkcs8 = PKCS8_PREFIX.to_vec();
+        pkcs8.extend(private_key);
+        pkcs8.extend(PKCS8_PUBLIC_KEY_PREFIX);
+        pkcs8.extend(public_key);
+        Wallet::from_pkcs8(pkcs8)
+    }
+
     pub fn get_address(&self) -> String {
         let pub_key_hash = hash_pub_key(self.public_key.as_slice());
         let mut payload: Vec<u8> = vec![];
@@ -53,7 +84,12 @@ fn checksum(payload: &[u8]) -> Vec<u8> {
 }
 
 pub fn validate_address(address: &str) -> bool {
-    let payload = crate::base58_decode(address);
+    let Ok(payload) = bs58::decode(address).into_vec() else {
+        return false;
+    };
+    if payload.len() <= 1 + ADDRESS_CHECK_SUM_LEN {
+        return false;
+    }
     let actual_checksum = payload[payload.len() - ADDRESS_CHECK_SUM_LEN..].to_vec();
     let version = payload[0];
     let pub_key_hash = payload[1..payload.len() - ADDRESS_CHECK_SUM_LEN].to_vec();
@@ -66,9 +102,18 @@ pub fn validate_address(address: &str) -> bool {
 }
 
 pub fn convert_address(pub_hash_key: &[u8]) -> String {
+    encode_address(VERSION, pub_hash_key)
+}
+
+/// Address of the pay to script hash output locking to `script_hash`.
+pub fn convert_script_address(script_hash: &[u8]) -> String {
+    encode_address(P2SH_VERSION, script_hash)
+}
+
+fn encode_address(version: u8, hash: &[u8]) -> String {
     let mut payload: Vec<u8> = vec![];
-    payload.push(VERSION);
-    payload.extend(pub_hash_key);
+    payload.push(version);
+    payload.extend(hash);
     let checksum = checksum(payload.as_slice());
     payload.extend(checksum.as_slice());
     crate::base58_encode(payload.as_slice())
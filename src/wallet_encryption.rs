@@ -0,0 +1,201 @@
+use crate::Wallet;
+
+use std::collections::HashMap;
+
+use crypto::scrypt::{ScryptParams, scrypt};
+use ring::aead::{Aad, CHACHA20_POLY1305, LessSafeKey, NONCE_LEN, Nonce, UnboundKey};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+const KEY_LEN: usize = 32;
+const SALT_LEN: usize = 16;
+/// scrypt cost of a passphrase: 2^15 rounds over 1 KiB blocks, about
+/// 32 MiB of memory per guess.
+const KDF_LOG_N: u8 = 15;
+const KDF_R: u32 = 8;
+const KDF_P: u32 = 1;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct KdfParams {
+    salt: Vec<u8>,
+    log_n: u8,
+    r: u32,
+    p: u32,
+}
+
+impl KdfParams {
+    pub fn new() -> KdfParams {
+        KdfParams::with_cost(KDF_LOG_N, KDF_R, KDF_P)
+    }
+
+    pub fn with_cost(log_n: u8, r: u32, p: u32) -> KdfParams {
+        KdfParams {
+            salt: random_bytes(SALT_LEN),
+            log_n,
+            r,
+            p,
+        }
+    }
+
+    /// The wallet key, wiped from memory once dropped.
+    pub fn derive_key(&self, passphrase: &str) -> Zeroizing<Vec<u8>> {
+        let params = ScryptParams::new(self.log_n, self.r, self.p);
+        let mut key = Zeroizing::new(vec![0; KEY_LEN]);
+        scrypt(
+            passphrase.as_bytes(),
+            self.salt.as_slice(),
+            &params,
+            &mut key,
+        );
+        key
+    }
+}
+
+/// ChaCha20-Poly1305 ciphertext with the random nonce it was sealed under.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Sealed {
+    nonce: Vec<u8>,
+    ciphertext: Vec<u8>,
+}
+
+impl Sealed {
+    pub fn seal(key: &[u8], aad: &[u8], plaintext: &[u8]) -> Sealed {
+        let nonce = random_bytes(NONCE_LEN);
+        let mut ciphertext = plaintext.to_vec();
+        aead_key(key)
+            .seal_in_place_append_tag(
+                Nonce::try_assume_unique_for_key(nonce.as_slice()).unwrap(),
+                Aad::from(aad),
+                &mut ciphertext,
+            )
+            .expect("Unable to seal wallet key");
+        Sealed { nonce, ciphertext }
+    }
+
+    /// The plaintext, or `None` if the key is wrong or the ciphertext or
+    /// `aad` were tampered with.
+    pub fn open(&self, key: &[u8], aad: &[u8]) -> Option<Vec<u8>> {
+        let nonce = Nonce::try_assume_unique_for_key(self.nonce.as_slice()).ok()?;
+        let mut in_out = self.ciphertext.clone();
+        let plaintext = aead_key(key)
+            .open_in_place(nonce, Aad::from(aad), &mut in_out)
+            .ok()?;
+        Some(plaintext.to_vec())
+    }
+}
+
+/// A private key sealed under the wallet key. The public key stays in the
+/// clear so that addresses can be listed while locked, and is bound to the
+/// private key as associated data.
+#[derive(Clone, Serialize, Deserialize)]
+struct EncryptedKey {
+    public_key: Vec<u8>,
+    pkcs8: Sealed,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct EncryptedWallets {
+    kdf: KdfParams,
+    /// Empty plaintext sealed under the key, to check a passphrase even
+    /// when there are no keys yet.
+    check: Sealed,
+    keys: HashMap<String, EncryptedKey>,
+}
+
+impl EncryptedWallets {
+    pub fn new(kdf: KdfParams, key: &[u8], wallets: &HashMap<String, Wallet>) -> EncryptedWallets {
+        let mut encrypted = EncryptedWallets {
+            kdf,
+            check: Sealed::seal(key, &[], &[]),
+            keys: HashMap::new(),
+        };
+        for (address, wallet) in wallets {
+            encrypted.add(key, address.as_str(), wallet);
+        }
+        encrypted
+    }
+
+    pub fn derive_key(&self, passphrase: &str) -> Zeroizing<Vec<u8>> {
+        self.kdf.derive_key(passphrase)
+    }
+
+    pub fn add(&mut self, key: &[u8], address: &str, wallet: &Wallet) {
+        let public_key = wallet.get_public_key().to_vec();
+        let pkcs8 = Sealed::seal(key, public_key.as_slice(), wallet.get_pkcs8());
+        self.keys
+            .insert(address.to_string(), EncryptedKey { public_key, pkcs8 });
+    }
+
+    pub fn decrypt(&self, key: &[u8]) -> Option<HashMap<String, Wallet>> {
+        self.check.open(key, &[])?;
+        let mut wallets = HashMap::new();
+        for (address, encrypted_key) in &self.keys {
+            let aad = encrypted_key.public_key.as_slice();
+            let wallet = Wallet::from_pkcs8(encrypted_key.pkcs8.open(key, aad)?)?;
+            if wallet.get_public_key() != aad {
+                return None;
+            }
+            wallets.insert(address.clone(), wallet);
+        }
+        Some(wallets)
+    }
+
+    pub fn get_addresses(&self) -> Vec<String> {
+        self.keys.keys().cloned().collect()
+    }
+
+    pub fn get_public_key(&self, address: &str) -> Option<&[u8]> {
+        let encrypted_key = self.keys.get(address)?;
+        Some(encrypted_key.public_key.as_slice())
+    }
+}
+
+fn aead_key(key: &[u8]) -> LessSafeKey {
+    LessSafeKey::new(UnboundKey::new(&CHACHA20_POLY1305, key).expect("Invalid wallet key"))
+}
+
+fn random_bytes(len: usize) -> Vec<u8> {
+    let mut bytes = vec![0; len];
+    SystemRandom::new().fill(&mut bytes).unwrap();
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EncryptedWallets, KdfParams};
+    use crate::Wallet;
+
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_encrypted_wallets() {
+        let wallet = Wallet::new();
+        let address = wallet.get_address();
+        let mut wallets = HashMap::new();
+        wallets.insert(address.clone(), wallet.clone());
+        // Cheap parameters, the default cost is slow in debug builds.
+        let kdf = KdfParams::with_cost(4, 8, 1);
+        let key = kdf.derive_key("correct");
+        let mut encrypted = EncryptedWallets::new(kdf, key.as_slice(), &wallets);
+        assert_eq!(encrypted.get_addresses(), vec![address.clone()]);
+        assert_eq!(
+            encrypted.get_public_key(address.as_str()),
+            Some(wallet.get_public_key())
+        );
+
+        assert!(encrypted.decrypt(&encrypted.derive_key("wrong")).is_none());
+        assert_eq!(encrypted.derive_key("correct"), key);
+        let decrypted = encrypted.decrypt(key.as_slice()).unwrap();
+        assert_eq!(decrypted[&address].get_pkcs8(), wallet.get_pkcs8());
+
+        let other = Wallet::new();
+        encrypted.add(key.as_slice(), other.get_address().as_str(), &other);
+        assert_eq!(encrypted.decrypt(key.as_slice()).unwrap().len(), 2);
+
+        // Swapping the public key of an entry breaks its seal.
+        let mut swapped = encrypted.clone();
+        swapped.keys.get_mut(&address).unwrap().public_key = other.get_public_key().to_vec();
+        assert!(swapped.decrypt(key.as_slice()).is_none());
+    }
+}
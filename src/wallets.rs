@@ -1,36 +1,142 @@
-use crate::Wallet;
+use crate::hd_wallet;
+use crate::wallet_encryption::{EncryptedWallets, KdfParams, Sealed};
+use crate::{Blockchain, Transaction, Wallet};
 
 use std::collections::HashMap;
 use std::env::current_dir;
-use std::fs::{File, OpenOptions};
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
 use std::io::{BufWriter, Read, Write};
-use std::vec;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::{thread, vec};
+
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
 
 pub const WALLET_FILE: &str = "wallets.dat";
+const WALLET_TRANSACTIONS_TREE: &str = "wallet_transactions";
+/// Index of the change output of each wallet transaction that has one.
+const WALLET_CHANGE_TREE: &str = "wallet_change";
+/// Starts the wallet file of an encrypted or HD wallet. Any other wallet
+/// file is a bare map of wallets.
+const FILE_MAGIC: &[u8] = b"SCWALLET";
+const ENCRYPTED_VERSION: u8 = 1;
+const FILE_VERSION: u8 = 2;
+/// Associated data of the sealed HD seed, which keeps it apart from the
+/// sealed private keys.
+const HD_SEED_AAD: &[u8] = b"hd seed";
+
+#[derive(Debug, PartialEq)]
+pub enum WalletError {
+    NotFound(String),
+    Locked,
+    WrongPassphrase,
+    NotEncrypted,
+    AlreadyEncrypted,
+    HdSeedExists,
+}
+
+impl fmt::Display for WalletError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WalletError::NotFound(address) => write!(f, "no wallet for address {}", address),
+            WalletError::Locked => {
+                write!(f, "wallet is locked, unlock it with walletpassphrase")
+            }
+            WalletError::WrongPassphrase => write!(f, "wrong wallet passphrase"),
+            WalletError::NotEncrypted => write!(f, "wallet is not encrypted"),
+            WalletError::AlreadyEncrypted => write!(f, "wallet is already encrypted"),
+            WalletError::HdSeedExists => write!(f, "wallet already has an HD seed"),
+        }
+    }
+}
+
+struct Unlock {
+    key: Zeroizing<Vec<u8>>,
+    until: i64,
+}
+
+/// Key left by `Wallets::unlock`. It lives in the memory of this process
+/// only and is wiped once it expires or the wallet is locked.
+static UNLOCKED: Mutex<Option<Unlock>> = Mutex::new(None);
+
+#[derive(Clone, Serialize, Deserialize)]
+enum HdSeed {
+    Plain(Vec<u8>),
+    Encrypted(Sealed),
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct HdChain {
+    seed: HdSeed,
+    next_index: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct WalletFile {
+    wallets: HashMap<String, Wallet>,
+    encrypted: Option<EncryptedWallets>,
+    hd_chain: Option<HdChain>,
+}
 
 pub struct Wallets {
+    /// Usable key pairs. Empty while an encrypted wallet is locked.
     wallets: HashMap<String, Wallet>,
+    encrypted: Option<EncryptedWallets>,
+    hd_chain: Option<HdChain>,
+    seed: Option<Vec<u8>>,
+    key: Option<Zeroizing<Vec<u8>>>,
 }
 
 impl Wallets {
     pub fn new() -> Wallets {
         let mut wallets = Wallets {
             wallets: HashMap::new(),
+            encrypted: None,
+            hd_chain: None,
+            seed: None,
+            key: None,
         };
 
         wallets.load_from_file();
         wallets
     }
 
-    pub fn create_wallet(&mut self) -> String {
-        let wallet = Wallet::new();
+    /// Adds a new key pair, the next one on the receiving path once the
+    /// wallet has an HD seed. An encrypted wallet must be unlocked, since
+    /// the new key is sealed under the wallet key.
+    pub fn create_wallet(&mut self) -> Result<String, WalletError> {
+        let wallet = match self.hd_chain.as_mut() {
+            Some(hd_chain) => {
+                let seed = self.seed.as_ref().ok_or(WalletError::Locked)?;
+                let wallet = hd_wallet::receive_chain(seed.as_slice())
+                    .derive_child(hd_chain.next_index)
+                    .to_wallet();
+                hd_chain.next_index += 1;
+                wallet
+            }
+            None => Wallet::new(),
+        };
+        let address = self.insert_wallet(wallet)?;
+        self.save_to_file();
+        Ok(address)
+    }
+
+    fn insert_wallet(&mut self, wallet: Wallet) -> Result<String, WalletError> {
         let address = wallet.get_address();
+        if let Some(encrypted) = self.encrypted.as_mut() {
+            let key = self.key.as_ref().ok_or(WalletError::Locked)?;
+            encrypted.add(key.as_slice(), address.as_str(), &wallet);
+        }
         self.wallets.insert(address.clone(), wallet);
-        self.save_to_file();
-        address
+        Ok(address)
     }
 
     pub fn get_addresses(&self) -> Vec<String> {
+        if let Some(encrypted) = self.encrypted.as_ref() {
+            return encrypted.get_addresses();
+        }
         let mut addresses = vec![];
         for (address, _) in &self.wallets {
             addresses.push(address.clone());
@@ -45,6 +151,187 @@ impl Wallets {
         None
     }
 
+    pub fn get_signing_wallet(&self, address: &str) -> Result<&Wallet, WalletError> {
+        if let Some(wallet) = self.wallets.get(address) {
+            return Ok(wallet);
+        }
+        if self.is_locked() && self.get_public_key(address).is_some() {
+            return Err(WalletError::Locked);
+        }
+        Err(WalletError::NotFound(address.to_string()))
+    }
+
+    pub fn get_public_key(&self, address: &str) -> Option<&[u8]> {
+        match self.encrypted.as_ref() {
+            Some(encrypted) => encrypted.get_public_key(address),
+            None => self
+                .wallets
+                .get(address)
+                .map(|wallet| wallet.get_public_key()),
+        }
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.encrypted.is_some()
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.encrypted.is_some() && self.key.is_none()
+    }
+
+    pub fn is_hd(&self) -> bool {
+        self.hd_chain.is_some()
+    }
+
+    /// Derives every new address from `seed` from now on. Addresses created
+    /// before stay random and are not covered by the seed.
+    pub fn set_hd_seed(&mut self, seed: Vec<u8>) -> Result<(), WalletError> {
+        if self.hd_chain.is_some() {
+            return Err(WalletError::HdSeedExists);
+        }
+        let hd_seed = match self.encrypted.as_ref() {
+            Some(_) => {
+                let key = self.key.as_ref().ok_or(WalletError::Locked)?;
+                HdSeed::Encrypted(Sealed::seal(key.as_slice(), HD_SEED_AAD, seed.as_slice()))
+            }
+            None => HdSeed::Plain(seed.clone()),
+        };
+        self.hd_chain = Some(HdChain {
+            seed: hd_seed,
+            next_index: 0,
+        });
+        self.seed = Some(seed);
+        self.save_to_file();
+        Ok(())
+    }
+
+    /// Sets `seed` and imports its receiving addresses up to the last one
+    /// that `is_used`, looking `gap_limit` addresses past the last used one
+    /// before giving up. Returns the imported addresses.
+    pub fn recover_hd_seed(
+        &mut self,
+        seed: Vec<u8>,
+        gap_limit: u32,
+        is_used: impl Fn(&str) -> bool,
+    ) -> Result<Vec<String>, WalletError> {
+        self.set_hd_seed(seed.clone())?;
+        let chain = hd_wallet::receive_chain(seed.as_slice());
+        let mut derived = vec![];
+        let mut used = 0;
+        while (derived.len() as u32) < used + gap_limit {
+            let wallet = chain.derive_child(derived.len() as u32).to_wallet();
+            if is_used(wallet.get_address().as_str()) {
+                used = derived.len() as u32 + 1;
+            }
+            derived.push(wallet);
+        }
+        derived.truncate(used as usize);
+        let mut addresses = vec![];
+        for wallet in derived {
+            addresses.push(self.insert_wallet(wallet)?);
+        }
+        self.hd_chain.as_mut().unwrap().next_index = used;
+        self.save_to_file();
+        Ok(addresses)
+    }
+
+    /// Encrypts every private key under `passphrase` and leaves the wallet
+    /// locked.
+    pub fn encrypt(&mut self, passphrase: &str) -> Result<(), WalletError> {
+        if self.encrypted.is_some() {
+            return Err(WalletError::AlreadyEncrypted);
+        }
+        self.encrypt_with(passphrase);
+        Ok(())
+    }
+
+    /// Keeps the wallet unlocked for `timeout` seconds, for every `Wallets`
+    /// loaded by this process, which for `walletpassphrase` is the node. The
+    /// key is never written to disk.
+    pub fn unlock(&mut self, passphrase: &str, timeout: u64) -> Result<(), WalletError> {
+        self.keep_unlocked(passphrase, Some(timeout))?;
+        thread::spawn(move || {
+            thread::sleep(Duration::from_secs(timeout));
+            expire_unlock();
+        });
+        Ok(())
+    }
+
+    /// Keeps the wallet unlocked until this process exits, for a command
+    /// that signs and returns.
+    pub fn unlock_until_exit(&mut self, passphrase: &str) -> Result<(), WalletError> {
+        self.keep_unlocked(passphrase, None)
+    }
+
+    /// The timeout runs from when the key is derived, which takes a while.
+    fn keep_unlocked(&mut self, passphrase: &str, timeout: Option<u64>) -> Result<(), WalletError> {
+        let encrypted = self.encrypted.as_ref().ok_or(WalletError::NotEncrypted)?;
+        let key = encrypted.derive_key(passphrase);
+        if !self.open(key.as_slice()) {
+            return Err(WalletError::WrongPassphrase);
+        }
+        let until = match timeout {
+            Some(timeout) => crate::current_timestamp() + timeout as i64 * 1000,
+            None => i64::MAX,
+        };
+        *UNLOCKED.lock().unwrap() = Some(Unlock { key, until });
+        Ok(())
+    }
+
+    pub fn lock(&mut self) -> Result<(), WalletError> {
+        if self.encrypted.is_none() {
+            return Err(WalletError::NotEncrypted);
+        }
+        UNLOCKED.lock().unwrap().take();
+        self.wallets.clear();
+        self.seed = None;
+        self.key = None;
+        Ok(())
+    }
+
+    /// Re-encrypts every private key under `new` with a fresh salt and
+    /// leaves the wallet locked.
+    pub fn change_passphrase(&mut self, old: &str, new: &str) -> Result<(), WalletError> {
+        let encrypted = self.encrypted.as_ref().ok_or(WalletError::NotEncrypted)?;
+        if !self.open(encrypted.derive_key(old).as_slice()) {
+            return Err(WalletError::WrongPassphrase);
+        }
+        self.encrypt_with(new);
+        Ok(())
+    }
+
+    /// Seals the usable keys and seed under a fresh key of `passphrase`,
+    /// then locks the wallet.
+    fn encrypt_with(&mut self, passphrase: &str) {
+        let kdf = KdfParams::new();
+        let key = kdf.derive_key(passphrase);
+        self.encrypted = Some(EncryptedWallets::new(kdf, key.as_slice(), &self.wallets));
+        if let (Some(hd_chain), Some(seed)) = (self.hd_chain.as_mut(), self.seed.as_ref()) {
+            let sealed = Sealed::seal(key.as_slice(), HD_SEED_AAD, seed.as_slice());
+            hd_chain.seed = HdSeed::Encrypted(sealed);
+        }
+        self.lock().unwrap();
+        self.save_to_file();
+    }
+
+    fn open(&mut self, key: &[u8]) -> bool {
+        let Some(wallets) = self.encrypted.as_ref().unwrap().decrypt(key) else {
+            return false;
+        };
+        let seed = match self.hd_chain.as_ref().map(|hd_chain| &hd_chain.seed) {
+            Some(HdSeed::Encrypted(sealed)) => match sealed.open(key, HD_SEED_AAD) {
+                Some(seed) => Some(seed),
+                None => return false,
+            },
+            Some(HdSeed::Plain(seed)) => Some(seed.clone()),
+            None => None,
+        };
+        self.wallets = wallets;
+        self.seed = seed;
+        self.key = Some(Zeroizing::new(key.to_vec()));
+        true
+    }
+
     pub fn load_from_file(&mut self) {
         let path = current_dir().unwrap().join(WALLET_FILE);
         if !path.exists() {
@@ -55,24 +342,128 @@ impl Wallets {
         let metadata = file.metadata().expect("Unable to read metadata");
         let mut buf = vec![0; metadata.len() as usize];
         let _ = file.read(&mut buf).expect("Unable to read file");
-        let wallets = bincode::deserialize(&buf[..]).expect("Unable to deserialize wallets");
-        self.wallets = wallets;
+        let header_len = FILE_MAGIC.len() + 1;
+        if buf.len() < header_len || !buf.starts_with(FILE_MAGIC) {
+            let wallets = bincode::deserialize(&buf[..]).expect("Unable to deserialize wallets");
+            self.wallets = wallets;
+            return;
+        }
+        let body = &buf[header_len..];
+        let wallet_file = match buf[FILE_MAGIC.len()] {
+            ENCRYPTED_VERSION => WalletFile {
+                wallets: HashMap::new(),
+                encrypted: Some(bincode::deserialize(body).expect("Unable to deserialize wallets")),
+                hd_chain: None,
+            },
+            FILE_VERSION => bincode::deserialize(body).expect("Unable to deserialize wallets"),
+            _ => panic!("Unsupported wallet file version"),
+        };
+        self.wallets = wallet_file.wallets;
+        self.encrypted = wallet_file.encrypted;
+        self.hd_chain = wallet_file.hd_chain;
+        if let Some(HdSeed::Plain(seed)) = self.hd_chain.as_ref().map(|hd_chain| &hd_chain.seed) {
+            self.seed = Some(seed.clone());
+        }
+        if self.encrypted.is_some() {
+            self.load_unlock();
+        }
+    }
+
+    fn load_unlock(&mut self) {
+        expire_unlock();
+        let key = match UNLOCKED.lock().unwrap().as_ref() {
+            Some(unlock) => unlock.key.clone(),
+            None => return,
+        };
+        if !self.open(key.as_slice()) {
+            UNLOCKED.lock().unwrap().take();
+        }
     }
 
     fn save_to_file(&self) {
         let path = current_dir().unwrap().join(WALLET_FILE);
-        let file = OpenOptions::new()
-            .create(true)
-            .write(true)
-            .open(&path)
-            .expect("Unable to open wallets.dat");
+        let mut options = OpenOptions::new();
+        options.create(true).write(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let file = options.open(&path).expect("Unable to open wallets.dat");
+        // The mode only applies to a new file.
+        #[cfg(unix)]
+        fs::set_permissions(&path, std::os::unix::fs::PermissionsExt::from_mode(0o600))
+            .expect("Unable to set the permissions of wallets.dat");
         let mut writer = BufWriter::new(file);
-        let wallets_bytes = bincode::serialize(&self.wallets).expect("Unable to serialize wallets");
+        let wallets_bytes = if self.encrypted.is_none() && self.hd_chain.is_none() {
+            bincode::serialize(&self.wallets).expect("Unable to serialize wallets")
+        } else {
+            let wallet_file = WalletFile {
+                wallets: match self.encrypted {
+                    Some(_) => HashMap::new(),
+                    None => self.wallets.clone(),
+                },
+                encrypted: self.encrypted.clone(),
+                hd_chain: self.hd_chain.clone(),
+            };
+            let mut bytes = FILE_MAGIC.to_vec();
+            bytes.push(FILE_VERSION);
+            bytes.extend(bincode::serialize(&wallet_file).expect("Unable to serialize wallets"));
+            bytes
+        };
         writer.write(wallets_bytes.as_slice()).unwrap();
         let _ = writer.flush();
     }
 }
 
+fn expire_unlock() {
+    let mut unlocked = UNLOCKED.lock().unwrap();
+    if unlocked
+        .as_ref()
+        .is_some_and(|unlock| unlock.until <= crate::current_timestamp())
+    {
+        unlocked.take();
+    }
+}
+
+/// Transactions sent from the local wallets, kept so that they can be
+/// replaced later on.
+pub struct WalletTransactions {
+    blockchain: Blockchain,
+}
+
+impl WalletTransactions {
+    pub fn new(blockchain: Blockchain) -> WalletTransactions {
+        WalletTransactions { blockchain }
+    }
+
+    /// Records `tx`, with the index of the output returning change to the
+    /// wallet, if any.
+    pub fn add(&self, tx: &Transaction, change: Option<usize>) {
+        let db = self.blockchain.get_db();
+        let tree = db.open_tree(WALLET_TRANSACTIONS_TREE).unwrap();
+        let _ = tree.insert(tx.get_id(), tx.serialize()).unwrap();
+        if let Some(change) = change {
+            let tree = db.open_tree(WALLET_CHANGE_TREE).unwrap();
+            let _ = tree
+                .insert(tx.get_id(), (change as u32).to_be_bytes().as_slice())
+                .unwrap();
+        }
+    }
+
+    /// Index of the change output of `txid`, as recorded by `add`.
+    pub fn get_change(&self, txid: &[u8]) -> Option<usize> {
+        let db = self.blockchain.get_db();
+        let tree = db.open_tree(WALLET_CHANGE_TREE).unwrap();
+        let bytes = tree.get(txid).unwrap()?;
+        Some(u32::from_be_bytes(bytes.as_ref().try_into().ok()?) as usize)
+    }
+
+    pub fn get(&self, txid: &[u8]) -> Option<Transaction> {
+        let db = self.blockchain.get_db();
+        let tree = db.open_tree(WALLET_TRANSACTIONS_TREE).unwrap();
+        let tx_bytes = tree.get(txid).unwrap()?;
+        Some(Transaction::deserialize(tx_bytes.as_ref()))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::Wallets;
@@ -80,7 +471,7 @@ mod tests {
     #[test]
     fn test_new_wallets() {
         let mut wallets = Wallets::new();
-        let address = wallets.create_wallet();
+        let address = wallets.create_wallet().unwrap();
         println!("The new wallet address is {}", address);
     }
 
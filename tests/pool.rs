@@ -0,0 +1,221 @@
+//! Runs a node in pool mode and points a few simulated miners at it.
+
+use assert_cmd::prelude::CommandCargoExt;
+use data_encoding::HEXLOWER;
+use socratix::{BlockTemplate, PoolClient, PoolMessage, ProofOfWork, Transaction, convert_address};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const NODE_ADDR: &str = "127.0.0.1:42161";
+const POOL_ADDR: &str = "127.0.0.1:42162";
+const WORKERS: usize = 2;
+const BLOCKS_TO_FIND: usize = 3;
+const TIMEOUT: Duration = Duration::from_secs(60);
+/// Nonces tried between checks for a new job.
+const NONCE_BATCH: i64 = 16;
+
+struct Node(Child);
+
+impl Drop for Node {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn socratix(dir: &Path) -> Command {
+    let mut command = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+    command.current_dir(dir);
+    command
+}
+
+fn create_wallet(dir: &Path) -> String {
+    let output = socratix(dir).arg("createwallet").output().unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    stdout.split_whitespace().last().unwrap().to_string()
+}
+
+/// What the simulated miners have seen so far.
+#[derive(Default)]
+struct Progress {
+    blocks: usize,
+    /// Accepted shares per miner address.
+    accepted: HashMap<String, usize>,
+    /// Addresses paid by the coinbase of any job handed out.
+    payees: HashSet<String>,
+}
+
+impl Progress {
+    fn is_done(&self, workers: &[String]) -> bool {
+        self.blocks >= BLOCKS_TO_FIND
+            && workers
+                .iter()
+                .all(|worker| self.accepted.contains_key(worker) && self.payees.contains(worker))
+    }
+}
+
+/// Grinds whatever job the pool sent last, submitting every share found.
+fn simulated_miner(address: String, stop: Arc<AtomicBool>, progress: Arc<Mutex<Progress>>) {
+    let mut client = PoolClient::connect(POOL_ADDR, address.as_str()).unwrap();
+    let mut reader = client.try_clone().unwrap();
+    let (sender, messages) = mpsc::channel();
+    thread::spawn(move || {
+        while let Some(message) = reader.next_message() {
+            if sender.send(message).is_err() {
+                break;
+            }
+        }
+    });
+
+    let Ok(PoolMessage::Subscribed {
+        extranonce_start,
+        share_target,
+        ..
+    }) = messages.recv()
+    else {
+        panic!("pool did not answer the subscription")
+    };
+    let share_target = HEXLOWER.decode(share_target.as_bytes()).unwrap();
+
+    let mut job: Option<(String, BlockTemplate, Transaction)> = None;
+    let mut extra_nonce = extranonce_start;
+    let mut nonce = 0;
+    while !stop.load(Ordering::Relaxed) {
+        loop {
+            match messages.try_recv() {
+                Ok(PoolMessage::Notify {
+                    job_id,
+                    template,
+                    coinbase,
+                    ..
+                }) => {
+                    let coinbase = Transaction::deserialize(coinbase.as_slice());
+                    let mut progress = progress.lock().unwrap();
+                    for output in coinbase.get_vout() {
+                        if let Some(pub_key_hash) = output.get_pub_key_hash() {
+                            progress.payees.insert(convert_address(pub_key_hash));
+                        }
+                    }
+                    let template = BlockTemplate::deserialize(template.as_slice());
+                    job = Some((job_id, template, coinbase));
+                    extra_nonce += 1;
+                    nonce = 0;
+                }
+                Ok(PoolMessage::SubmitResult {
+                    accepted,
+                    block_found,
+                    ..
+                }) => {
+                    let mut progress = progress.lock().unwrap();
+                    if accepted {
+                        *progress.accepted.entry(address.clone()).or_default() += 1;
+                    }
+                    if block_found {
+                        progress.blocks += 1;
+                    }
+                }
+                Ok(_) => {}
+                Err(_) => break,
+            }
+        }
+        let Some((job_id, template, coinbase)) = &job else {
+            thread::sleep(Duration::from_millis(10));
+            continue;
+        };
+
+        let mut coinbase = coinbase.clone();
+        coinbase.set_extra_nonce(extra_nonce);
+        let block = template.new_block(coinbase);
+        let pow = ProofOfWork::new_proof_of_work(block.clone());
+        for _ in 0..NONCE_BATCH {
+            let hash = pow.hash_header(nonce);
+            if hash.as_slice() < share_target.as_slice() {
+                client.send(&PoolMessage::Submit {
+                    job_id: job_id.clone(),
+                    extra_nonce,
+                    timestamp: block.get_timestamp(),
+                    nonce,
+                });
+            }
+            nonce += 1;
+        }
+    }
+}
+
+#[test]
+fn pool_pays_simulated_miners() {
+    let dir = std::env::temp_dir().join(format!("socratix-pool-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let operator = create_wallet(&dir);
+    let workers: Vec<String> = (0..WORKERS).map(|_| create_wallet(&dir)).collect();
+    assert!(
+        socratix(&dir)
+            .arg("createblockchain")
+            .arg(operator.as_str())
+            .status()
+            .unwrap()
+            .success()
+    );
+
+    let _node = Node(
+        socratix(&dir)
+            .arg("startnode")
+            .arg(operator.as_str())
+            .env("NODE_ADDRESS", NODE_ADDR)
+            .env("POOL_ADDRESS", POOL_ADDR)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap(),
+    );
+    let started = Instant::now();
+    while std::net::TcpStream::connect(POOL_ADDR).is_err() {
+        assert!(started.elapsed() < TIMEOUT, "pool did not start");
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let progress = Arc::new(Mutex::new(Progress::default()));
+    let miners: Vec<_> = workers
+        .iter()
+        .map(|address| {
+            let (address, stop, progress) = (address.clone(), stop.clone(), progress.clone());
+            thread::spawn(move || simulated_miner(address, stop, progress))
+        })
+        .collect();
+
+    let started = Instant::now();
+    while !progress.lock().unwrap().is_done(&workers) && started.elapsed() < TIMEOUT {
+        thread::sleep(Duration::from_millis(50));
+    }
+    stop.store(true, Ordering::Relaxed);
+    for miner in miners {
+        miner.join().unwrap();
+    }
+
+    let progress = progress.lock().unwrap();
+    assert!(
+        progress.blocks >= BLOCKS_TO_FIND,
+        "pool found too few blocks"
+    );
+    for worker in &workers {
+        assert!(
+            progress.accepted.contains_key(worker),
+            "every miner gets shares accepted"
+        );
+        assert!(
+            progress.payees.contains(worker),
+            "coinbase pays every worker"
+        );
+    }
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,262 @@
+//! Relays blocks to a running node and checks which ones it connects.
+
+use assert_cmd::prelude::CommandCargoExt;
+use data_encoding::HEXLOWER;
+use socratix::{
+    Block, MAX_FUTURE_BLOCK_TIME, Package, ProofOfWork, SigHashType, Transaction, Wallet,
+    create_raw_transaction, get_block_template, get_mining_info, send_tx, sign_raw_transaction,
+    submit_block,
+};
+use std::io::Write;
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+/// How long a relayed block is given to be connected.
+const SETTLE: Duration = Duration::from_millis(500);
+
+struct Node {
+    child: Child,
+    dir: PathBuf,
+    addr: String,
+}
+
+impl Drop for Node {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn socratix(dir: &Path) -> Command {
+    let mut command = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+    command.current_dir(dir);
+    command
+}
+
+/// Starts a node that does not mine on `port`, on a chain holding the
+/// genesis block.
+fn start_node(port: u16) -> (Node, String) {
+    let addr = format!("127.0.0.1:{}", port);
+    let dir = std::env::temp_dir().join(format!("socratix-relay-{}-{}", port, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let output = socratix(&dir).arg("createwallet").output().unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let address = stdout.split_whitespace().last().unwrap().to_string();
+    assert!(
+        socratix(&dir)
+            .arg("createblockchain")
+            .arg(address.as_str())
+            .status()
+            .unwrap()
+            .success()
+    );
+
+    let child = socratix(&dir)
+        .arg("startnode")
+        .env("NODE_ADDRESS", addr.as_str())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let started = Instant::now();
+    while TcpStream::connect(addr.as_str()).is_err() {
+        assert!(started.elapsed() < TIMEOUT, "node did not start");
+        thread::sleep(Duration::from_millis(50));
+    }
+    (Node { child, dir, addr }, address)
+}
+
+impl Node {
+    /// Sends `block` the way a peer announces a block it was asked for.
+    fn relay(&self, block: &Block) {
+        let stream = TcpStream::connect(self.addr.as_str()).unwrap();
+        let package = Package::Block {
+            addr_from: self.addr.clone(),
+            block: block.serialize(),
+        };
+        serde_json::to_writer(&stream, &package).unwrap();
+        (&stream).flush().unwrap();
+        thread::sleep(SETTLE);
+    }
+
+    fn height(&self) -> usize {
+        get_mining_info(self.addr.as_str()).unwrap().get_height()
+    }
+
+    /// The next block, paying the whole template value to `address`.
+    fn next_block(&self, address: &str) -> Block {
+        let template = get_block_template(self.addr.as_str()).unwrap();
+        let coinbase_tx =
+            Transaction::new_coinbase_tx_with_value(address, template.get_coinbase_value() as i32);
+        template.new_block(coinbase_tx)
+    }
+}
+
+#[test]
+fn node_validates_relayed_blocks() {
+    let (node, address) = start_node(42171);
+    assert_eq!(node.height(), 0);
+
+    let unsealed = node.next_block(address.as_str());
+    node.relay(&unsealed);
+    assert_eq!(node.height(), 0, "an unsealed block is dropped");
+
+    let sealed = ProofOfWork::new_proof_of_work(node.next_block(address.as_str())).run();
+    node.relay(&sealed);
+    assert_eq!(node.height(), 1, "a valid block is connected");
+}
+
+/// A transaction sending half of the coinbase of `block` back to `wallet`,
+/// locked until `lock_time`.
+fn spend_coinbase(block: &Block, wallet: &Wallet, lock_time: u32) -> Transaction {
+    let coinbase_tx = &block.get_transactions()[0];
+    let prev_outputs = &coinbase_tx.get_vout()[..1];
+    let address = wallet.get_address();
+    let mut tx = create_raw_transaction(
+        &[format!("{}:0", HEXLOWER.encode(coinbase_tx.get_id()))],
+        &[format!("{}:{}", address, prev_outputs[0].get_value() / 2)],
+        lock_time,
+        false,
+    )
+    .unwrap();
+    assert!(sign_raw_transaction(
+        &mut tx,
+        prev_outputs,
+        &[wallet],
+        SigHashType::ALL
+    ));
+    tx
+}
+
+#[test]
+fn node_connects_one_of_two_racing_blocks() {
+    let (node, _) = start_node(42173);
+    let wallets = [Wallet::new(), Wallet::new()];
+    let blocks: Vec<Block> = wallets
+        .iter()
+        .map(|wallet| {
+            let block = node.next_block(wallet.get_address().as_str());
+            ProofOfWork::new_proof_of_work(block).run()
+        })
+        .collect();
+
+    thread::scope(|scope| {
+        for block in &blocks {
+            let node = &node;
+            scope.spawn(move || node.relay(block));
+        }
+    });
+    assert_eq!(node.height(), 1);
+
+    let tip_hash = get_block_template(node.addr.as_str())
+        .unwrap()
+        .get_prev_block_hash()
+        .to_string();
+    let winner = blocks
+        .iter()
+        .position(|block| block.get_hash() == tip_hash)
+        .expect("one of the blocks is the tip");
+    let loser = 1 - winner;
+    assert_eq!(
+        send_tx(
+            node.addr.as_str(),
+            &spend_coinbase(&blocks[loser], &wallets[loser], 0)
+        ),
+        Err("inputs are missing or already spent".to_string()),
+        "the outputs of the block left out are not in the UTXO set"
+    );
+    assert_eq!(
+        send_tx(
+            node.addr.as_str(),
+            &spend_coinbase(&blocks[winner], &wallets[winner], 0)
+        ),
+        Ok(())
+    );
+}
+
+#[test]
+fn node_checks_block_timestamps() {
+    let (node, address) = start_node(42174);
+
+    let mut future = node.next_block(address.as_str());
+    future.set_timestamp(future.get_timestamp() + MAX_FUTURE_BLOCK_TIME + 60_000);
+    node.relay(&ProofOfWork::new_proof_of_work(future).run());
+    assert_eq!(node.height(), 0, "a block from the future is dropped");
+
+    let mut past = node.next_block(address.as_str());
+    past.set_timestamp(1);
+    node.relay(&ProofOfWork::new_proof_of_work(past).run());
+    assert_eq!(node.height(), 0, "a block older than its parent is dropped");
+
+    let sealed = ProofOfWork::new_proof_of_work(node.next_block(address.as_str())).run();
+    node.relay(&sealed);
+    assert_eq!(node.height(), 1);
+}
+
+#[test]
+fn node_checks_lock_times_against_median_time_past() {
+    let (node, _) = start_node(42175);
+    let wallet = Wallet::new();
+    let address = wallet.get_address();
+    let funding = ProofOfWork::new_proof_of_work(node.next_block(address.as_str())).run();
+    node.relay(&funding);
+    assert_eq!(node.height(), 1);
+
+    // With the genesis block and `funding` on the chain, the median time
+    // past is the time of `funding`.
+    let median_time_past = (funding.get_timestamp() / 1000) as u32;
+    let spending_block = |lock_time: u32| {
+        let coinbase_tx = Transaction::new_coinbase_tx(address.as_str());
+        let tx = spend_coinbase(&funding, &wallet, lock_time);
+        let mut block = Block::new_template(funding.get_hash().to_string(), &[coinbase_tx, tx], 2);
+        // Past the lock time by its own timestamp.
+        block.set_timestamp(funding.get_timestamp() + 60_000);
+        ProofOfWork::new_proof_of_work(block).run()
+    };
+
+    node.relay(&spending_block(median_time_past + 30));
+    assert_eq!(
+        node.height(),
+        1,
+        "a spend locked past the median time past is dropped"
+    );
+    node.relay(&spending_block(median_time_past - 1));
+    assert_eq!(node.height(), 2);
+}
+
+#[test]
+fn node_answers_sent_transactions() {
+    let (node, address) = start_node(42172);
+    let coinbase_tx = Transaction::new_coinbase_tx(address.as_str());
+    assert_eq!(
+        send_tx(node.addr.as_str(), &coinbase_tx),
+        Err("coinbase transactions are not relayed".to_string())
+    );
+}
+
+#[test]
+fn node_checks_submitted_template_ids() {
+    let (node, address) = start_node(42176);
+    let template = get_block_template(node.addr.as_str()).unwrap();
+    let coinbase_tx = Transaction::new_coinbase_tx_with_value(
+        address.as_str(),
+        template.get_coinbase_value() as i32,
+    );
+    let block = ProofOfWork::new_proof_of_work(template.new_block(coinbase_tx)).run();
+
+    let unknown = submit_block(node.addr.as_str(), "00", &block).unwrap_err();
+    assert!(unknown.contains("unknown template"), "{}", unknown);
+    assert_eq!(
+        submit_block(node.addr.as_str(), template.get_template_id(), &block),
+        Ok(())
+    );
+    let stale = submit_block(node.addr.as_str(), template.get_template_id(), &block).unwrap_err();
+    assert!(stale.contains("is stale"), "{}", stale);
+}
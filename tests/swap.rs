@@ -0,0 +1,192 @@
+//! Swaps coins between two local chains with hash time-locked contracts.
+//!
+//! Each chain lives in its own directory, whose wallet file holds the keys
+//! both parties use on that chain.
+
+use assert_cmd::prelude::CommandCargoExt;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+use std::thread;
+use std::time::Duration;
+
+struct Chain {
+    dir: PathBuf,
+    name: &'static str,
+}
+
+impl Chain {
+    fn new(test: &str, name: &'static str) -> Chain {
+        let dir = std::env::temp_dir().join(format!(
+            "socratix-swap-{}-{}-{}",
+            test,
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        Chain { dir, name }
+    }
+
+    fn socratix(&self) -> Command {
+        let mut command = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        command.current_dir(&self.dir).env("CHAIN", self.name);
+        command
+    }
+
+    fn run(&self, args: &[&str]) -> Output {
+        self.socratix().args(args).output().unwrap()
+    }
+
+    /// Runs a command that must succeed and returns its output.
+    fn ok(&self, args: &[&str]) -> String {
+        let output = self.run(args);
+        assert!(
+            output.status.success(),
+            "{:?} failed: {}",
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    fn create_wallet(&self) -> String {
+        let stdout = self.ok(&["createwallet"]);
+        stdout.split_whitespace().last().unwrap().to_string()
+    }
+
+    fn balance(&self, address: &str) -> i32 {
+        let stdout = self.ok(&["getbalance", address]);
+        stdout.split_whitespace().last().unwrap().parse().unwrap()
+    }
+}
+
+impl Drop for Chain {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// The value of a `name: value` line.
+fn field<'a>(stdout: &'a str, name: &str) -> &'a str {
+    let prefix = format!("{}: ", name);
+    stdout
+        .lines()
+        .find_map(|line| line.strip_prefix(prefix.as_str()))
+        .unwrap_or_else(|| panic!("no {} in {}", name, stdout))
+}
+
+/// Alice funds chain A and Bob chain B, each with the genesis reward of 10.
+fn funded_chains(test: &str) -> (Chain, Chain, [String; 4]) {
+    let (chain_a, chain_b) = (Chain::new(test, "main"), Chain::new(test, "sha256d"));
+    let (alice_a, bob_a) = (chain_a.create_wallet(), chain_a.create_wallet());
+    let (alice_b, bob_b) = (chain_b.create_wallet(), chain_b.create_wallet());
+    chain_a.ok(&["createblockchain", alice_a.as_str()]);
+    chain_b.ok(&["createblockchain", bob_b.as_str()]);
+    (chain_a, chain_b, [alice_a, bob_a, alice_b, bob_b])
+}
+
+#[test]
+fn swap_between_two_chains() {
+    let (chain_a, chain_b, [alice_a, bob_a, alice_b, bob_b]) = funded_chains("redeem");
+
+    // Alice locks 6 on chain A to a secret only Alice knows.
+    let initiated = chain_a.ok(&["initiate", alice_a.as_str(), bob_a.as_str(), "6", "--mine"]);
+    let secret = field(&initiated, "Secret");
+    let secret_hash = field(&initiated, "Secret hash");
+    let contract_a = field(&initiated, "Contract");
+    let txid_a = field(&initiated, "Txid");
+
+    // Bob checks Alice's contract on chain A, then locks 4 on chain B to
+    // the same hash, but only against the 6 agreed on.
+    let audit = chain_a.dir.join("audit");
+    let audit = audit.to_str().unwrap();
+    let audited = chain_a.ok(&["auditcontract", contract_a, txid_a, audit]);
+    assert_eq!(field(&audited, "Secret hash"), secret_hash);
+    assert_eq!(field(&audited, "Recipient"), bob_a);
+    assert_eq!(field(&audited, "Amount"), "6");
+    let participate = |expect_amount: &str, timeout: &str| {
+        chain_b.run(&[
+            "participate",
+            bob_b.as_str(),
+            alice_b.as_str(),
+            "4",
+            audit,
+            "--expect-amount",
+            expect_amount,
+            "--timeout",
+            timeout,
+            "--mine",
+        ])
+    };
+    let wrong_amount = participate("7", "86400");
+    assert!(String::from_utf8_lossy(&wrong_amount.stderr).contains("instead of 7"));
+    let too_late = participate("6", "172800");
+    assert!(String::from_utf8_lossy(&too_late.stderr).contains("too close"));
+    let participated = participate("6", "86400");
+    assert!(participated.status.success());
+    let participated = String::from_utf8(participated.stdout).unwrap();
+    let contract_b = field(&participated, "Contract");
+    let txid_b = field(&participated, "Txid");
+
+    // Bob cannot take Alice's coins without the secret.
+    let guess = "00".repeat(32);
+    assert!(
+        !chain_a
+            .run(&["redeem", contract_a, txid_a, guess.as_str(), "--mine"])
+            .status
+            .success()
+    );
+
+    // Alice takes Bob's coins, revealing the secret on chain B, where Bob
+    // finds it to take Alice's.
+    chain_b.ok(&["redeem", contract_b, txid_b, secret, "--mine"]);
+    let extracted = chain_b.ok(&["extractsecret", contract_b]);
+    assert_eq!(field(&extracted, "Secret"), secret);
+    chain_a.ok(&["redeem", contract_a, txid_a, secret, "--mine"]);
+
+    // Each redemption pays the contract less a fee of 1, and mining it
+    // another reward of 10.
+    assert_eq!(chain_a.balance(bob_a.as_str()), 5 + 10);
+    assert_eq!(chain_b.balance(alice_b.as_str()), 3 + 10);
+    // What is left of the genesis rewards after funding the contracts
+    // with a fee of 1, plus the reward for mining that.
+    assert_eq!(chain_a.balance(alice_a.as_str()), 3 + 10);
+    assert_eq!(chain_b.balance(bob_b.as_str()), 5 + 10);
+}
+
+#[test]
+fn refund_after_timeout() {
+    let (chain_a, _chain_b, [alice_a, bob_a, ..]) = funded_chains("refund");
+
+    let initiated = chain_a.ok(&[
+        "initiate",
+        alice_a.as_str(),
+        bob_a.as_str(),
+        "6",
+        "--timeout",
+        "2",
+        "--mine",
+    ]);
+    let contract = field(&initiated, "Contract");
+    let txid = field(&initiated, "Txid");
+
+    let early = chain_a.run(&["refund", contract, txid, "--mine"]);
+    assert!(!early.status.success());
+    assert!(String::from_utf8_lossy(&early.stderr).contains("locked"));
+
+    thread::sleep(Duration::from_secs(4));
+    // The lock counts in median time past, which only moves with blocks
+    // mined after the timeout.
+    for _ in 0..2 {
+        chain_a.ok(&["send", alice_a.as_str(), alice_a.as_str(), "1", "1"]);
+    }
+    chain_a.ok(&["refund", contract, txid, "--mine"]);
+    // The genesis reward, four mining rewards, less the four fees.
+    assert_eq!(chain_a.balance(alice_a.as_str()), 10 + 4 * 10 - 4);
+
+    // The secret no longer helps once the coins are refunded.
+    let secret = field(&initiated, "Secret");
+    let late = chain_a.run(&["redeem", contract, txid, secret, "--mine"]);
+    assert!(!late.status.success());
+    assert!(String::from_utf8_lossy(&late.stderr).contains("no unspent output"));
+}